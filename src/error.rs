@@ -1,10 +1,392 @@
-#[derive(thiserror::Error, Debug)]
+use std::{fmt, io};
+
+use doip_definitions::{
+    header::{PayloadType, ProtocolVersion},
+    message::DoipMessage,
+    payload::{ActivationCode, DiagnosticMessageNack, RoutingActivationResponse},
+};
+
+/// Errors which can occur when sending a DoIP frame
+///
+/// `Display` and `std::error::Error` are implemented by hand, as
+/// `doip_codec::Error` does not implement `std::error::Error`. The `source` of
+/// an `EncodeError` is the IO or slice error underneath the codec error.
+#[derive(Debug)]
 pub enum SocketSendError {
     /// Encode error from Codec
-    #[error("Underlying Codec Error: {0}")]
     EncodeError(doip_codec::Error),
 
     /// Payload Type not supported by TCP Socket
-    #[error("Payload Type not supported by TCP Socket")]
     InvalidTcpPayload,
+
+    /// Payload Type not supported by UDP Socket
+    InvalidUdpPayload,
+
+    /// The peer closed or reset the connection
+    ConnectionClosed,
+
+    /// The peer or its network is unreachable, as reported to a connected
+    /// UDP Socket
+    PeerUnreachable,
+
+    /// The payload type is not valid under the configured protocol version
+    PayloadNotAllowedForVersion(ProtocolVersion, PayloadType),
+
+    /// The payload fields are inconsistent and cannot be encoded
+    InvalidPayloadContents(PayloadType, String),
+}
+
+impl SocketSendError {
+    /// Whether the frame failed to encode, or the IO beneath the codec failed
+    pub fn is_encode_error(&self) -> bool {
+        matches!(self, SocketSendError::EncodeError(_))
+    }
+
+    /// Whether the payload was rejected before being sent, as invalid for the
+    /// transport or protocol version, or with inconsistent contents
+    pub fn is_invalid_payload(&self) -> bool {
+        matches!(
+            self,
+            SocketSendError::InvalidTcpPayload
+                | SocketSendError::InvalidUdpPayload
+                | SocketSendError::PayloadNotAllowedForVersion(..)
+                | SocketSendError::InvalidPayloadContents(..)
+        )
+    }
+
+    /// Whether the peer closed or reset the connection
+    pub fn is_connection_closed(&self) -> bool {
+        matches!(self, SocketSendError::ConnectionClosed)
+    }
+
+    /// Whether the peer or its network is unreachable
+    pub fn is_peer_unreachable(&self) -> bool {
+        matches!(self, SocketSendError::PeerUnreachable)
+    }
+}
+
+impl fmt::Display for SocketSendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SocketSendError::EncodeError(err) => write!(f, "Underlying Codec Error: {err}"),
+            SocketSendError::InvalidTcpPayload => {
+                write!(f, "Payload Type not supported by TCP Socket")
+            }
+            SocketSendError::InvalidUdpPayload => {
+                write!(f, "Payload Type not supported by UDP Socket")
+            }
+            SocketSendError::ConnectionClosed => write!(f, "Connection closed by peer"),
+            SocketSendError::PeerUnreachable => write!(f, "Peer unreachable"),
+            SocketSendError::PayloadNotAllowedForVersion(version, payload_type) => write!(
+                f,
+                "Payload Type {payload_type:?} not allowed for protocol version {version:?}"
+            ),
+            SocketSendError::InvalidPayloadContents(payload_type, reason) => {
+                write!(f, "Invalid {payload_type:?} payload contents: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SocketSendError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SocketSendError::EncodeError(doip_codec::Error::IoError(err)) => Some(err),
+            SocketSendError::EncodeError(doip_codec::Error::SliceError(err)) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<doip_codec::Error> for SocketSendError {
+    fn from(err: doip_codec::Error) -> Self {
+        match err {
+            doip_codec::Error::IoError(ref io_err)
+                if matches!(
+                    io_err.kind(),
+                    io::ErrorKind::BrokenPipe
+                        | io::ErrorKind::ConnectionReset
+                        | io::ErrorKind::ConnectionAborted
+                        | io::ErrorKind::NotConnected
+                ) =>
+            {
+                SocketSendError::ConnectionClosed
+            }
+            doip_codec::Error::IoError(ref io_err)
+                if matches!(
+                    io_err.kind(),
+                    io::ErrorKind::ConnectionRefused
+                        | io::ErrorKind::HostUnreachable
+                        | io::ErrorKind::NetworkUnreachable
+                ) =>
+            {
+                SocketSendError::PeerUnreachable
+            }
+            err => SocketSendError::EncodeError(err),
+        }
+    }
+}
+
+/// Error returned by `TcpStream::send_all`, identifying the payload which
+/// failed
+#[derive(thiserror::Error, Debug)]
+#[error("Failed to send payload {index}: {error}")]
+pub struct SendAllError {
+    /// Index of the failed payload, payloads before it were buffered
+    ///
+    /// If the final flush failed this is the number of payloads, as every
+    /// payload was buffered but how many reached the OS is unknown.
+    pub index: usize,
+
+    /// The error the payload failed with
+    #[source]
+    pub error: SocketSendError,
+}
+
+/// Errors which can occur when reading a DoIP frame with `read_message`
+///
+/// Separates failures of the connection, after which reconnecting is the only
+/// option, from frames which were rejected by the codec.
+#[derive(thiserror::Error, Debug)]
+pub enum SocketReadError {
+    /// The connection failed, or a read timeout or alive check flood was
+    /// raised, see `SocketError`
+    #[error("IO error: {0}")]
+    Io(io::Error),
+
+    /// A frame could not be decoded, exceeded the max message size or failed
+    /// the strict version check
+    ///
+    /// Only an oversized frame or a version mismatch is recoverable, as the
+    /// frame is consumed and reading can continue. A frame which could not be
+    /// decoded is left in the buffer and the codec stops, so the next read
+    /// returns `Closed` and the connection should be dropped.
+    #[error("Failed to decode frame: {0}")]
+    Decode(doip_codec::Error),
+
+    /// The peer closed the connection
+    #[error("Connection closed by peer")]
+    Closed,
+}
+
+impl From<doip_codec::Error> for SocketReadError {
+    fn from(err: doip_codec::Error) -> Self {
+        match err {
+            doip_codec::Error::IoError(err) if err.kind() != io::ErrorKind::InvalidData => {
+                SocketReadError::Io(err)
+            }
+            err => SocketReadError::Decode(err),
+        }
+    }
+}
+
+impl SocketReadError {
+    pub(crate) fn from_read(
+        res: Option<Result<DoipMessage, doip_codec::Error>>,
+    ) -> Result<DoipMessage, Self> {
+        match res {
+            Some(res) => res.map_err(SocketReadError::from),
+            None => Err(SocketReadError::Closed),
+        }
+    }
+}
+
+/// Errors raised by a socket while reading, about the behaviour of the peer
+///
+/// Returned from `read` wrapped in a `doip_codec::Error::IoError` of kind
+/// `io::ErrorKind::Other`, unless stated otherwise, and can be recovered with
+/// `io::Error::get_ref`.
+#[derive(thiserror::Error, Debug)]
+pub enum SocketError {
+    /// The peer sent more Alive Check Requests than the configured
+    /// `max_alive_check_rate`
+    #[error("Alive Check Requests exceeded the maximum rate")]
+    AliveCheckFlood,
+
+    /// No frame arrived within the configured `read_timeout`, wrapped in an
+    /// error of kind `io::ErrorKind::TimedOut`
+    ///
+    /// The stream is left open and the read can be retried.
+    #[error("Timed out waiting for a frame")]
+    ReadTimeout,
+
+    /// A frame's header version differed from the configured protocol version
+    /// while `strict_version` is enabled, wrapped in an error of kind
+    /// `io::ErrorKind::InvalidData`
+    #[error("Received protocol version {observed:?}, expected {expected:?}")]
+    VersionMismatch {
+        /// The protocol version of the socket config
+        expected: ProtocolVersion,
+
+        /// The protocol version of the received frame
+        observed: ProtocolVersion,
+    },
+}
+
+/// Errors which can occur during a routing activation handshake
+#[derive(thiserror::Error, Debug)]
+pub enum ActivationError {
+    /// Failed to send the Routing Activation Request
+    #[error("Failed to send Routing Activation Request: {0}")]
+    SendError(SocketSendError),
+
+    /// Failed to read the Routing Activation Response
+    #[error("Failed to read Routing Activation Response: {0}")]
+    ReadError(doip_codec::Error),
+
+    /// The connection was closed before a Routing Activation Response arrived
+    #[error("Connection closed before Routing Activation Response")]
+    ConnectionClosed,
+
+    /// The DoIP entity denied the routing activation
+    #[error("Routing Activation denied: {:?}", .0.activation_code)]
+    Denied(RoutingActivationResponse),
+
+    /// Failed to connect to the DoIP entity
+    #[error("Failed to connect to DoIP entity: {0}")]
+    ConnectError(io::Error),
+
+    /// No Routing Activation Response arrived in time
+    #[error("Timed out waiting for Routing Activation Response")]
+    Timeout,
+
+    /// The DoIP entity rejected every protocol version offered
+    #[error("No offered protocol version was accepted")]
+    VersionNotSupported,
+}
+
+impl ActivationError {
+    /// The activation code returned by the DoIP entity, if the activation was denied
+    pub fn activation_code(&self) -> Option<ActivationCode> {
+        match self {
+            ActivationError::Denied(res) => Some(res.activation_code),
+            _ => None,
+        }
+    }
+}
+
+/// Errors which can occur during a session opened by `TcpStream::with_session`
+#[derive(thiserror::Error, Debug)]
+pub enum SessionError<E> {
+    /// The session could not be opened
+    #[error("Failed to open session: {0}")]
+    Activation(#[from] ActivationError),
+
+    /// The DoIP entity requires a confirmation step before the session is
+    /// usable
+    #[error("Routing Activation requires confirmation")]
+    ConfirmationRequired(RoutingActivationResponse),
+
+    /// The session closure returned an error
+    #[error("Session failed: {0}")]
+    Session(E),
+}
+
+/// Errors which can occur during a diagnostic message exchange
+#[derive(thiserror::Error, Debug)]
+pub enum DoipError {
+    /// Failed to send the Diagnostic Message
+    #[error("Failed to send Diagnostic Message: {0}")]
+    SendError(SocketSendError),
+
+    /// Failed to read a frame off the stream
+    #[error("Failed to read Diagnostic Message: {0}")]
+    ReadError(doip_codec::Error),
+
+    /// The DoIP entity rejected the Diagnostic Message
+    #[error("Diagnostic Message rejected: {:?}", .0.nack_code)]
+    Nack(DiagnosticMessageNack),
+
+    /// No acknowledgement arrived in time
+    #[error("Timed out waiting for Diagnostic Message acknowledgement")]
+    Timeout,
+
+    /// The connection closed before an acknowledgement arrived
+    #[error("Connection closed before Diagnostic Message acknowledgement")]
+    ConnectionClosed,
+}
+
+/// Errors which can occur when checking a DoIP frame survives encode and decode
+#[derive(thiserror::Error, Debug)]
+pub enum RoundtripError {
+    /// The frame could not be encoded
+    #[error("Failed to encode frame: {0}")]
+    EncodeError(doip_codec::Error),
+
+    /// The encoded bytes could not be decoded
+    #[error("Failed to decode frame: {0}")]
+    DecodeError(doip_codec::Error),
+
+    /// The encoded bytes did not decode to exactly one frame
+    #[error("Encoded frame is {0} bytes, decoded frame did not consume all of them")]
+    LengthMismatch(usize),
+
+    /// The decoded frame differs from the original
+    #[error("Decoded frame differs from original: {0:?}")]
+    Mismatch(Box<DoipMessage>),
+}
+
+/// Errors which can occur when enqueueing a frame on an `OutboundQueue`
+#[derive(thiserror::Error, Debug)]
+pub enum EnqueueError {
+    /// The queue is full and the overflow policy is `OverflowPolicy::Error`
+    #[error("Outbound queue is full")]
+    Full,
+
+    /// The writer has stopped after a send failed
+    #[error("Outbound queue writer has stopped")]
+    Closed,
+}
+
+#[cfg(test)]
+mod test_error {
+    use std::{error::Error, io};
+
+    use crate::error::{SocketReadError, SocketSendError};
+
+    #[test]
+    fn test_socket_read_error_from_codec() {
+        let err = doip_codec::Error::IoError(io::Error::from(io::ErrorKind::ConnectionReset));
+        assert!(matches!(SocketReadError::from(err), SocketReadError::Io(_)));
+
+        let err = doip_codec::Error::IoError(io::Error::from(io::ErrorKind::InvalidData));
+        assert!(matches!(
+            SocketReadError::from(err),
+            SocketReadError::Decode(_)
+        ));
+
+        let err = doip_codec::Error::Heapless(());
+        assert!(matches!(
+            SocketReadError::from(err),
+            SocketReadError::Decode(_)
+        ));
+
+        assert!(matches!(
+            SocketReadError::from_read(None),
+            Err(SocketReadError::Closed)
+        ));
+    }
+
+    #[test]
+    fn test_socket_send_error_display_source() {
+        let err = SocketSendError::EncodeError(doip_codec::Error::IoError(io::Error::other(
+            "encode failed",
+        )));
+
+        assert!(err.to_string().starts_with("Underlying Codec Error: "));
+        assert_eq!(err.source().unwrap().to_string(), "encode failed");
+        assert!(err.is_encode_error());
+        assert!(!err.is_invalid_payload());
+
+        let err = SocketSendError::InvalidTcpPayload;
+
+        assert_eq!(err.to_string(), "Payload Type not supported by TCP Socket");
+        assert!(err.source().is_none());
+
+        assert!(err.is_invalid_payload());
+        assert!(!err.is_encode_error());
+
+        let boxed: Box<dyn Error> = Box::new(SocketSendError::ConnectionClosed);
+        assert_eq!(boxed.to_string(), "Connection closed by peer");
+    }
 }
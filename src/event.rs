@@ -0,0 +1,107 @@
+use std::net::SocketAddr;
+
+use doip_codec::Error as CodecError;
+use doip_definitions::message::DoipMessage;
+use futures::{stream, Stream};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{tcp::TcpStreamReadHalf, udp::UdpRecvHalf};
+
+/// A DoIP frame tagged with the transport it arrived on
+#[derive(Debug)]
+pub enum DoipEvent {
+    /// A frame read from the TCP Stream
+    Tcp(Result<DoipMessage, CodecError>),
+
+    /// A frame received on the UDP Socket, alongside the sender's address
+    Udp(Result<(DoipMessage, SocketAddr), CodecError>),
+}
+
+/// Merges the frames read from a TCP Stream and a UDP Socket into one stream
+///
+/// Useful for testers handling session traffic and discovery in a single
+/// `select` loop. The merged stream ends once both inputs have ended.
+pub fn merge_doip<T>(tcp: TcpStreamReadHalf<T>, udp: UdpRecvHalf) -> impl Stream<Item = DoipEvent>
+where
+    T: AsyncRead + AsyncWrite,
+{
+    let tcp = stream::unfold(tcp, |mut tcp| async move {
+        let res = tcp.read().await?;
+        Some((DoipEvent::Tcp(res), tcp))
+    });
+
+    let udp = stream::unfold(udp, |mut udp| async move {
+        let res = udp.recv().await?;
+        Some((DoipEvent::Udp(res), udp))
+    });
+
+    stream::select(Box::pin(tcp), Box::pin(udp))
+}
+
+#[cfg(test)]
+mod test_event {
+    use doip_definitions::payload::{AliveCheckRequest, DoipPayload, VehicleIdentificationRequest};
+    use futures::StreamExt;
+
+    use super::{merge_doip, DoipEvent};
+    use crate::{tcp::TcpStream, udp::UdpSocket};
+
+    #[tokio::test]
+    async fn test_merge_doip() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+        let (tcp_read, _tcp_write) = client.into_split();
+
+        let udp = UdpSocket::bind(TESTER_ADDR).await.unwrap();
+        let udp_addr = udp.get_socket_ref().local_addr().unwrap();
+        let (udp_recv, _udp_send) = udp.into_split();
+        let mut entity = UdpSocket::bind(TESTER_ADDR).await.unwrap();
+
+        let events = merge_doip(tcp_read, udp_recv);
+        tokio::pin!(events);
+
+        server
+            .send(DoipPayload::AliveCheckRequest(AliveCheckRequest {}))
+            .await
+            .unwrap();
+        entity
+            .send(
+                DoipPayload::VehicleIdentificationRequest(VehicleIdentificationRequest {}),
+                udp_addr,
+            )
+            .await
+            .unwrap();
+
+        let mut tcp_seen = false;
+        let mut udp_seen = false;
+
+        for _ in 0..2 {
+            match events.next().await.unwrap() {
+                DoipEvent::Tcp(res) => {
+                    assert_eq!(
+                        res.unwrap().payload,
+                        DoipPayload::AliveCheckRequest(AliveCheckRequest {})
+                    );
+                    tcp_seen = true;
+                }
+                DoipEvent::Udp(res) => {
+                    let (msg, addr) = res.unwrap();
+                    assert_eq!(
+                        msg.payload,
+                        DoipPayload::VehicleIdentificationRequest(VehicleIdentificationRequest {})
+                    );
+                    assert_eq!(addr, entity.get_socket_ref().local_addr().unwrap());
+                    udp_seen = true;
+                }
+            }
+        }
+
+        assert!(tcp_seen && udp_seen);
+    }
+}
@@ -14,8 +14,20 @@
 //! currently of which is solely limited to the version of the protocol used,
 //! however can be extended in future version.
 
-use doip_definitions::header::ProtocolVersion;
+use std::{
+    future::Future,
+    sync::{PoisonError, RwLock},
+    time::Duration,
+};
+
+use doip_definitions::{
+    header::ProtocolVersion,
+    message::DoipMessage,
+    payload::{DoipPayload, EntityStatusResponse, NackCode, PowerInformationResponse},
+};
+use tokio::{runtime::Handle, task::JoinHandle};
 mod error;
+mod pacing;
 
 /// Simple TCP Stream and Split implentation for a TCP Stream allowing the conversion of a
 /// socket into a stream for Codec use, or the creating of a new TCP Stream
@@ -25,13 +37,543 @@ pub mod tcp;
 /// Simple UDP Socket implementation for UDP communication.
 pub mod udp;
 
+/// Helpers for inspecting and debugging DoIP frames.
+pub mod util;
+
+/// Combinators for handling TCP and UDP frames in a single event loop.
+pub mod event;
+
 pub use doip_codec::Error;
+pub use error::*;
+
+static DEFAULT_SOCKET_CONFIG: RwLock<Option<SocketConfig>> = RwLock::new(None);
+
+/// Set the process-wide default config for sockets created without one
+///
+/// Consulted by `TcpStream::new`, `TcpStream::connect`, `TcpListener::new`,
+/// `DoipServerBuilder::new` and `UdpSocket::bind` along with the constructors
+/// built on them. The default is read once when a
+/// socket is constructed, so changing it does not affect existing sockets.
+/// Safe to call from any thread. `None` restores the built-in defaults.
+pub fn set_default_socket_config(config: Option<SocketConfig>) {
+    *DEFAULT_SOCKET_CONFIG
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = config;
+}
 
 /// Configuration for UDP and TCP Sockets
 ///
 /// This provides the methods within each struct with constants which can be set
 /// during a typical usage with DoIP such as the protocol_version.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub struct SocketConfig {
     protocol_version: ProtocolVersion,
+    auto_alive_check: Option<[u8; 2]>,
+    send_rate_limit: Option<u32>,
+    runtime: Option<Handle>,
+    max_message_size: Option<u32>,
+    oversize_nack: Option<NackCode>,
+    outbound_queue_capacity: usize,
+    overflow_policy: tcp::OverflowPolicy,
+    enforce_payload_version: bool,
+    max_alive_check_rate: Option<u32>,
+    on_unknown_payload: udp::UnknownPayloadPolicy,
+    oem_specific: Option<[u8; 4]>,
+    frame_history: Option<usize>,
+    min_send_gap: Option<Duration>,
+    dry_run: bool,
+    relay_mode: bool,
+    auto_status_response: Option<EntityStatusResponse>,
+    auto_power_mode: Option<PowerInformationResponse>,
+    read_timeout: Option<Duration>,
+    nodelay: Option<bool>,
+    keepalive: Option<Duration>,
+    strict_version: bool,
+}
+
+impl SocketConfig {
+    /// Creates a builder starting from the default config
+    pub fn builder() -> SocketConfigBuilder {
+        SocketConfigBuilder::new()
+    }
+
+    /// Set the protocol version used when building outbound frames
+    pub fn set_protocol_version(&mut self, version: ProtocolVersion) {
+        self.protocol_version = version
+    }
+
+    /// The protocol version used when building outbound frames
+    ///
+    /// Defaults to `ProtocolVersion::Iso13400_2012`.
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.protocol_version
+    }
+
+    /// Limit outbound sends to a maximum number of frames per second
+    ///
+    /// Bursts of up to one second's worth of frames are sent immediately,
+    /// further sends are delayed. `None` or `Some(0)` disables the limit.
+    pub fn set_send_rate_limit(&mut self, frames_per_second: Option<u32>) {
+        self.send_rate_limit = frames_per_second.filter(|rate| *rate > 0);
+    }
+
+    /// The maximum number of frames sent per second, if limited
+    pub fn send_rate_limit(&self) -> Option<u32> {
+        self.send_rate_limit
+    }
+
+    /// Set the runtime used by `spawn_*` helpers for their background tasks
+    ///
+    /// When `None` tasks are spawned on the ambient runtime.
+    pub fn set_runtime(&mut self, runtime: Option<Handle>) {
+        self.runtime = runtime
+    }
+
+    /// The runtime used by `spawn_*` helpers, if set
+    pub fn runtime(&self) -> Option<&Handle> {
+        self.runtime.as_ref()
+    }
+
+    /// Set the largest inbound payload length accepted by a TCP Stream
+    ///
+    /// Frames over the limit are rejected from their header, their payload is
+    /// discarded without being buffered. `None` accepts any length, so a limit
+    /// should be set when the peer is untrusted, as the header may advertise a
+    /// payload of up to 4 GiB.
+    pub fn set_max_message_size(&mut self, max_message_size: Option<u32>) {
+        self.max_message_size = max_message_size
+    }
+
+    /// The largest inbound payload length accepted, if limited
+    pub fn max_message_size(&self) -> Option<u32> {
+        self.max_message_size
+    }
+
+    /// Automatically answer frames over the max message size with a Generic
+    /// Nack of the given code, such as `NackCode::MessageTooLarge` or
+    /// `NackCode::OutOfMemory`
+    ///
+    /// When `None` oversized frames are returned from `read` as an error.
+    pub fn set_oversize_nack(&mut self, nack_code: Option<NackCode>) {
+        self.oversize_nack = nack_code
+    }
+
+    /// The Generic Nack code sent for oversized frames, if set
+    pub fn oversize_nack(&self) -> Option<NackCode> {
+        self.oversize_nack
+    }
+
+    /// Set the capacity and overflow policy of the `OutboundQueue` created by
+    /// `spawn_writer`
+    ///
+    /// A capacity of zero is treated as one.
+    pub fn set_outbound_queue(&mut self, capacity: usize, policy: tcp::OverflowPolicy) {
+        self.outbound_queue_capacity = capacity.max(1);
+        self.overflow_policy = policy;
+    }
+
+    /// The capacity of the `OutboundQueue` created by `spawn_writer`
+    pub fn outbound_queue_capacity(&self) -> usize {
+        self.outbound_queue_capacity
+    }
+
+    /// The overflow policy of the `OutboundQueue` created by `spawn_writer`
+    pub fn overflow_policy(&self) -> tcp::OverflowPolicy {
+        self.overflow_policy
+    }
+
+    /// Reject sends of payload types not valid under the protocol version
+    ///
+    /// When enabled, sends on a TCP Stream return
+    /// `SocketSendError::PayloadNotAllowedForVersion` as decided by
+    /// `util::is_payload_allowed`.
+    pub fn set_enforce_payload_version(&mut self, enforce: bool) {
+        self.enforce_payload_version = enforce
+    }
+
+    /// Whether sends are checked against the protocol version
+    pub fn enforce_payload_version(&self) -> bool {
+        self.enforce_payload_version
+    }
+
+    /// Limit the Alive Check Requests answered automatically per second
+    ///
+    /// Requests are counted over a sliding one second window. Once the rate is
+    /// exceeded `read` returns a `SocketError::AliveCheckFlood` so the caller
+    /// can throttle or disconnect the peer. `None` or `Some(0)` disables the
+    /// limit.
+    pub fn set_max_alive_check_rate(&mut self, per_second: Option<u32>) {
+        self.max_alive_check_rate = per_second.filter(|rate| *rate > 0);
+    }
+
+    /// The maximum Alive Check Requests answered automatically per second, if
+    /// limited
+    pub fn max_alive_check_rate(&self) -> Option<u32> {
+        self.max_alive_check_rate
+    }
+
+    /// Set how UDP Sockets handle inbound frames with a reserved or unknown
+    /// payload type
+    ///
+    /// Defaults to `UnknownPayloadPolicy::Skip` so discovery tolerates nodes
+    /// emitting newer payload types.
+    pub fn set_on_unknown_payload(&mut self, policy: udp::UnknownPayloadPolicy) {
+        self.on_unknown_payload = policy
+    }
+
+    /// How UDP Sockets handle inbound frames with an unknown payload type
+    pub fn on_unknown_payload(&self) -> udp::UnknownPayloadPolicy {
+        self.on_unknown_payload
+    }
+
+    /// Set the OEM specific bytes appended to Routing Activation Requests and
+    /// Responses sent on a TCP Stream
+    ///
+    /// Fills the optional OEM specific field for gateways whose handshake
+    /// requires it, such as in `TcpStream::respond_activation`. `None` omits
+    /// the field.
+    pub fn set_oem_specific(&mut self, oem_specific: Option<[u8; 4]>) {
+        self.oem_specific = oem_specific
+    }
+
+    /// The OEM specific bytes appended to Routing Activation frames, if set
+    pub fn oem_specific(&self) -> Option<[u8; 4]> {
+        self.oem_specific
+    }
+
+    /// Keep the most recent frames sent and received on a TCP Stream
+    ///
+    /// Up to `capacity` frames are kept with the time they were sent or
+    /// received, and returned by `TcpStream::recent_frames` for post-mortem
+    /// analysis. `None` or `Some(0)` disables the history.
+    pub fn set_frame_history(&mut self, capacity: Option<usize>) {
+        self.frame_history = capacity.filter(|capacity| *capacity > 0);
+    }
+
+    /// The number of recent frames kept on a TCP Stream, if enabled
+    pub fn frame_history(&self) -> Option<usize> {
+        self.frame_history
+    }
+
+    /// Guarantee a minimum silence between consecutive frames sent on a TCP
+    /// Stream
+    ///
+    /// Unlike the send rate limit, which allows bursts, `send` always waits
+    /// until `gap` has passed since the previous frame was sent. `None` or a
+    /// zero gap disables it.
+    pub fn set_min_send_gap(&mut self, gap: Option<Duration>) {
+        self.min_send_gap = gap.filter(|gap| !gap.is_zero());
+    }
+
+    /// The minimum gap between consecutive frames sent, if set
+    pub fn min_send_gap(&self) -> Option<Duration> {
+        self.min_send_gap
+    }
+
+    /// Validate and encode frames sent on a TCP Stream or its write half
+    /// without writing them to the transport
+    ///
+    /// Applies to every send path, including `feed`, `send_all`, `poll_send`
+    /// and the `Sink` impls. The encoded bytes are kept and can be taken with
+    /// `TcpStream::take_dry_run_bytes`, which is useful for testing frame
+    /// construction without a peer.
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    /// Whether frames are encoded without being sent
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Send frames on a TCP Stream without checking their payload
+    ///
+    /// Intended for transparent relays forwarding frames they did not build.
+    /// The payload contents check, the TCP payload type check and
+    /// `enforce_payload_version` are all skipped, so an inconsistent frame from the caller is put on the wire as
+    /// is and may be rejected or misread by the peer.
+    pub fn set_relay_mode(&mut self, relay_mode: bool) {
+        self.relay_mode = relay_mode;
+    }
+
+    /// Whether payloads are sent without being checked
+    pub fn relay_mode(&self) -> bool {
+        self.relay_mode
+    }
+
+    /// Automatically answer Entity Status Requests received on a TCP Stream
+    ///
+    /// When set, `TcpStream::read` replies with `response` and continues
+    /// reading rather than returning the request.
+    pub fn set_auto_status_response(&mut self, response: Option<EntityStatusResponse>) {
+        self.auto_status_response = response;
+    }
+
+    /// The Entity Status Response sent automatically, if set
+    pub fn auto_status_response(&self) -> Option<EntityStatusResponse> {
+        self.auto_status_response
+    }
+
+    /// Automatically answer Diagnostic Power Mode Information Requests received
+    /// on a TCP Stream
+    ///
+    /// When set, `TcpStream::read` replies with `response` and continues
+    /// reading rather than returning the request.
+    pub fn set_auto_power_mode(&mut self, response: Option<PowerInformationResponse>) {
+        self.auto_power_mode = response;
+    }
+
+    /// The Diagnostic Power Mode Information Response sent automatically, if
+    /// set
+    pub fn auto_power_mode(&self) -> Option<PowerInformationResponse> {
+        self.auto_power_mode
+    }
+
+    /// Give up on reads from a TCP Stream or its read half when no frame
+    /// arrives within `timeout`
+    ///
+    /// An elapsed read returns `SocketError::ReadTimeout` without closing the
+    /// stream, so the read can be retried. `None` waits forever.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    /// The time a read waits for a frame, if limited
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout
+    }
+
+    /// Set `TCP_NODELAY` on TCP Streams when they are created
+    ///
+    /// Applied by `TcpStream::new`, `TcpStream::connect` and accepted streams
+    /// of a `TcpListener`. `None` keeps the OS default.
+    pub fn set_nodelay(&mut self, nodelay: Option<bool>) {
+        self.nodelay = nodelay;
+    }
+
+    /// The `TCP_NODELAY` setting applied to new TCP Streams, if any
+    pub fn nodelay(&self) -> Option<bool> {
+        self.nodelay
+    }
+
+    /// Enable TCP keepalive on TCP Streams when they are created, probing
+    /// after the connection has been idle for `idle`
+    ///
+    /// Applied alongside `set_nodelay`, see `TcpStream::set_keepalive` for
+    /// the platforms supported. `None` keeps the OS default.
+    pub fn set_keepalive(&mut self, idle: Option<Duration>) {
+        self.keepalive = idle;
+    }
+
+    /// The TCP keepalive idle time applied to new TCP Streams, if any
+    pub fn keepalive(&self) -> Option<Duration> {
+        self.keepalive
+    }
+
+    /// Reject received frames whose header version differs from the protocol
+    /// version
+    ///
+    /// When enabled, every read of a TCP Stream or its read half, including
+    /// `read_of_type` and their `Stream` impls, discards such a frame and
+    /// returns a `SocketError::VersionMismatch` of kind
+    /// `io::ErrorKind::InvalidData`, the stream remains usable. Off by default,
+    /// accepting frames of any version.
+    pub fn set_strict_version(&mut self, strict: bool) {
+        self.strict_version = strict;
+    }
+
+    /// Whether received frames are checked against the protocol version
+    pub fn strict_version(&self) -> bool {
+        self.strict_version
+    }
+
+    pub(crate) fn process_default() -> Option<SocketConfig> {
+        DEFAULT_SOCKET_CONFIG
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Builds the DoIP frame for a TCP send, checking the payload unless in
+    /// relay mode
+    ///
+    /// Payloads only valid over UDP are rejected with
+    /// `SocketSendError::InvalidTcpPayload`.
+    pub(crate) fn build_message(
+        &self,
+        payload: DoipPayload,
+    ) -> Result<DoipMessage, SocketSendError> {
+        let msg = self.build_frame(payload)?;
+
+        if !self.relay_mode && !tcp::is_tcp_payload(msg.header.payload_type) {
+            return Err(SocketSendError::InvalidTcpPayload);
+        }
+
+        Ok(msg)
+    }
+
+    /// Builds a DoIP frame as `build_message`, but allows payloads only valid
+    /// over UDP
+    ///
+    /// Used for the frames the stream sends over TCP by explicit opt in, such
+    /// as the automatic Entity Status and Power Mode responses.
+    pub(crate) fn build_frame(&self, payload: DoipPayload) -> Result<DoipMessage, SocketSendError> {
+        if self.relay_mode {
+            return Ok(util::frame_message(self.protocol_version, payload));
+        }
+
+        let msg = util::build_message(self.protocol_version, payload)?;
+        let payload_type = msg.header.payload_type;
+
+        if self.enforce_payload_version
+            && !util::is_payload_allowed(self.protocol_version, payload_type)
+        {
+            return Err(SocketSendError::PayloadNotAllowedForVersion(
+                self.protocol_version,
+                payload_type,
+            ));
+        }
+
+        Ok(msg)
+    }
+
+    /// Checks a received frame against the protocol version when strict
+    /// version checking is enabled
+    pub(crate) fn check_version(&self, msg: &DoipMessage) -> Result<(), doip_codec::Error> {
+        let observed = msg.header.protocol_version;
+
+        if !self.strict_version || observed == self.protocol_version {
+            return Ok(());
+        }
+
+        Err(doip_codec::Error::IoError(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            SocketError::VersionMismatch {
+                expected: self.protocol_version,
+                observed,
+            },
+        )))
+    }
+
+    pub(crate) fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        match &self.runtime {
+            Some(handle) => handle.spawn(future),
+            None => tokio::spawn(future),
+        }
+    }
+}
+
+/// Builder for a `SocketConfig`
+///
+/// Starts from `SocketConfig::default()`, so fields added in later versions
+/// keep their defaults without breaking existing construction.
+#[derive(Debug, Clone, Default)]
+pub struct SocketConfigBuilder {
+    config: SocketConfig,
+}
+
+impl SocketConfigBuilder {
+    /// Creates a new builder using ISO-13400 2012 and the default config
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the protocol version used when building outbound frames
+    pub fn protocol_version(mut self, version: ProtocolVersion) -> Self {
+        self.config.protocol_version = version;
+        self
+    }
+
+    /// Automatically answer Alive Check Requests with the given source address
+    pub fn auto_alive_check(mut self, source_address: [u8; 2]) -> Self {
+        self.config.auto_alive_check = Some(source_address);
+        self
+    }
+
+    /// Limit outbound frames to a number per second
+    ///
+    /// As `SocketConfig::set_send_rate_limit`, 0 disables the limit.
+    pub fn send_rate_limit(mut self, frames_per_second: u32) -> Self {
+        self.config.set_send_rate_limit(Some(frames_per_second));
+        self
+    }
+
+    /// Set the largest payload length accepted on read
+    pub fn max_message_size(mut self, max_message_size: u32) -> Self {
+        self.config.max_message_size = Some(max_message_size);
+        self
+    }
+
+    /// Set how long a read waits for a frame before timing out
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.config.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the `TCP_NODELAY` setting applied to new TCP Streams
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.config.nodelay = Some(nodelay);
+        self
+    }
+
+    /// Enable TCP keepalive on new TCP Streams, probing after `idle`
+    pub fn keepalive(mut self, idle: Duration) -> Self {
+        self.config.keepalive = Some(idle);
+        self
+    }
+
+    /// Finalise the config
+    pub fn build(self) -> SocketConfig {
+        self.config
+    }
+}
+
+#[cfg(test)]
+mod test_socket_config {
+    use std::time::Duration;
+
+    use doip_definitions::{
+        header::ProtocolVersion,
+        payload::{AliveCheckRequest, DoipPayload},
+    };
+
+    use crate::{tcp::TcpStream, SocketConfig};
+
+    #[test]
+    fn test_builder() {
+        let config = SocketConfig::builder().build();
+        assert_eq!(config.protocol_version(), ProtocolVersion::Iso13400_2012);
+        assert_eq!(config.read_timeout(), None);
+
+        let config = SocketConfig::builder()
+            .protocol_version(ProtocolVersion::Iso13400_2019)
+            .read_timeout(Duration::from_secs(2))
+            .nodelay(true)
+            .build();
+        assert_eq!(config.protocol_version(), ProtocolVersion::Iso13400_2019);
+        assert_eq!(config.read_timeout(), Some(Duration::from_secs(2)));
+        assert_eq!(config.nodelay(), Some(true));
+    }
+
+    #[tokio::test]
+    async fn test_builder_zero_send_rate_limit() {
+        let config = SocketConfig::builder().send_rate_limit(0).build();
+        assert_eq!(config.send_rate_limit(), None);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        let mut client = client.with_config(config);
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+        client.send(alive_check.clone()).await.unwrap();
+        assert_eq!(server.read().await.unwrap().unwrap().payload, alive_check);
+    }
 }
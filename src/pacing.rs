@@ -0,0 +1,106 @@
+use std::{collections::VecDeque, time::Duration};
+
+use tokio::time::Instant;
+
+/// Token bucket used to limit the rate of outbound frames
+///
+/// The bucket holds up to one second's worth of tokens so short bursts are
+/// sent immediately, sends beyond the rate are delayed until a token is
+/// available.
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(rate: u32) -> Self {
+        TokenBucket {
+            rate: f64::from(rate),
+            tokens: f64::from(rate),
+            last: Instant::now(),
+        }
+    }
+
+    pub(crate) async fn acquire(&mut self) {
+        self.refill();
+
+        if self.tokens < 1.0 {
+            let wait = Duration::from_secs_f64((1.0 - self.tokens) / self.rate);
+            tokio::time::sleep(wait).await;
+            self.refill();
+        }
+
+        self.tokens -= 1.0;
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last = now;
+    }
+}
+
+/// Enforces a minimum gap between the end of one send and the start of the next
+#[derive(Debug)]
+pub(crate) struct SendGap {
+    gap: Duration,
+    last: Option<Instant>,
+}
+
+impl SendGap {
+    pub(crate) fn new(gap: Duration) -> Self {
+        SendGap { gap, last: None }
+    }
+
+    /// Wait until the gap since the previous send has elapsed
+    pub(crate) async fn wait(&self) {
+        if let Some(last) = self.last {
+            tokio::time::sleep_until(last + self.gap).await;
+        }
+    }
+
+    /// Mark the end of a send
+    pub(crate) fn mark(&mut self) {
+        self.last = Some(Instant::now());
+    }
+}
+
+/// Sliding window counter used to detect floods of inbound frames
+///
+/// Records the arrival time of each frame and reports whether more than
+/// `limit` arrived within the last `window`.
+#[derive(Debug)]
+pub(crate) struct SlidingWindow {
+    limit: u32,
+    window: Duration,
+    hits: VecDeque<Instant>,
+}
+
+impl SlidingWindow {
+    pub(crate) fn new(limit: u32, window: Duration) -> Self {
+        SlidingWindow {
+            limit,
+            window,
+            hits: VecDeque::new(),
+        }
+    }
+
+    /// Record a hit, returning `false` if the limit is exceeded
+    pub(crate) fn record(&mut self) -> bool {
+        let now = Instant::now();
+
+        while let Some(first) = self.hits.front() {
+            if now.duration_since(*first) < self.window {
+                break;
+            }
+            self.hits.pop_front();
+        }
+
+        self.hits.push_back(now);
+        self.hits.len() <= self.limit as usize
+    }
+}
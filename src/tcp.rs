@@ -1,43 +1,242 @@
+use std::{io, time::Duration};
+
+use doip_codec::Error as CodecError;
 use doip_definitions::{
-    header::ProtocolVersion,
+    header::{PayloadType, ProtocolVersion},
     payload::{
-        AliveCheckRequest, AliveCheckResponse, DiagnosticMessage, DiagnosticMessageAck,
-        DiagnosticMessageNack, GenericNack, RoutingActivationRequest, RoutingActivationResponse,
+        ActivationCode, AliveCheckRequest, AliveCheckResponse, DiagnosticMessage,
+        DiagnosticMessageAck, DiagnosticMessageNack, DoipPayload, GenericNack,
+        RoutingActivationRequest, RoutingActivationResponse, VehicleAnnouncementMessage,
     },
 };
 
-use crate::SocketConfig;
+use futures::{Stream, StreamExt};
+
+use crate::{
+    error::{ActivationError, SocketError},
+    udp::UnknownPayloadPolicy,
+    SocketConfig,
+};
 
+mod doip_client;
+mod frame_codec;
+mod frame_history;
+mod logical_address_table;
+mod outbound_queue;
+mod retry_policy;
+mod socket_metrics;
 #[cfg(feature = "ssl")]
 mod ssl_stream;
+mod tcp_info;
 mod tcp_listener;
 mod tcp_socket;
 mod tcp_split;
 mod tcp_stream;
+#[cfg(feature = "tls")]
+mod tls_stream;
+#[cfg(unix)]
+mod unix_stream;
+#[cfg(feature = "ws")]
+mod ws_stream;
+pub use crate::tcp::doip_client::{DoipClient, DOIP_CLIENT_RESPONSE_TIMEOUT};
+pub use crate::tcp::frame_history::Direction;
+pub use crate::tcp::logical_address_table::*;
+pub use crate::tcp::outbound_queue::{OutboundQueue, OverflowPolicy};
+pub use crate::tcp::retry_policy::{Backoff, RetryPolicy};
+pub use crate::tcp::socket_metrics::SocketMetrics;
 #[cfg(feature = "ssl")]
 pub use crate::tcp::ssl_stream::*;
+pub use crate::tcp::tcp_info::TcpInfoSnapshot;
 pub use crate::tcp::tcp_listener::*;
 pub use crate::tcp::tcp_socket::*;
 pub use crate::tcp::tcp_split::*;
 pub use crate::tcp::tcp_stream::*;
+#[cfg(feature = "tls")]
+pub use crate::tcp::tls_stream::*;
+#[cfg(unix)]
+pub use crate::tcp::unix_stream::*;
+#[cfg(feature = "ws")]
+pub use crate::tcp::ws_stream::*;
+
+/// Default capacity of an `OutboundQueue`
+pub const DOIP_OUTBOUND_QUEUE_CAPACITY: usize = 64;
 
 /// Helper Trait which assists in applying LSP hints to the send and receive of
 /// sockets.
-pub trait DoipTcpPayload {}
+///
+/// Implemented for the payloads valid over TCP, so `TcpStream::feed` cannot be
+/// given a payload such as a Vehicle Identification Request.
+pub trait DoipTcpPayload {
+    /// Wrap the payload in its `DoipPayload` variant
+    fn into_payload(self) -> DoipPayload;
+}
+
+macro_rules! impl_tcp_payload {
+    ($($payload:ident),*) => {
+        $(
+            impl DoipTcpPayload for $payload {
+                fn into_payload(self) -> DoipPayload {
+                    DoipPayload::$payload(self)
+                }
+            }
+        )*
+    };
+}
+
+impl_tcp_payload!(
+    GenericNack,
+    RoutingActivationRequest,
+    RoutingActivationResponse,
+    AliveCheckRequest,
+    AliveCheckResponse,
+    DiagnosticMessage,
+    DiagnosticMessageAck,
+    DiagnosticMessageNack
+);
+
+/// Returns whether a payload type may be carried over a TCP connection
+///
+/// The runtime counterpart to `DoipTcpPayload`, used where a fully formed
+/// `DoipMessage` is sent and the payload type is only known from its header.
+pub(crate) fn is_tcp_payload(payload_type: PayloadType) -> bool {
+    matches!(
+        payload_type,
+        PayloadType::GenericNack
+            | PayloadType::RoutingActivationRequest
+            | PayloadType::RoutingActivationResponse
+            | PayloadType::AliveCheckRequest
+            | PayloadType::AliveCheckResponse
+            | PayloadType::DiagnosticMessage
+            | PayloadType::DiagnosticMessageAck
+            | PayloadType::DiagnosticMessageNack
+    )
+}
+
+/// The Vehicle Identification Response returned by `TcpStream::identify`
+///
+/// ISO-13400 answers a Vehicle Identification Request with a frame of the same
+/// layout as the Vehicle Announcement Message, which `doip-definitions` decodes
+/// as a `VehicleAnnouncementMessage`.
+pub type VehicleIdentificationResponse = VehicleAnnouncementMessage;
+
+/// Successful outcome of a routing activation handshake
+#[derive(Debug, Clone, PartialEq)]
+pub enum RoutingActivationOutcome {
+    /// Routing was successfully activated and the session is usable
+    Activated(RoutingActivationResponse),
+
+    /// Routing was activated but the DoIP entity requires a further
+    /// confirmation step before the session is usable
+    PendingConfirmation(RoutingActivationResponse),
+}
+
+impl RoutingActivationOutcome {
+    pub(crate) fn from_response(res: RoutingActivationResponse) -> Result<Self, ActivationError> {
+        match res.activation_code {
+            ActivationCode::SuccessfullyActivated => Ok(Self::Activated(res)),
+            ActivationCode::ActivatedConfirmationRequired => Ok(Self::PendingConfirmation(res)),
+            _ => Err(ActivationError::Denied(res)),
+        }
+    }
+}
+
+/// Distinguishes a peer closing mid-frame from other decode errors
+///
+/// When the stream ends with a partial frame buffered the codec reports a
+/// generic error, this replaces it with `io::ErrorKind::UnexpectedEof` so a
+/// truncated frame can be told apart from a clean close, which yields `None`.
+pub(crate) fn truncated_frame(err: CodecError, buffered: usize) -> CodecError {
+    match err {
+        CodecError::IoError(err) if err.kind() == io::ErrorKind::Other && buffered > 0 => {
+            CodecError::IoError(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Stream closed with a truncated frame of {buffered} bytes buffered"),
+            ))
+        }
+        err => err,
+    }
+}
+
+/// Whether a read error leaves the stream usable
+///
+/// A read timeout, an oversized frame and a version mismatch consume nothing
+/// or only the offending frame, so reading can continue. Any other error ends
+/// the stream.
+pub(crate) fn is_recoverable(err: &CodecError) -> bool {
+    matches!(
+        err,
+        CodecError::IoError(err)
+            if matches!(err.kind(), io::ErrorKind::TimedOut | io::ErrorKind::InvalidData)
+    )
+}
 
-impl DoipTcpPayload for GenericNack {}
-impl DoipTcpPayload for RoutingActivationRequest {}
-impl DoipTcpPayload for RoutingActivationResponse {}
-impl DoipTcpPayload for AliveCheckRequest {}
-impl DoipTcpPayload for AliveCheckResponse {}
-impl DoipTcpPayload for DiagnosticMessage {}
-impl DoipTcpPayload for DiagnosticMessageAck {}
-impl DoipTcpPayload for DiagnosticMessageNack {}
+/// Error returned for a frame whose payload exceeds the max message size
+pub(crate) fn oversized_error(payload_length: u32) -> CodecError {
+    CodecError::IoError(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Frame payload of {payload_length} bytes exceeds max message size"),
+    ))
+}
+
+/// Converts a codec error into an `io::Error`, decode errors becoming
+/// `io::ErrorKind::InvalidData`
+pub(crate) fn codec_to_io(err: CodecError) -> io::Error {
+    match err {
+        CodecError::IoError(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, err.to_string()),
+    }
+}
+
+/// Reads the next item off a framed stream, giving up after `timeout`
+///
+/// An elapsed timeout is returned as `SocketError::ReadTimeout` wrapped in an
+/// `io::ErrorKind::TimedOut` error, leaving the stream open so the read can be
+/// retried.
+pub(crate) async fn next_frame<S, I>(
+    io: &mut S,
+    timeout: Option<Duration>,
+) -> Option<Result<I, CodecError>>
+where
+    S: Stream<Item = Result<I, CodecError>> + Unpin,
+{
+    let Some(timeout) = timeout else {
+        return io.next().await;
+    };
+
+    match tokio::time::timeout(timeout, io.next()).await {
+        Ok(res) => res,
+        Err(_) => Some(Err(CodecError::IoError(io::Error::new(
+            io::ErrorKind::TimedOut,
+            SocketError::ReadTimeout,
+        )))),
+    }
+}
 
 impl Default for SocketConfig {
     fn default() -> Self {
         Self {
-            protocol_version: ProtocolVersion::DefaultValue,
+            protocol_version: ProtocolVersion::Iso13400_2012,
+            auto_alive_check: None,
+            send_rate_limit: None,
+            runtime: None,
+            max_message_size: None,
+            oversize_nack: None,
+            outbound_queue_capacity: DOIP_OUTBOUND_QUEUE_CAPACITY,
+            overflow_policy: OverflowPolicy::Block,
+            enforce_payload_version: false,
+            max_alive_check_rate: None,
+            on_unknown_payload: UnknownPayloadPolicy::Skip,
+            oem_specific: None,
+            frame_history: None,
+            min_send_gap: None,
+            dry_run: false,
+            relay_mode: false,
+            auto_status_response: None,
+            auto_power_mode: None,
+            read_timeout: None,
+            nodelay: None,
+            keepalive: None,
+            strict_version: false,
         }
     }
 }
@@ -0,0 +1,202 @@
+use std::{io, time::Duration};
+
+use doip_definitions::payload::{ActivationType, DiagnosticMessage, RoutingActivationResponse};
+use tokio::net::ToSocketAddrs;
+
+use crate::error::{ActivationError, DoipError};
+
+use super::TcpStream;
+
+/// Default time `DoipClient` waits for the response to a Diagnostic Message
+pub const DOIP_CLIENT_RESPONSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// High level DoIP client for the common tester to ECU case
+///
+/// Wraps a `TcpStream` along with the tester `source_address` and the
+/// `logical_address` of the target ECU, so a session is a connect, an
+/// activation and a series of `send_diagnostic` calls. The underlying stream
+/// remains reachable through `stream_mut` for anything not covered here.
+#[derive(Debug)]
+pub struct DoipClient {
+    stream: TcpStream,
+    source_address: [u8; 2],
+    logical_address: [u8; 2],
+    timeout: Duration,
+}
+
+impl DoipClient {
+    /// Connect to the DoIP entity at `addr`
+    ///
+    /// Routing is not activated, call `activate` before sending diagnostics.
+    pub async fn connect<A: ToSocketAddrs>(
+        addr: A,
+        source_address: [u8; 2],
+        logical_address: [u8; 2],
+    ) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+
+        Ok(Self::from_stream(stream, source_address, logical_address))
+    }
+
+    /// Wrap an already connected `TcpStream`
+    pub fn from_stream(
+        stream: TcpStream,
+        source_address: [u8; 2],
+        logical_address: [u8; 2],
+    ) -> Self {
+        DoipClient {
+            stream,
+            source_address,
+            logical_address,
+            timeout: DOIP_CLIENT_RESPONSE_TIMEOUT,
+        }
+    }
+
+    /// Set how long `send_diagnostic` waits for a response
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Activate routing for the tester `source_address`
+    ///
+    /// As `TcpStream::activate`, only `ActivationCode::SuccessfullyActivated`
+    /// is accepted.
+    pub async fn activate(
+        &mut self,
+        activation_type: ActivationType,
+    ) -> Result<RoutingActivationResponse, ActivationError> {
+        self.stream
+            .activate(self.source_address, activation_type)
+            .await
+    }
+
+    /// Send UDS `data` to the target ECU and return its response
+    ///
+    /// The Diagnostic Message Ack is consumed, a Nack is returned as
+    /// `DoipError::Nack`. The response bytes are returned as received, so a
+    /// UDS negative response is left for the caller to interpret. Frames
+    /// unrelated to the request are queued on the underlying stream.
+    pub async fn send_diagnostic(&mut self, data: Vec<u8>) -> Result<Vec<u8>, DoipError> {
+        let request = DiagnosticMessage {
+            source_address: self.source_address,
+            target_address: self.logical_address,
+            message: data,
+        };
+
+        self.stream
+            .send_request(request, self.timeout)
+            .await
+            .map(|res| res.message)
+    }
+
+    /// Flush any buffered frames and shut down the connection
+    pub async fn close(mut self) -> io::Result<()> {
+        self.stream.shutdown().await
+    }
+
+    /// The tester logical address used as the source of requests
+    pub fn source_address(&self) -> [u8; 2] {
+        self.source_address
+    }
+
+    /// The logical address of the target ECU
+    pub fn logical_address(&self) -> [u8; 2] {
+        self.logical_address
+    }
+
+    /// Mutably borrow the underlying `TcpStream`
+    pub fn stream_mut(&mut self) -> &mut TcpStream {
+        &mut self.stream
+    }
+
+    /// Consume the client, returning the underlying `TcpStream`
+    pub fn into_stream(self) -> TcpStream {
+        self.stream
+    }
+}
+
+#[cfg(test)]
+mod test_doip_client {
+    use doip_definitions::payload::{
+        ActivationCode, ActivationType, DiagnosticAckCode, DiagnosticMessage, DiagnosticMessageAck,
+        DiagnosticMessageNack, DiagnosticNackCode, DoipPayload, RoutingActivationResponse,
+    };
+
+    use crate::{error::DoipError, tcp::TcpStream};
+
+    use super::DoipClient;
+
+    #[tokio::test]
+    async fn test_doip_client_session() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        const TESTER: [u8; 2] = [0x0e, 0x80];
+        const ECU: [u8; 2] = [0x14, 0x11];
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let ecu = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut server = TcpStream::new(socket);
+
+            server.read().await.unwrap().unwrap();
+            server
+                .send(DoipPayload::RoutingActivationResponse(
+                    RoutingActivationResponse {
+                        logical_address: TESTER,
+                        source_address: ECU,
+                        activation_code: ActivationCode::SuccessfullyActivated,
+                        buffer: [0, 0, 0, 0],
+                    },
+                ))
+                .await
+                .unwrap();
+
+            server.read().await.unwrap().unwrap();
+            server
+                .send(DoipPayload::DiagnosticMessageAck(DiagnosticMessageAck {
+                    source_address: ECU,
+                    target_address: TESTER,
+                    ack_code: DiagnosticAckCode::Acknowledged,
+                }))
+                .await
+                .unwrap();
+            server
+                .send(DoipPayload::DiagnosticMessage(DiagnosticMessage {
+                    source_address: ECU,
+                    target_address: TESTER,
+                    message: vec![0x62, 0xf1, 0x90],
+                }))
+                .await
+                .unwrap();
+
+            server.read().await.unwrap().unwrap();
+            server
+                .send(DoipPayload::DiagnosticMessageNack(DiagnosticMessageNack {
+                    source_address: ECU,
+                    target_address: TESTER,
+                    nack_code: DiagnosticNackCode::UnknownTargetAddress,
+                }))
+                .await
+                .unwrap();
+
+            assert!(server.read().await.is_none());
+        });
+
+        let mut client = DoipClient::connect(addr, TESTER, ECU).await.unwrap();
+        client.activate(ActivationType::Default).await.unwrap();
+
+        let res = client
+            .send_diagnostic(vec![0x22, 0xf1, 0x90])
+            .await
+            .unwrap();
+        assert_eq!(res, vec![0x62, 0xf1, 0x90]);
+
+        let res = client.send_diagnostic(vec![0x22, 0xf1, 0x90]).await;
+        assert!(matches!(res, Err(DoipError::Nack(_))));
+
+        client.close().await.unwrap();
+        ecu.await.unwrap();
+    }
+}
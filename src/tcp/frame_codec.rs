@@ -0,0 +1,195 @@
+use doip_codec::{DoipCodec, Error as CodecError};
+use doip_definitions::{
+    definitions::{
+        DOIP_HEADER_LEN, DOIP_LENGTH_OFFSET, DOIP_ROUTING_ACTIVATION_REQ_LEN,
+        DOIP_ROUTING_ACTIVATION_REQ_OEM_LEN, DOIP_ROUTING_ACTIVATION_RES_LEN,
+        DOIP_ROUTING_ACTIVATION_RES_OEM_LEN, DOIP_TYPE_OFFSET,
+    },
+    header::PayloadType,
+    message::DoipMessage,
+    payload::DoipPayload,
+};
+use tokio_util::{
+    bytes::{Buf, BytesMut},
+    codec::{Decoder, Encoder},
+};
+
+use crate::SocketConfig;
+
+/// A frame decoded by the `FrameCodec`
+#[derive(Debug)]
+pub(crate) enum Frame {
+    /// A complete DoIP frame
+    Message(DoipMessage),
+
+    /// A frame whose payload exceeds the maximum, carrying the advertised
+    /// payload length. The payload is discarded as it arrives.
+    Oversized(u32),
+}
+
+/// Wraps the `DoipCodec` with an optional limit on the inbound payload length
+///
+/// Frames over the limit are reported from their header alone and their
+/// payload skipped, so the frame is never fully buffered.
+///
+/// The optional OEM specific field of Routing Activation frames, which the
+/// `DoipCodec` does not model, is appended on encode when configured and kept
+/// from the last Routing Activation frame decoded.
+#[derive(Debug)]
+pub(crate) struct FrameCodec {
+    inner: DoipCodec,
+    max_payload_length: Option<u32>,
+    discard: usize,
+    oem_specific: Option<[u8; 4]>,
+    received_oem_specific: Option<[u8; 4]>,
+}
+
+/// Length of a Routing Activation payload without the OEM specific field, and
+/// the length of the OEM specific field
+fn activation_lengths(payload_type: PayloadType) -> Option<(usize, usize)> {
+    match payload_type {
+        PayloadType::RoutingActivationRequest => Some((
+            DOIP_ROUTING_ACTIVATION_REQ_LEN,
+            DOIP_ROUTING_ACTIVATION_REQ_OEM_LEN,
+        )),
+        PayloadType::RoutingActivationResponse => Some((
+            DOIP_ROUTING_ACTIVATION_RES_LEN,
+            DOIP_ROUTING_ACTIVATION_RES_OEM_LEN,
+        )),
+        _ => None,
+    }
+}
+
+impl FrameCodec {
+    pub(crate) fn new(max_payload_length: Option<u32>) -> Self {
+        FrameCodec {
+            inner: DoipCodec {},
+            max_payload_length,
+            discard: 0,
+            oem_specific: None,
+            received_oem_specific: None,
+        }
+    }
+
+    /// Creates a codec with the message size limit and OEM specific bytes of
+    /// `config`
+    pub(crate) fn from_config(config: &SocketConfig) -> Self {
+        let mut codec = Self::new(config.max_message_size);
+        codec.set_oem_specific(config.oem_specific);
+        codec
+    }
+
+    /// Creates a codec with the same limit and OEM specific bytes but no
+    /// decode state, for the write half of a split stream
+    pub(crate) fn encoder(&self) -> Self {
+        let mut codec = Self::new(self.max_payload_length);
+        codec.set_oem_specific(self.oem_specific);
+        codec
+    }
+
+    pub(crate) fn set_max_payload_length(&mut self, max_payload_length: Option<u32>) {
+        self.max_payload_length = max_payload_length
+    }
+
+    pub(crate) fn set_oem_specific(&mut self, oem_specific: Option<[u8; 4]>) {
+        self.oem_specific = oem_specific
+    }
+
+    pub(crate) fn received_oem_specific(&self) -> Option<[u8; 4]> {
+        self.received_oem_specific
+    }
+
+    /// Stop discarding the remainder of an oversized frame, for when the bytes
+    /// still to come belong to a new transport
+    pub(crate) fn clear_discard(&mut self) {
+        self.discard = 0
+    }
+
+    fn peek_oem_specific(src: &BytesMut) -> Option<[u8; 4]> {
+        let header = src.get(..DOIP_HEADER_LEN)?;
+        let payload_type = PayloadType::try_from(&header[DOIP_TYPE_OFFSET..]).ok()?;
+        let (base, oem_len) = activation_lengths(payload_type)?;
+
+        let mut length = [0u8; 4];
+        length.copy_from_slice(&header[DOIP_LENGTH_OFFSET..]);
+        if u32::from_be_bytes(length) as usize != base + oem_len {
+            return None;
+        }
+
+        let start = DOIP_HEADER_LEN + base;
+        let mut oem = [0u8; 4];
+        oem.copy_from_slice(src.get(start..start + oem_len)?);
+
+        Some(oem)
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = Frame;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if self.discard > 0 {
+            let skip = self.discard.min(src.len());
+            src.advance(skip);
+            self.discard -= skip;
+
+            if self.discard > 0 {
+                return Ok(None);
+            }
+        }
+
+        if let (Some(max), Some(header)) = (self.max_payload_length, src.get(..DOIP_HEADER_LEN)) {
+            let mut length = [0u8; 4];
+            length.copy_from_slice(&header[DOIP_LENGTH_OFFSET..]);
+            let payload_length = u32::from_be_bytes(length);
+
+            if payload_length > max {
+                src.advance(DOIP_HEADER_LEN);
+                self.discard = payload_length as usize;
+
+                return Ok(Some(Frame::Oversized(payload_length)));
+            }
+        }
+
+        let oem_specific = Self::peek_oem_specific(src);
+        let msg = self.inner.decode(src)?;
+
+        if let Some(msg) = &msg {
+            if activation_lengths(msg.header.payload_type).is_some() {
+                self.received_oem_specific = oem_specific;
+            }
+        }
+
+        Ok(msg.map(Frame::Message))
+    }
+}
+
+impl Encoder<DoipMessage> for FrameCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, item: DoipMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let oem_specific = self.oem_specific.filter(|_| {
+            matches!(
+                item.payload,
+                DoipPayload::RoutingActivationRequest(_)
+                    | DoipPayload::RoutingActivationResponse(_)
+            )
+        });
+
+        let start = dst.len();
+        self.inner.encode(item, dst)?;
+
+        if let Some(oem) = oem_specific {
+            let length_field = start + DOIP_LENGTH_OFFSET..start + DOIP_HEADER_LEN;
+            let mut length = [0u8; 4];
+            length.copy_from_slice(&dst[length_field.clone()]);
+
+            let length = u32::from_be_bytes(length) + oem.len() as u32;
+            dst[length_field].copy_from_slice(&length.to_be_bytes());
+            dst.extend_from_slice(&oem);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,44 @@
+use std::{collections::VecDeque, time::Instant};
+
+use doip_definitions::message::DoipMessage;
+
+/// Direction of a frame recorded in the frame history of a TCP Stream
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+    /// The frame was sent to the peer
+    Sent,
+
+    /// The frame was received from the peer
+    Received,
+}
+
+/// Ring buffer of the most recent frames sent and received on a TCP Stream
+///
+/// Once full the oldest frame is dropped for each new one.
+#[derive(Debug)]
+pub(crate) struct FrameHistory {
+    capacity: usize,
+    frames: VecDeque<(Direction, Instant, DoipMessage)>,
+}
+
+impl FrameHistory {
+    pub(crate) fn new(capacity: usize) -> Self {
+        FrameHistory {
+            capacity,
+            frames: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub(crate) fn record(&mut self, direction: Direction, msg: &DoipMessage) {
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+
+        self.frames
+            .push_back((direction, Instant::now(), msg.clone()));
+    }
+
+    pub(crate) fn frames(&self) -> Vec<(Direction, Instant, DoipMessage)> {
+        self.frames.iter().cloned().collect()
+    }
+}
@@ -0,0 +1,48 @@
+use std::collections::HashMap;
+
+/// Table of logical addresses known to a DoIP entity
+///
+/// Maps each registered logical address to a handler of type `H`, such as a
+/// channel to the ECU behind a gateway. Consulted by
+/// `TcpStream::respond_activation` and `TcpStream::route_diagnostic` to accept
+/// or reject addresses.
+#[derive(Debug, Clone)]
+pub struct LogicalAddressTable<H = ()> {
+    entries: HashMap<[u8; 2], H>,
+}
+
+impl<H> LogicalAddressTable<H> {
+    /// Creates an empty Logical Address Table
+    pub fn new() -> Self {
+        LogicalAddressTable {
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Register a logical address, returning the previous handler if the
+    /// address was already registered
+    pub fn register(&mut self, address: [u8; 2], handler: H) -> Option<H> {
+        self.entries.insert(address, handler)
+    }
+
+    /// Unregister a logical address, returning its handler
+    pub fn unregister(&mut self, address: [u8; 2]) -> Option<H> {
+        self.entries.remove(&address)
+    }
+
+    /// Get the handler registered for a logical address
+    pub fn lookup(&self, address: [u8; 2]) -> Option<&H> {
+        self.entries.get(&address)
+    }
+
+    /// Whether a logical address is registered
+    pub fn contains(&self, address: [u8; 2]) -> bool {
+        self.entries.contains_key(&address)
+    }
+}
+
+impl<H> Default for LogicalAddressTable<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,174 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use doip_definitions::payload::DoipPayload;
+use tokio::sync::Notify;
+
+use crate::error::EnqueueError;
+
+/// Behaviour of an `OutboundQueue` when a frame is enqueued while it is full
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait until the writer makes space
+    Block,
+
+    /// Discard the frame being enqueued
+    DropNewest,
+
+    /// Discard the oldest queued frame to make space
+    DropOldest,
+
+    /// Return `EnqueueError::Full`
+    Error,
+}
+
+#[derive(Debug)]
+struct State {
+    frames: VecDeque<DoipPayload>,
+    producers_closed: bool,
+    writer_closed: bool,
+}
+
+#[derive(Debug)]
+pub(crate) struct Shared {
+    state: Mutex<State>,
+    frame_ready: Notify,
+    space_ready: Notify,
+    capacity: usize,
+    policy: OverflowPolicy,
+}
+
+impl Shared {
+    pub(crate) fn new(capacity: usize, policy: OverflowPolicy) -> Arc<Self> {
+        Arc::new(Shared {
+            state: Mutex::new(State {
+                frames: VecDeque::with_capacity(capacity),
+                producers_closed: false,
+                writer_closed: false,
+            }),
+            frame_ready: Notify::new(),
+            space_ready: Notify::new(),
+            capacity: capacity.max(1),
+            policy,
+        })
+    }
+
+    /// Wait for the next queued frame, returns `None` once every
+    /// `OutboundQueue` is dropped and the queue is drained
+    pub(crate) async fn next_frame(&self) -> Option<DoipPayload> {
+        loop {
+            {
+                let mut state = self.state.lock().expect("Outbound queue lock poisoned");
+
+                if let Some(payload) = state.frames.pop_front() {
+                    self.space_ready.notify_one();
+                    return Some(payload);
+                }
+
+                if state.producers_closed {
+                    return None;
+                }
+            }
+
+            self.frame_ready.notified().await;
+        }
+    }
+
+    /// Mark the writer as stopped, so further enqueues fail
+    pub(crate) fn close_writer(&self) {
+        let mut state = self.state.lock().expect("Outbound queue lock poisoned");
+        state.writer_closed = true;
+        state.frames.clear();
+        self.space_ready.notify_waiters();
+    }
+}
+
+#[derive(Debug)]
+struct Producers(Arc<Shared>);
+
+impl Drop for Producers {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().expect("Outbound queue lock poisoned");
+        state.producers_closed = true;
+        self.0.frame_ready.notify_one();
+    }
+}
+
+/// Bounded queue of outbound frames drained by a background writer
+///
+/// Created by `TcpStreamWriteHalf::spawn_writer`, with the capacity and
+/// overflow policy taken from the `SocketConfig`. Cloning the queue shares it,
+/// the writer stops once every clone is dropped and the queue is drained.
+#[derive(Debug, Clone)]
+pub struct OutboundQueue {
+    shared: Arc<Shared>,
+    _producers: Arc<Producers>,
+}
+
+impl OutboundQueue {
+    pub(crate) fn new(shared: Arc<Shared>) -> Self {
+        OutboundQueue {
+            _producers: Arc::new(Producers(shared.clone())),
+            shared,
+        }
+    }
+
+    /// Queue a frame to be sent by the writer
+    ///
+    /// When the queue is full the overflow policy decides whether this waits,
+    /// discards a frame, or returns `EnqueueError::Full`.
+    pub async fn enqueue(&self, payload: DoipPayload) -> Result<(), EnqueueError> {
+        let shared = &self.shared;
+
+        loop {
+            let space_ready = shared.space_ready.notified();
+            tokio::pin!(space_ready);
+
+            {
+                let mut state = shared.state.lock().expect("Outbound queue lock poisoned");
+
+                if state.writer_closed {
+                    return Err(EnqueueError::Closed);
+                }
+
+                if state.frames.len() >= shared.capacity {
+                    match shared.policy {
+                        OverflowPolicy::Block => {
+                            space_ready.as_mut().enable();
+                        }
+                        OverflowPolicy::DropNewest => return Ok(()),
+                        OverflowPolicy::DropOldest => {
+                            state.frames.pop_front();
+                        }
+                        OverflowPolicy::Error => return Err(EnqueueError::Full),
+                    }
+                }
+
+                if state.frames.len() < shared.capacity {
+                    state.frames.push_back(payload);
+                    shared.frame_ready.notify_one();
+                    return Ok(());
+                }
+            }
+
+            space_ready.await;
+        }
+    }
+
+    /// Number of frames waiting to be sent
+    pub fn len(&self) -> usize {
+        let state = self
+            .shared
+            .state
+            .lock()
+            .expect("Outbound queue lock poisoned");
+        state.frames.len()
+    }
+
+    /// Whether no frames are waiting to be sent
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
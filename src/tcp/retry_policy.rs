@@ -0,0 +1,113 @@
+use std::time::Duration;
+
+/// How the delay between connection attempts grows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backoff {
+    /// Wait the base delay before every retry
+    Fixed,
+
+    /// Wait the base delay multiplied by the retry number
+    Linear,
+
+    /// Double the delay after every retry, starting from the base delay
+    Exponential,
+}
+
+/// Policy for retrying a failed connection attempt
+///
+/// A DoIP entity which is rebooting, such as during flashing, refuses
+/// connections until its TCP stack is up again, so retrying quickly at first
+/// then backing off exponentially up to a cap of a few seconds suits most
+/// gateways.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    retries: usize,
+    delay: Duration,
+    backoff: Backoff,
+    max_delay: Option<Duration>,
+}
+
+impl RetryPolicy {
+    /// Retry up to `retries` times, waiting `delay` before each retry
+    pub fn fixed(retries: usize, delay: Duration) -> Self {
+        Self::new(retries, delay, Backoff::Fixed)
+    }
+
+    /// Retry up to `retries` times, waiting `delay` longer before each retry
+    pub fn linear(retries: usize, delay: Duration) -> Self {
+        Self::new(retries, delay, Backoff::Linear)
+    }
+
+    /// Retry up to `retries` times, waiting `delay` before the first retry and
+    /// doubling the wait before each retry after
+    pub fn exponential(retries: usize, delay: Duration) -> Self {
+        Self::new(retries, delay, Backoff::Exponential)
+    }
+
+    fn new(retries: usize, delay: Duration, backoff: Backoff) -> Self {
+        RetryPolicy {
+            retries,
+            delay,
+            backoff,
+            max_delay: None,
+        }
+    }
+
+    /// Cap the wait before any single retry
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = Some(max_delay);
+        self
+    }
+
+    /// The number of retries after the first attempt
+    pub fn retries(&self) -> usize {
+        self.retries
+    }
+
+    /// The backoff used between retries
+    pub fn backoff(&self) -> Backoff {
+        self.backoff
+    }
+
+    /// The wait before retry number `retry`, counting from zero
+    pub fn delay(&self, retry: usize) -> Duration {
+        let retry = u32::try_from(retry).unwrap_or(u32::MAX);
+
+        let delay = match self.backoff {
+            Backoff::Fixed => self.delay,
+            Backoff::Linear => self.delay.saturating_mul(retry.saturating_add(1)),
+            Backoff::Exponential => self
+                .delay
+                .saturating_mul(2u32.checked_pow(retry).unwrap_or(u32::MAX)),
+        };
+
+        match self.max_delay {
+            Some(max_delay) => delay.min(max_delay),
+            None => delay,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_retry_policy {
+    use std::time::Duration;
+
+    use crate::tcp::RetryPolicy;
+
+    #[test]
+    fn test_retry_policy_delay() {
+        let delay = Duration::from_millis(100);
+
+        let policy = RetryPolicy::fixed(3, delay);
+        assert_eq!(policy.delay(2), delay);
+
+        let policy = RetryPolicy::linear(3, delay);
+        assert_eq!(policy.delay(2), delay * 3);
+
+        let policy = RetryPolicy::exponential(3, delay).with_max_delay(delay * 5);
+        assert_eq!(policy.delay(0), delay);
+        assert_eq!(policy.delay(2), delay * 4);
+        assert_eq!(policy.delay(3), delay * 5);
+        assert_eq!(policy.delay(usize::MAX), delay * 5);
+    }
+}
@@ -0,0 +1,69 @@
+use doip_definitions::header::PayloadType;
+
+/// Counts of the frames sent and received on a TCP Stream
+///
+/// `PayloadType` does not implement `Hash`, so the per payload type counts are
+/// kept in the order each type was first seen.
+#[derive(Debug, Clone, Default)]
+pub struct SocketMetrics {
+    frames_sent: u64,
+    frames_received: u64,
+    per_type_sent: Vec<(PayloadType, u64)>,
+    per_type_received: Vec<(PayloadType, u64)>,
+}
+
+impl SocketMetrics {
+    /// Total number of frames sent
+    pub fn frames_sent(&self) -> u64 {
+        self.frames_sent
+    }
+
+    /// Total number of frames received
+    pub fn frames_received(&self) -> u64 {
+        self.frames_received
+    }
+
+    /// Number of frames sent of each payload type
+    pub fn per_type_sent(&self) -> &[(PayloadType, u64)] {
+        &self.per_type_sent
+    }
+
+    /// Number of frames received of each payload type
+    pub fn per_type_received(&self) -> &[(PayloadType, u64)] {
+        &self.per_type_received
+    }
+
+    /// Number of frames sent of `payload_type`
+    pub fn sent_of(&self, payload_type: PayloadType) -> u64 {
+        count_of(&self.per_type_sent, payload_type)
+    }
+
+    /// Number of frames received of `payload_type`
+    pub fn received_of(&self, payload_type: PayloadType) -> u64 {
+        count_of(&self.per_type_received, payload_type)
+    }
+
+    pub(crate) fn record_sent(&mut self, payload_type: PayloadType) {
+        self.frames_sent += 1;
+        increment(&mut self.per_type_sent, payload_type);
+    }
+
+    pub(crate) fn record_received(&mut self, payload_type: PayloadType) {
+        self.frames_received += 1;
+        increment(&mut self.per_type_received, payload_type);
+    }
+}
+
+fn count_of(counts: &[(PayloadType, u64)], payload_type: PayloadType) -> u64 {
+    counts
+        .iter()
+        .find(|(ty, _)| *ty == payload_type)
+        .map_or(0, |(_, count)| *count)
+}
+
+fn increment(counts: &mut Vec<(PayloadType, u64)>, payload_type: PayloadType) {
+    match counts.iter_mut().find(|(ty, _)| *ty == payload_type) {
+        Some((_, count)) => *count += 1,
+        None => counts.push((payload_type, 1)),
+    }
+}
@@ -1,25 +1,30 @@
 use std::{
     io::{self},
     pin::Pin,
+    sync::Arc,
 };
 
 use doip_codec::{DoipCodec, Error as CodecError};
 use doip_definitions::{
-    builder::DoipMessageBuilder, header::ProtocolVersion, message::DoipMessage,
-    payload::DoipPayload,
+    header::ProtocolVersion,
+    message::DoipMessage,
+    payload::{AliveCheckResponse, DoipPayload},
 };
 use futures::{SinkExt, StreamExt};
 use openssl::ssl::{Ssl, SslContextBuilder, SslMethod, SslOptions, SslVerifyMode, SslVersion};
-use tokio::net::{TcpStream as TokioTcpStream, ToSocketAddrs};
+use tokio::{
+    net::{TcpStream as TokioTcpStream, ToSocketAddrs},
+    sync::OwnedSemaphorePermit,
+};
 
 use tokio_openssl::SslStream;
 use tokio_util::codec::{Framed, FramedRead, FramedWrite};
 
-use crate::error::SocketSendError;
+use crate::{error::SocketSendError, util::build_message};
 
 use super::{
-    tcp_split::{TcpStreamReadHalf, TcpStreamWriteHalf},
-    SocketConfig,
+    tcp_split::{ErrorSignal, TcpStreamReadHalf, TcpStreamWriteHalf},
+    truncated_frame, SocketConfig,
 };
 /// Simple implementation of a TCP Stream
 ///
@@ -30,6 +35,7 @@ use super::{
 pub struct DoIpSslStream {
     io: Framed<SslStream<TokioTcpStream>, DoipCodec>,
     config: SocketConfig,
+    permit: Option<Arc<OwnedSemaphorePermit>>,
 }
 
 impl DoIpSslStream {
@@ -39,7 +45,9 @@ impl DoIpSslStream {
             io: Framed::new(io, DoipCodec {}),
             config: SocketConfig {
                 protocol_version: ProtocolVersion::Iso13400_2012,
+                ..Default::default()
             },
+            permit: None,
         }
     }
 
@@ -93,7 +101,7 @@ impl DoIpSslStream {
                 Pin::new(&mut stream)
                     .connect()
                     .await
-                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                    .map_err(io::Error::other)?;
 
                 Ok(Self::apply_codec(stream))
             }
@@ -106,26 +114,65 @@ impl DoIpSslStream {
             io: Framed::new(stream, DoipCodec {}),
             config: SocketConfig {
                 protocol_version: ProtocolVersion::Iso13400_2012,
+                ..Default::default()
             },
+            permit: None,
+        }
+    }
+
+    pub(crate) fn with_listener_config(
+        io: SslStream<TokioTcpStream>,
+        config: SocketConfig,
+        permit: Option<OwnedSemaphorePermit>,
+    ) -> DoIpSslStream {
+        DoIpSslStream {
+            io: Framed::new(io, DoipCodec {}),
+            config,
+            permit: permit.map(Arc::new),
         }
     }
 
     /// Send a DoIP frame to the sink
     pub async fn send(&mut self, payload: DoipPayload) -> Result<(), SocketSendError> {
-        let msg = DoipMessageBuilder::new()
-            .protocol_version(self.config.protocol_version)
-            .payload(payload)
-            .build();
+        let msg = build_message(self.config.protocol_version, payload)?;
 
         match self.io.send(msg).await {
             Ok(_) => Ok(()),
-            Err(err) => Err(SocketSendError::EncodeError(err)),
+            Err(err) => Err(SocketSendError::from(err)),
         }
     }
 
     /// Read a DoIP frame off the stream
+    ///
+    /// If the config has an auto alive check source address set, Alive Check
+    /// Requests are answered transparently and not returned.
     pub async fn read(&mut self) -> Option<Result<DoipMessage, CodecError>> {
-        self.io.next().await
+        loop {
+            let msg =
+                self.io.next().await.map(|res| {
+                    res.map_err(|err| truncated_frame(err, self.io.read_buffer().len()))
+                });
+
+            if let (
+                Some(source_address),
+                Some(Ok(DoipMessage {
+                    payload: DoipPayload::AliveCheckRequest(_),
+                    ..
+                })),
+            ) = (self.config.auto_alive_check, &msg)
+            {
+                let response =
+                    DoipPayload::AliveCheckResponse(AliveCheckResponse { source_address });
+
+                match self.send(response).await {
+                    Ok(()) => continue,
+                    Err(SocketSendError::EncodeError(err)) => return Some(Err(err)),
+                    Err(err) => return Some(Err(CodecError::IoError(io::Error::other(err)))),
+                }
+            }
+
+            return msg;
+        }
     }
 
     /// Splits the TCP Stream into a Read Half and Write Half
@@ -135,16 +182,28 @@ impl DoIpSslStream {
         TcpStreamReadHalf<SslStream<TokioTcpStream>>,
         TcpStreamWriteHalf<SslStream<TokioTcpStream>>,
     ) {
+        let tcp = self.io.get_ref().get_ref();
+        let addrs = (tcp.peer_addr().ok(), tcp.local_addr().ok());
         let stream: SslStream<TokioTcpStream> = self.io.into_inner();
 
         let (r_half, w_half) = tokio::io::split(stream);
 
         let read = FramedRead::new(r_half, DoipCodec {});
         let write = FramedWrite::new(w_half, DoipCodec {});
+        let error = Arc::new(ErrorSignal::default());
 
         (
-            TcpStreamReadHalf::new(read, Some(self.config)),
-            TcpStreamWriteHalf::new(write, Some(self.config)),
+            TcpStreamReadHalf::new(read, Some(self.config.clone()))
+                .with_permit(self.permit.clone())
+                .with_error_signal(error.clone())
+                .with_addrs(addrs, |stream| {
+                    let tcp = stream.get_ref();
+                    (tcp.peer_addr().ok(), tcp.local_addr().ok())
+                }),
+            TcpStreamWriteHalf::new(write, Some(self.config))
+                .with_permit(self.permit)
+                .with_error_signal(error)
+                .with_addrs(addrs),
         )
     }
 
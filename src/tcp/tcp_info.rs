@@ -0,0 +1,71 @@
+use std::{io, time::Duration};
+
+use tokio::net::TcpStream as TokioTcpStream;
+
+/// Selected fields of the kernel `TCP_INFO` for a TCP Stream
+///
+/// Useful for correlating DoIP throughput with link conditions.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TcpInfoSnapshot {
+    /// Smoothed round trip time
+    pub rtt: Duration,
+
+    /// Round trip time variance
+    pub rtt_var: Duration,
+
+    /// Congestion window, in segments
+    pub snd_cwnd: u32,
+
+    /// Maximum segment size used when sending, in bytes
+    pub snd_mss: u32,
+
+    /// Segments sent which are not yet acknowledged
+    pub unacked: u32,
+
+    /// Segments considered lost
+    pub lost: u32,
+
+    /// Total segments retransmitted over the life of the connection
+    pub total_retrans: u32,
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn tcp_info(stream: &TokioTcpStream) -> io::Result<TcpInfoSnapshot> {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: all-zero is a valid representation for this C struct.
+    let mut info: libc::tcp_info = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+
+    // SAFETY: `info` is a live buffer of `len` bytes.
+    let res = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            &mut info as *mut libc::tcp_info as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(TcpInfoSnapshot {
+        rtt: Duration::from_micros(info.tcpi_rtt.into()),
+        rtt_var: Duration::from_micros(info.tcpi_rttvar.into()),
+        snd_cwnd: info.tcpi_snd_cwnd,
+        snd_mss: info.tcpi_snd_mss,
+        unacked: info.tcpi_unacked,
+        lost: info.tcpi_lost,
+        total_retrans: info.tcpi_total_retrans,
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn tcp_info(_stream: &TokioTcpStream) -> io::Result<TcpInfoSnapshot> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "TCP_INFO is only supported on Linux",
+    ))
+}
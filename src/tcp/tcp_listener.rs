@@ -1,24 +1,149 @@
-use std::{io, net::SocketAddr};
-use tokio::net::TcpListener as TokioTcpListener;
+use std::{io, net::SocketAddr, sync::Arc};
 
-use super::TcpStream;
+use doip_definitions::{definitions::DOIP_PORT, header::ProtocolVersion};
+use tokio::{
+    net::{TcpListener as TokioTcpListener, ToSocketAddrs},
+    sync::Semaphore,
+};
+
+#[cfg(feature = "ssl")]
+use openssl::ssl::{Ssl, SslAcceptor};
+#[cfg(feature = "ssl")]
+use std::{pin::Pin, time::Duration};
+#[cfg(feature = "ssl")]
+use tokio_openssl::SslStream;
+
+#[cfg(feature = "ssl")]
+use super::DoIpSslStream;
+use super::{SocketConfig, TcpStream};
+
+/// Time allowed for the TLS handshake of a connection accepted by `accept_tls`
+#[cfg(feature = "ssl")]
+pub const DOIP_TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// A TCP socket server, listening for connections
+///
+/// Each accepted connection is returned as a `TcpStream` already carrying the
+/// listener's `SocketConfig`, as the server side counterpart to
+/// `TcpStream::connect`.
 pub struct TcpListener {
     io: TokioTcpListener,
+    config: SocketConfig,
+    sockets: Option<Arc<Semaphore>>,
+    #[cfg(feature = "ssl")]
+    tls_acceptor: Option<SslAcceptor>,
 }
 
 impl TcpListener {
     /// Initialised a new TcpListener
+    ///
+    /// Accepted streams use the config set by `set_default_socket_config`, if
+    /// any.
     pub fn new(io: TokioTcpListener) -> Self {
-        TcpListener { io }
+        TcpListener {
+            io,
+            config: SocketConfig::process_default().unwrap_or_default(),
+            sockets: None,
+            #[cfg(feature = "ssl")]
+            tls_acceptor: None,
+        }
+    }
+
+    /// Binds a new listener to `addr`
+    pub async fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Ok(Self::new(TokioTcpListener::bind(addr).await?))
+    }
+
+    /// Binds a new listener to `addr`, applying `config` to each accepted
+    /// stream
+    pub async fn bind_with_config<A: ToSocketAddrs>(
+        addr: A,
+        config: SocketConfig,
+    ) -> io::Result<Self> {
+        Ok(Self::bind(addr).await?.with_config(config))
+    }
+
+    /// Set the config applied to each subsequently accepted stream
+    pub fn with_config(mut self, config: SocketConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Returns the local address the listener is bound to
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.io.local_addr()
     }
 
     /// Accepts an new incoming connection from the listener
+    ///
+    /// When a maximum number of sockets is configured, connections beyond the
+    /// limit are closed immediately and the listener keeps waiting.
     pub async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
-        let (stream, addr) = self.io.accept().await?;
+        loop {
+            let (stream, addr) = self.io.accept().await?;
+
+            let permit = match &self.sockets {
+                Some(sockets) => match sockets.clone().try_acquire_owned() {
+                    Ok(permit) => Some(permit),
+                    Err(_) => continue,
+                },
+                None => None,
+            };
 
-        Ok((TcpStream::new(stream), addr))
+            return Ok((
+                TcpStream::with_listener_config(stream, self.config.clone(), permit),
+                addr,
+            ));
+        }
+    }
+
+    /// Accepts a new incoming connection and performs the TLS handshake using
+    /// the acceptor configured on the `DoipServerBuilder`
+    ///
+    /// Connections whose handshake fails, or does not complete within
+    /// `DOIP_TLS_HANDSHAKE_TIMEOUT`, are closed and the listener keeps waiting,
+    /// as with connections beyond the maximum number of sockets.
+    #[cfg(feature = "ssl")]
+    pub async fn accept_tls(&self) -> io::Result<(DoIpSslStream, SocketAddr)> {
+        let acceptor = self.tls_acceptor.as_ref().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "No TLS acceptor configured")
+        })?;
+
+        loop {
+            let (stream, addr) = self.io.accept().await?;
+
+            let permit = match &self.sockets {
+                Some(sockets) => match sockets.clone().try_acquire_owned() {
+                    Ok(permit) => Some(permit),
+                    Err(_) => continue,
+                },
+                None => None,
+            };
+
+            let ssl = Ssl::new(acceptor.context())?;
+            let mut stream = SslStream::new(ssl, stream)?;
+
+            let handshake = Pin::new(&mut stream).accept();
+
+            match tokio::time::timeout(DOIP_TLS_HANDSHAKE_TIMEOUT, handshake).await {
+                Ok(Ok(())) => {}
+                Ok(Err(_err)) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(peer = %addr, error = %_err, "TLS handshake failed");
+                    continue;
+                }
+                Err(_) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(peer = %addr, "TLS handshake timed out");
+                    continue;
+                }
+            }
+
+            return Ok((
+                DoIpSslStream::with_listener_config(stream, self.config.clone(), permit),
+                addr,
+            ));
+        }
     }
 
     /// Retruns the reference for the internal listener
@@ -26,3 +151,215 @@ impl TcpListener {
         &self.io
     }
 }
+
+/// Alias of `TcpListener` for use when writing a DoIP server or ECU simulator
+pub type DoipListener = TcpListener;
+
+/// Builder for a `TcpListener` acting as a DoIP server
+///
+/// Centralises the server configuration so each accepted `TcpStream` carries
+/// the same protocol version and alive check behaviour.
+pub struct DoipServerBuilder {
+    addr: SocketAddr,
+    config: SocketConfig,
+    max_sockets: Option<usize>,
+    #[cfg(feature = "ssl")]
+    tls_acceptor: Option<SslAcceptor>,
+}
+
+impl DoipServerBuilder {
+    /// Creates a new builder bound to `0.0.0.0:13400`
+    ///
+    /// Starts from the config set by `set_default_socket_config`, or the
+    /// ISO-13400 2012 defaults if none is set.
+    pub fn new() -> Self {
+        DoipServerBuilder {
+            addr: SocketAddr::from(([0, 0, 0, 0], DOIP_PORT as u16)),
+            config: SocketConfig::process_default().unwrap_or_default(),
+            max_sockets: None,
+            #[cfg(feature = "ssl")]
+            tls_acceptor: None,
+        }
+    }
+
+    /// Set the local address the server binds to
+    pub fn bind_addr(mut self, addr: SocketAddr) -> Self {
+        self.addr = addr;
+        self
+    }
+
+    /// Set the maximum number of concurrently open sockets
+    pub fn max_sockets(mut self, max_sockets: usize) -> Self {
+        self.max_sockets = Some(max_sockets);
+        self
+    }
+
+    /// Set the protocol version used by each accepted stream
+    pub fn protocol_version(mut self, version: ProtocolVersion) -> Self {
+        self.config.protocol_version = version;
+        self
+    }
+
+    /// Automatically answer Alive Check Requests on each accepted stream with
+    /// the given source address
+    pub fn auto_alive_check(mut self, source_address: [u8; 2]) -> Self {
+        self.config.auto_alive_check = Some(source_address);
+        self
+    }
+
+    /// Set the TLS acceptor used by `TcpListener::accept_tls`
+    #[cfg(feature = "ssl")]
+    pub fn tls_acceptor(mut self, acceptor: SslAcceptor) -> Self {
+        self.tls_acceptor = Some(acceptor);
+        self
+    }
+
+    /// Bind the listener and apply the configuration
+    pub async fn build(self) -> io::Result<TcpListener> {
+        let io = TokioTcpListener::bind(self.addr).await?;
+
+        Ok(TcpListener {
+            io,
+            config: self.config,
+            sockets: self.max_sockets.map(|max| Arc::new(Semaphore::new(max))),
+            #[cfg(feature = "ssl")]
+            tls_acceptor: self.tls_acceptor,
+        })
+    }
+}
+
+impl Default for DoipServerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test_tcp_listener {
+    use doip_definitions::{
+        header::ProtocolVersion,
+        payload::{
+            ActivationCode, ActivationType, AliveCheckRequest, DiagnosticMessage, DoipPayload,
+            RoutingActivationResponse,
+        },
+    };
+
+    use super::{DoipListener, DoipServerBuilder};
+    use crate::{
+        tcp::{DoipClient, TcpStream},
+        SocketConfig,
+    };
+
+    #[tokio::test]
+    async fn test_doip_listener_ecu_simulator() {
+        const TESTER: [u8; 2] = [0x0e, 0x80];
+        const ECU: [u8; 2] = [0x14, 0x11];
+
+        let config = SocketConfig::builder()
+            .protocol_version(ProtocolVersion::Iso13400_2019)
+            .build();
+        let listener = DoipListener::bind_with_config("127.0.0.1:0", config)
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // A minimal ECU answering routing activation and echoing a positive
+        // response to each Diagnostic Message
+        let ecu = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            assert_eq!(stream.protocol_version(), ProtocolVersion::Iso13400_2019);
+
+            while let Some(Ok(msg)) = stream.read().await {
+                let res = match msg.payload {
+                    DoipPayload::RoutingActivationRequest(req) => {
+                        DoipPayload::RoutingActivationResponse(RoutingActivationResponse {
+                            logical_address: req.source_address,
+                            source_address: ECU,
+                            activation_code: ActivationCode::SuccessfullyActivated,
+                            buffer: [0, 0, 0, 0],
+                        })
+                    }
+                    DoipPayload::DiagnosticMessage(req) => {
+                        let mut message = req.message;
+                        message[0] += 0x40;
+
+                        DoipPayload::DiagnosticMessage(DiagnosticMessage {
+                            source_address: req.target_address,
+                            target_address: req.source_address,
+                            message,
+                        })
+                    }
+                    _ => continue,
+                };
+
+                stream.send(res).await.unwrap();
+            }
+        });
+
+        let mut client = DoipClient::connect(addr, TESTER, ECU).await.unwrap();
+        client.activate(ActivationType::Default).await.unwrap();
+
+        let res = client.send_diagnostic(vec![0x3e, 0x00]).await.unwrap();
+        assert_eq!(res, vec![0x7e, 0x00]);
+
+        client.close().await.unwrap();
+        ecu.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_server_builder() {
+        let listener = DoipServerBuilder::new()
+            .bind_addr("127.0.0.1:0".parse().unwrap())
+            .max_sockets(1)
+            .protocol_version(ProtocolVersion::Iso13400_2010)
+            .build()
+            .await
+            .unwrap();
+        let addr = listener.get_ref().local_addr().unwrap();
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+
+        server
+            .send(DoipPayload::AliveCheckRequest(AliveCheckRequest {}))
+            .await
+            .unwrap();
+        let msg = client.read().await.unwrap().unwrap();
+
+        assert_eq!(msg.header.protocol_version, ProtocolVersion::Iso13400_2010);
+
+        let mut rejected = TcpStream::connect(addr).await.unwrap();
+        let pending = tokio::spawn(async move { listener.accept().await.map(|_| ()) });
+
+        assert!(!matches!(rejected.read().await, Some(Ok(_))));
+
+        pending.abort();
+    }
+
+    #[cfg(feature = "ssl")]
+    #[tokio::test]
+    async fn test_accept_tls_skips_failed_handshake() {
+        use std::time::Duration;
+
+        use openssl::ssl::{SslAcceptor, SslMethod};
+        use tokio::io::AsyncWriteExt;
+
+        let acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())
+            .unwrap()
+            .build();
+        let listener = DoipServerBuilder::new()
+            .bind_addr("127.0.0.1:0".parse().unwrap())
+            .tls_acceptor(acceptor)
+            .build()
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Not a TLS client hello, the handshake fails
+        let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        client.write_all(&[0x02, 0xfd, 0x00, 0x07]).await.unwrap();
+
+        let res = tokio::time::timeout(Duration::from_millis(200), listener.accept_tls()).await;
+        assert!(res.is_err());
+    }
+}
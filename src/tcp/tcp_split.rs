@@ -1,24 +1,132 @@
+use std::{
+    collections::VecDeque,
+    fmt, io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
 use doip_codec::{DoipCodec, Error as CodecError};
-use doip_definitions::{builder::DoipMessageBuilder, message::DoipMessage, payload::DoipPayload};
-use futures::{SinkExt, StreamExt};
-use tokio::io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
-use tokio_util::codec::{FramedRead, FramedWrite};
+use doip_definitions::{
+    header::PayloadType,
+    message::DoipMessage,
+    payload::{AliveCheckRequest, DoipPayload},
+};
+use futures::{Sink, SinkExt, Stream};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf},
+    sync::{Notify, OwnedSemaphorePermit},
+    task::JoinHandle,
+};
+use tokio_util::{
+    bytes::BytesMut,
+    codec::{Encoder, Framed, FramedParts, FramedRead, FramedWrite},
+};
+
+use crate::{
+    error::{SocketReadError, SocketSendError},
+    pacing::{SendGap, TokenBucket},
+};
+
+use super::{
+    codec_to_io,
+    frame_codec::{Frame, FrameCodec},
+    next_frame,
+    outbound_queue::{OutboundQueue, Shared},
+    oversized_error, truncated_frame, DoipTcpPayload, SocketConfig, TcpStream,
+};
+
+/// Signal shared by the halves of a split stream, raised on the first error
+#[derive(Debug, Default)]
+pub(crate) struct ErrorSignal {
+    raised: AtomicBool,
+    notify: Notify,
+}
 
-use crate::error::SocketSendError;
+impl ErrorSignal {
+    fn raise(&self) {
+        if !self.raised.swap(true, Ordering::SeqCst) {
+            self.notify.notify_waiters();
+        }
+    }
+
+    async fn notified(&self) {
+        let notified = self.notify.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
 
-use super::SocketConfig;
+        if !self.raised.load(Ordering::SeqCst) {
+            notified.await;
+        }
+    }
+}
+
+/// Yields each frame read off the read half
+///
+/// Frames queued by `read_of_type` are yielded first. The read timeout only
+/// applies to `read`, frames are checked against the protocol version as in
+/// `read` if strict version checking is enabled.
+impl<T> Stream for TcpStreamReadHalf<T>
+where
+    T: AsyncRead + AsyncWrite,
+{
+    type Item = Result<DoipMessage, CodecError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        if let Some(msg) = this.queue.pop_front() {
+            return Poll::Ready(Some(Ok(msg)));
+        }
+
+        let res = ready!(Pin::new(&mut this.io).poll_next(cx));
+        Poll::Ready(res.map(|res| this.frame_result(res)))
+    }
+}
+
+/// Peer and local address of the socket a stream was split from, if known
+pub(crate) type SocketAddrs = (Option<SocketAddr>, Option<SocketAddr>);
+
+/// Reads the `SocketAddrs` of a transport, kept by a stream so a split can
+/// report the addresses of transports which have them
+pub(crate) type AddrsOf<T> = fn(&T) -> SocketAddrs;
+
+/// `AddrsOf` for transports without socket addresses
+pub(crate) fn no_addrs<T>(_: &T) -> SocketAddrs {
+    (None, None)
+}
+
+fn known_addr(addr: Option<SocketAddr>) -> io::Result<SocketAddr> {
+    addr.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Transport has no socket address",
+        )
+    })
+}
 
 /// Simple implementation of a TCP Stream Read Half
 ///
 /// Allows for the passing of the read half being passed into a different thread
-/// seperate to the write half. Will be dropped if the Write Half is dropped.
+/// seperate to the write half. Keeps reading after the write half is shut down
+/// or dropped, so responses can be drained after a half-close.
 pub struct TcpStreamReadHalf<T>
 where
     T: AsyncRead + AsyncWrite,
 {
-    io: FramedRead<ReadHalf<T>, DoipCodec>,
-    #[allow(dead_code)]
+    io: FramedRead<ReadHalf<T>, FrameCodec>,
     config: SocketConfig,
+    queue: VecDeque<DoipMessage>,
+    #[allow(dead_code)]
+    permit: Option<Arc<OwnedSemaphorePermit>>,
+    error: Arc<ErrorSignal>,
+    addrs: SocketAddrs,
+    addrs_of: AddrsOf<T>,
 }
 
 impl<T> TcpStreamReadHalf<T>
@@ -27,19 +135,236 @@ where
 {
     /// Creates a new TCP Stream Read Half from an existing Tokio TCP Stream and
     /// config
+    ///
+    /// The `DoipCodec` is replaced by one applying the max message size of the
+    /// config, bytes already buffered are kept.
     pub fn new(io: FramedRead<ReadHalf<T>, DoipCodec>, config: Option<SocketConfig>) -> Self {
+        let config = config.unwrap_or_default();
+        let io = io.map_decoder(|_| FrameCodec::from_config(&config));
+
+        Self::from_framed(io, config)
+    }
+
+    pub(crate) fn from_framed(
+        io: FramedRead<ReadHalf<T>, FrameCodec>,
+        config: SocketConfig,
+    ) -> Self {
         TcpStreamReadHalf {
             io,
-            config: config.unwrap_or_default(),
+            config,
+            queue: VecDeque::new(),
+            permit: None,
+            error: Arc::default(),
+            addrs: (None, None),
+            addrs_of: no_addrs,
+        }
+    }
+
+    pub(crate) fn with_permit(mut self, permit: Option<Arc<OwnedSemaphorePermit>>) -> Self {
+        self.permit = permit;
+        self
+    }
+
+    pub(crate) fn with_queue(mut self, queue: VecDeque<DoipMessage>) -> Self {
+        self.queue = queue;
+        self
+    }
+
+    pub(crate) fn with_error_signal(mut self, error: Arc<ErrorSignal>) -> Self {
+        self.error = error;
+        self
+    }
+
+    pub(crate) fn with_addrs(mut self, addrs: SocketAddrs, addrs_of: AddrsOf<T>) -> Self {
+        self.addrs = addrs;
+        self.addrs_of = addrs_of;
+        self
+    }
+
+    /// The remote address of the connection the half was split from
+    ///
+    /// Returns `io::ErrorKind::Unsupported` if the transport is not a TCP
+    /// socket.
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        known_addr(self.addrs.0)
+    }
+
+    /// The local address of the connection the half was split from
+    ///
+    /// Returns `io::ErrorKind::Unsupported` if the transport is not a TCP
+    /// socket.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        known_addr(self.addrs.1)
+    }
+
+    /// Resolves once either half of the split stream has returned an error
+    ///
+    /// Lets the task driving one half stop when the other half fails, such as
+    /// on a connection reset. Resolves immediately if an error already occurred.
+    pub async fn error_notified(&self) {
+        self.error.notified().await
+    }
+
+    /// The OEM specific bytes of the last Routing Activation Request or
+    /// Response read off the half, as `TcpStream::received_oem_specific`
+    pub fn received_oem_specific(&self) -> Option<[u8; 4]> {
+        self.io.decoder().received_oem_specific()
+    }
+
+    fn raise_error(&self, err: CodecError) -> CodecError {
+        // A read timeout leaves the stream usable, so is not signalled
+        if matches!(&err, CodecError::IoError(err) if err.kind() == io::ErrorKind::TimedOut) {
+            return err;
+        }
+
+        let err = truncated_frame(err, self.io.read_buffer().len());
+        self.error.raise();
+        err
+    }
+
+    /// Maps a decoded frame to a message, signalling fatal errors
+    ///
+    /// A frame over the max message size has its payload discarded and is
+    /// returned as an `io::ErrorKind::InvalidData` error without signalling the
+    /// write half, as the stream remains usable. The same holds for a frame
+    /// failing the strict version check.
+    fn frame_result(&self, res: Result<Frame, CodecError>) -> Result<DoipMessage, CodecError> {
+        match res {
+            Ok(Frame::Message(msg)) => self.config.check_version(&msg).map(|()| msg),
+            Ok(Frame::Oversized(payload_length)) => Err(oversized_error(payload_length)),
+            Err(err) => Err(self.raise_error(err)),
         }
     }
 
     /// Read from the stream
+    ///
+    /// If a max message size is set, frames over it are discarded and returned
+    /// as an `io::ErrorKind::InvalidData` error. The read half cannot send, so
+    /// no Generic Nack is sent even if an oversize nack code is set, answer
+    /// through the write half if one is required.
+    ///
+    /// If strict version checking is enabled, frames of another protocol
+    /// version are discarded and returned as a `SocketError::VersionMismatch`
+    /// without signalling the write half. Frames queued by `read_of_type` are
+    /// returned first, in the order they arrived.
     pub async fn read(&mut self) -> Option<Result<DoipMessage, CodecError>> {
-        self.io.next().await
+        if let Some(msg) = self.queue.pop_front() {
+            return Some(Ok(msg));
+        }
+
+        let res = next_frame(&mut self.io, self.config.read_timeout).await?;
+        Some(self.frame_result(res))
+    }
+
+    /// Read from the stream, as `read` does, with a structured error
+    ///
+    /// A clean close is returned as `SocketReadError::Closed`.
+    pub async fn read_message(&mut self) -> Result<DoipMessage, SocketReadError> {
+        SocketReadError::from_read(self.read().await)
+    }
+
+    /// Read until a frame of the given payload type arrives
+    ///
+    /// Frames of any other payload type are queued and returned by subsequent
+    /// calls to `read`, so no frames are lost. Errors are returned as in
+    /// `read`, including those of the strict version check.
+    pub async fn read_of_type(
+        &mut self,
+        payload_type: PayloadType,
+    ) -> Option<Result<DoipMessage, CodecError>> {
+        self.read_filtered(|msg| msg.header.payload_type == payload_type)
+            .await
+    }
+
+    /// Read until a frame satisfying `pred` arrives
+    ///
+    /// Queued frames are checked first, frames not satisfying `pred` are
+    /// queued and returned by subsequent calls to `read`, so no frames are
+    /// lost.
+    pub async fn read_filtered(
+        &mut self,
+        pred: impl Fn(&DoipMessage) -> bool,
+    ) -> Option<Result<DoipMessage, CodecError>> {
+        if let Some(pos) = self.queue.iter().position(&pred) {
+            return self.queue.remove(pos).map(Ok);
+        }
+
+        loop {
+            let res = next_frame(&mut self.io, self.config.read_timeout).await?;
+
+            match self.frame_result(res) {
+                Ok(msg) if pred(&msg) => return Some(Ok(msg)),
+                Ok(msg) => self.queue.push_back(msg),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+impl<T> TcpStreamReadHalf<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Join the read half with the write half it was split from
+    ///
+    /// No data is lost, frames queued by `read_of_type`, bytes of partially
+    /// received frames and unflushed bytes of the write half are carried over
+    /// to the TCP Stream, as is the decode state of the read half such as the
+    /// remainder of an oversized frame being discarded. The config of the read
+    /// half is kept. Returns both halves in a `ReuniteError` if they were split
+    /// from different streams.
+    pub fn reunite(self, other: TcpStreamWriteHalf<T>) -> Result<TcpStream<T>, ReuniteError<T>> {
+        if !self.io.get_ref().is_pair_of(other.io.get_ref()) {
+            return Err(ReuniteError(Box::new(self), Box::new(other)));
+        }
+
+        let read = self.io.into_parts();
+        let write = other.io.into_parts();
+
+        let mut parts = FramedParts::new(read.io.unsplit(write.io), read.codec);
+        parts.read_buf = read.read_buf;
+        parts.write_buf = write.write_buf;
+
+        Ok(
+            TcpStream::from_framed(Framed::from_parts(parts), self.config, self.permit)
+                .with_queue(self.queue)
+                .with_addrs_of(self.addrs_of)
+                .with_dry_run_bytes(other.dry_run_bytes),
+        )
+    }
+}
+
+/// Error returned by `TcpStreamReadHalf::reunite` when the halves were split
+/// from different TCP Streams
+///
+/// Holds both halves so they can still be used.
+pub struct ReuniteError<T>(
+    pub Box<TcpStreamReadHalf<T>>,
+    pub Box<TcpStreamWriteHalf<T>>,
+)
+where
+    T: AsyncRead + AsyncWrite;
+
+impl<T> fmt::Debug for ReuniteError<T>
+where
+    T: AsyncRead + AsyncWrite,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ReuniteError").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Display for ReuniteError<T>
+where
+    T: AsyncRead + AsyncWrite,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Halves were not split from the same TCP Stream")
     }
 }
 
+impl<T> std::error::Error for ReuniteError<T> where T: AsyncRead + AsyncWrite {}
+
 /// Simple implementation of a TCP Stream Write Half
 ///
 /// Can be used to write messages to the sink. If dropped this will close the
@@ -48,33 +373,842 @@ pub struct TcpStreamWriteHalf<T>
 where
     T: AsyncRead + AsyncWrite,
 {
-    io: FramedWrite<WriteHalf<T>, DoipCodec>,
+    io: FramedWrite<WriteHalf<T>, FrameCodec>,
     config: SocketConfig,
+    #[allow(dead_code)]
+    permit: Option<Arc<OwnedSemaphorePermit>>,
+    send_limiter: Option<TokenBucket>,
+    send_gap: Option<SendGap>,
+    error: Arc<ErrorSignal>,
+    addrs: SocketAddrs,
+    dry_run_bytes: BytesMut,
 }
 
 impl<T> TcpStreamWriteHalf<T>
 where
     T: AsyncRead + AsyncWrite,
 {
-    /// Creates a new TCP Stream Read Half from an existing Tokio TCP Stream and
-    /// config
+    /// Creates a new TCP Stream Write Half from an existing Tokio TCP Stream
+    /// and config
+    ///
+    /// The `DoipCodec` is replaced by one appending the OEM specific bytes of
+    /// the config, bytes already buffered are kept.
     pub fn new(io: FramedWrite<WriteHalf<T>, DoipCodec>, config: Option<SocketConfig>) -> Self {
+        let config = config.unwrap_or_default();
+        let io = io.map_encoder(|_| FrameCodec::from_config(&config));
+
+        Self::from_framed(io, config)
+    }
+
+    pub(crate) fn from_framed(
+        io: FramedWrite<WriteHalf<T>, FrameCodec>,
+        config: SocketConfig,
+    ) -> Self {
         TcpStreamWriteHalf {
             io,
-            config: config.unwrap_or_default(),
+            send_limiter: config.send_rate_limit.map(TokenBucket::new),
+            send_gap: config.min_send_gap.map(SendGap::new),
+            config,
+            permit: None,
+            error: Arc::default(),
+            addrs: (None, None),
+            dry_run_bytes: BytesMut::new(),
         }
     }
 
+    pub(crate) fn with_permit(mut self, permit: Option<Arc<OwnedSemaphorePermit>>) -> Self {
+        self.permit = permit;
+        self
+    }
+
+    pub(crate) fn with_error_signal(mut self, error: Arc<ErrorSignal>) -> Self {
+        self.error = error;
+        self
+    }
+
+    pub(crate) fn with_addrs(mut self, addrs: SocketAddrs) -> Self {
+        self.addrs = addrs;
+        self
+    }
+
+    pub(crate) fn with_dry_run_bytes(mut self, dry_run_bytes: BytesMut) -> Self {
+        self.dry_run_bytes = dry_run_bytes;
+        self
+    }
+
+    /// The remote address of the connection the half was split from
+    ///
+    /// Returns `io::ErrorKind::Unsupported` if the transport is not a TCP
+    /// socket.
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        known_addr(self.addrs.0)
+    }
+
+    /// The local address of the connection the half was split from
+    ///
+    /// Returns `io::ErrorKind::Unsupported` if the transport is not a TCP
+    /// socket.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        known_addr(self.addrs.1)
+    }
+
+    /// Resolves once either half of the split stream has returned an error
+    ///
+    /// The write half equivalent of `TcpStreamReadHalf::error_notified`.
+    pub async fn error_notified(&self) {
+        self.error.notified().await
+    }
+
     /// Send a message to the sink
+    ///
+    /// If a send rate limit or minimum send gap is configured this waits until
+    /// the frame may be sent. In dry run mode the frame is encoded into the
+    /// bytes returned by `take_dry_run_bytes` instead of being sent.
     pub async fn send(&mut self, payload: DoipPayload) -> Result<(), SocketSendError> {
-        let msg = DoipMessageBuilder::new()
-            .protocol_version(self.config.protocol_version)
-            .payload(payload)
-            .build();
+        let msg = self.config.build_message(payload)?;
+
+        if self.config.dry_run {
+            return self.start_frame(msg);
+        }
+
+        if let Some(limiter) = &mut self.send_limiter {
+            limiter.acquire().await;
+        }
+
+        if let Some(gap) = &self.send_gap {
+            gap.wait().await;
+        }
+
+        let res = self.io.send(msg).await;
+
+        if let Some(gap) = &mut self.send_gap {
+            gap.mark();
+        }
+
+        self.signal_send_error(res)
+    }
+
+    /// Buffer a DoIP frame without flushing it
+    ///
+    /// As `TcpStream::feed`, frames still buffered when the write half is
+    /// dropped are lost. Send errors are signalled to the read half.
+    pub async fn feed<A: DoipTcpPayload>(&mut self, payload: A) -> Result<(), SocketSendError> {
+        SinkExt::feed(self, payload.into_payload()).await
+    }
+
+    /// Write any buffered frames to the OS
+    pub async fn flush(&mut self) -> Result<(), SocketSendError> {
+        SinkExt::<DoipPayload>::flush(self).await
+    }
+
+    /// Flush any buffered frames then shut down the write side of the socket
+    ///
+    /// Only the write direction is shut down, sending a FIN, so the peer reads
+    /// EOF once this returns while the read half keeps receiving frames until
+    /// the peer closes its side.
+    pub async fn shutdown(&mut self) -> io::Result<()> {
+        SinkExt::<DoipMessage>::flush(&mut self.io)
+            .await
+            .map_err(codec_to_io)?;
+
+        self.io.get_mut().shutdown().await
+    }
+
+    /// Take the bytes encoded in dry run mode since the last call
+    ///
+    /// Bytes encoded by the TCP Stream before it was split are included.
+    pub fn take_dry_run_bytes(&mut self) -> Vec<u8> {
+        self.dry_run_bytes.split().to_vec()
+    }
+
+    /// Buffer a frame on the sink, or in dry run mode encode it into the bytes
+    /// returned by `take_dry_run_bytes` instead
+    fn start_frame(&mut self, msg: DoipMessage) -> Result<(), SocketSendError> {
+        if self.config.dry_run {
+            return self
+                .io
+                .encoder_mut()
+                .encode(msg, &mut self.dry_run_bytes)
+                .map_err(SocketSendError::from);
+        }
+
+        let res = Pin::new(&mut self.io).start_send(msg);
+        self.signal_send_error(res)
+    }
+
+    fn signal_send_error(&self, res: Result<(), CodecError>) -> Result<(), SocketSendError> {
+        res.map_err(|err| {
+            self.error.raise();
+            SocketSendError::from(err)
+        })
+    }
+
+    /// Spawn a task sending an Alive Check Request every `interval`
+    ///
+    /// The task is spawned on the runtime set in the config, or the ambient
+    /// runtime if none is set. It runs until a send fails, returning the error.
+    pub fn spawn_heartbeat(mut self, interval: Duration) -> JoinHandle<SocketSendError>
+    where
+        T: Send + 'static,
+    {
+        let config = self.config.clone();
+
+        config.spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+
+            loop {
+                ticker.tick().await;
+
+                let payload = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+                if let Err(err) = self.send(payload).await {
+                    return err;
+                }
+            }
+        })
+    }
+
+    /// Spawn a task writing the frames fed to the returned `OutboundQueue`
+    ///
+    /// The queue capacity and overflow policy are taken from the config, and
+    /// the task is spawned on the configured runtime. The task ends once every
+    /// clone of the queue is dropped and the queue is drained, or a send fails,
+    /// after which enqueueing returns `EnqueueError::Closed`.
+    pub fn spawn_writer(mut self) -> (OutboundQueue, JoinHandle<Result<(), SocketSendError>>)
+    where
+        T: Send + 'static,
+    {
+        let shared = Shared::new(
+            self.config.outbound_queue_capacity,
+            self.config.overflow_policy,
+        );
+        let queue = OutboundQueue::new(shared.clone());
+
+        let writer = self.config.clone().spawn(async move {
+            while let Some(payload) = shared.next_frame().await {
+                if let Err(err) = self.send(payload).await {
+                    shared.close_writer();
+                    return Err(err);
+                }
+            }
+
+            Ok(())
+        });
 
-        match self.io.send(msg).await {
-            Ok(_) => Ok(()),
-            Err(err) => Err(SocketSendError::EncodeError(err)),
+        (queue, writer)
+    }
+}
+
+/// Sends each payload as a DoIP frame using the configured protocol version
+///
+/// Payloads are checked as in `send`, but the send rate limit and minimum send
+/// gap are not applied. Send errors are signalled to the read half.
+impl<T> Sink<DoipPayload> for TcpStreamWriteHalf<T>
+where
+    T: AsyncRead + AsyncWrite,
+{
+    type Error = SocketSendError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        let res = ready!(Pin::new(&mut this.io).poll_ready(cx));
+        Poll::Ready(this.signal_send_error(res))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: DoipPayload) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        let msg = this.config.build_message(item)?;
+        this.start_frame(msg)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        let res = ready!(Pin::new(&mut this.io).poll_flush(cx));
+        Poll::Ready(this.signal_send_error(res))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        let res = ready!(Pin::new(&mut this.io).poll_close(cx));
+        Poll::Ready(this.signal_send_error(res))
+    }
+}
+
+#[cfg(test)]
+mod test_tcp_split {
+    use doip_definitions::{
+        header::PayloadType,
+        message::DoipMessage,
+        payload::{
+            ActivationCode, AliveCheckRequest, DiagnosticMessage, DoipPayload,
+            RoutingActivationResponse, VehicleIdentificationRequest,
+        },
+    };
+
+    use futures::StreamExt;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use crate::{
+        error::{SocketError, SocketSendError},
+        tcp::{OverflowPolicy, ReuniteError, TcpStream},
+        SocketConfig,
+    };
+
+    #[tokio::test]
+    async fn test_write_half_dry_run() {
+        const ALIVE_CHECK: [u8; 8] = [0x02, 0xfd, 0x00, 0x07, 0x00, 0x00, 0x00, 0x00];
+
+        let (io, mut peer) = tokio::io::duplex(64);
+        let mut config = SocketConfig::default();
+        config.set_dry_run(true);
+        let (_read, mut write) = TcpStream::from_io(io).with_config(config).into_split();
+
+        write
+            .send(DoipPayload::AliveCheckRequest(AliveCheckRequest {}))
+            .await
+            .unwrap();
+        write.feed(AliveCheckRequest {}).await.unwrap();
+        write.flush().await.unwrap();
+
+        assert_eq!(write.take_dry_run_bytes(), ALIVE_CHECK.repeat(2));
+
+        let mut buf = [0u8; 8];
+        let read = tokio::time::timeout(Duration::from_millis(50), peer.read(&mut buf)).await;
+        assert!(read.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_write_half_rejects_udp_payload() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let vehicle_id = DoipPayload::VehicleIdentificationRequest(VehicleIdentificationRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (_read, mut write) = client.into_split();
+
+        let res = write.send(vehicle_id.clone()).await;
+        assert!(matches!(res, Err(SocketSendError::InvalidTcpPayload)));
+
+        let res = futures::SinkExt::send(&mut write, vehicle_id).await;
+        assert!(matches!(res, Err(SocketSendError::InvalidTcpPayload)));
+    }
+
+    #[tokio::test]
+    async fn test_write_half_sink() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        let (_read, write) = client.into_split();
+        futures::stream::iter(vec![alive_check.clone(); 2])
+            .map(Ok)
+            .forward(write)
+            .await
+            .unwrap();
+
+        for _ in 0..2 {
+            assert_eq!(server.read().await.unwrap().unwrap().payload, alive_check);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_half_shutdown() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        let (mut read, mut write) = client.into_split();
+        write.feed(AliveCheckRequest {}).await.unwrap();
+        write.shutdown().await.unwrap();
+
+        assert_eq!(server.read().await.unwrap().unwrap().payload, alive_check);
+        assert!(server.read().await.is_none());
+
+        server.send(alive_check.clone()).await.unwrap();
+        assert_eq!(read.read().await.unwrap().unwrap().payload, alive_check);
+    }
+
+    #[tokio::test]
+    async fn test_read_half_after_write_half_dropped() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let request = DoipPayload::DiagnosticMessage(DiagnosticMessage {
+            source_address: [0x0e, 0x80],
+            target_address: [0x14, 0x11],
+            message: vec![0x22, 0xf1, 0x90],
+        });
+        let response = DoipPayload::DiagnosticMessage(DiagnosticMessage {
+            source_address: [0x14, 0x11],
+            target_address: [0x0e, 0x80],
+            message: vec![0x62, 0xf1, 0x90],
+        });
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        let (mut read, mut write) = client.into_split();
+        write.send(request.clone()).await.unwrap();
+        write.shutdown().await.unwrap();
+        drop(write);
+
+        assert_eq!(server.read().await.unwrap().unwrap().payload, request);
+        assert!(server.read().await.is_none());
+        server.send(response.clone()).await.unwrap();
+        server.shutdown().await.unwrap();
+
+        assert_eq!(read.read().await.unwrap().unwrap().payload, response);
+        assert!(read.read().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_read_half_stream() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let (read, _write) = TcpStream::new(socket).into_split();
+
+        for _ in 0..2 {
+            client.send(alive_check.clone()).await.unwrap();
+        }
+
+        let msgs: Vec<_> = read.take(2).collect().await;
+        assert!(msgs
+            .iter()
+            .all(|res| res.as_ref().unwrap().payload == alive_check));
+    }
+
+    #[tokio::test]
+    async fn test_split_addrs() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (_socket, peer) = listener.accept().await.unwrap();
+
+        assert_eq!(client.local_addr().unwrap(), peer);
+        assert_eq!(client.peer_addr().unwrap(), listener.local_addr().unwrap());
+
+        let (read, write) = client.into_split();
+        assert_eq!(read.local_addr().unwrap(), peer);
+        assert_eq!(write.peer_addr().unwrap(), listener.local_addr().unwrap());
+
+        let (read, write) = read.reunite(write).unwrap().into_split();
+        assert_eq!(read.local_addr().unwrap(), peer);
+        assert_eq!(write.peer_addr().unwrap(), listener.local_addr().unwrap());
+
+        let (io, _peer) = tokio::io::duplex(64);
+        let (read, _write) = TcpStream::from_io(io).into_split();
+        assert_eq!(
+            read.peer_addr().unwrap_err().kind(),
+            std::io::ErrorKind::Unsupported
+        );
+
+        // A borrowed transport can be split too
+        let (mut io, _peer) = tokio::io::duplex(64);
+        let (read, _write) = TcpStream::from_io(&mut io).into_split();
+        assert_eq!(
+            read.local_addr().unwrap_err().kind(),
+            std::io::ErrorKind::Unsupported
+        );
+    }
+
+    #[tokio::test]
+    async fn test_split_keeps_partial_frame() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+        let bytes = [0x02, 0xfd, 0x00, 0x07, 0x00, 0x00, 0x00, 0x00];
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = tokio::net::TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+
+        let mut config = SocketConfig::default();
+        config.set_read_timeout(Some(Duration::from_millis(50)));
+        let mut stream = TcpStream::new(socket).with_config(config);
+
+        client.write_all(&bytes[..1]).await.unwrap();
+        assert!(stream.read().await.unwrap().is_err());
+        assert_eq!(stream.read_buffer().len(), 1);
+
+        let (mut read, _write) = stream.into_split();
+
+        client.write_all(&bytes[1..]).await.unwrap();
+        assert_eq!(read.read().await.unwrap().unwrap().payload, alive_check);
+    }
+
+    #[tokio::test]
+    async fn test_split_oversized_frame() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        const OVERSIZED_HEADER: [u8; 8] = [0x02, 0xfd, 0x80, 0x01, 0x00, 0x00, 0x00, 0x40];
+        const ALIVE_CHECK: [u8; 8] = [0x02, 0xfd, 0x00, 0x07, 0x00, 0x00, 0x00, 0x00];
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = tokio::net::TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+
+        let mut config = SocketConfig::default();
+        config.set_max_message_size(Some(16));
+        let mut stream = TcpStream::new(socket).with_config(config);
+
+        // Split while the body of an oversized frame is still being discarded
+        client.write_all(&OVERSIZED_HEADER).await.unwrap();
+        client.write_all(&[0xff; 32]).await.unwrap();
+        let err = stream.read().await.unwrap().unwrap_err();
+        assert!(matches!(err, doip_codec::Error::IoError(err)
+            if err.kind() == std::io::ErrorKind::InvalidData));
+
+        let (mut read, _write) = stream.into_split();
+
+        client.write_all(&[0xff; 32]).await.unwrap();
+        client.write_all(&ALIVE_CHECK).await.unwrap();
+        assert_eq!(read.read().await.unwrap().unwrap().payload, alive_check);
+
+        // An oversized frame arriving on the read half is not buffered
+        client.write_all(&OVERSIZED_HEADER).await.unwrap();
+        client.write_all(&[0xff; 64]).await.unwrap();
+        client.write_all(&ALIVE_CHECK).await.unwrap();
+        let err = read.read().await.unwrap().unwrap_err();
+        assert!(matches!(err, doip_codec::Error::IoError(err)
+            if err.kind() == std::io::ErrorKind::InvalidData));
+        assert_eq!(read.read().await.unwrap().unwrap().payload, alive_check);
+    }
+
+    #[tokio::test]
+    async fn test_oversize_length_header_split() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        const MAX_LENGTH_HEADER: [u8; 8] = [0x02, 0xfd, 0x80, 0x01, 0xff, 0xff, 0xff, 0xff];
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = tokio::net::TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+
+        let mut config = SocketConfig::default();
+        config.set_max_message_size(Some(4096));
+        config.set_read_timeout(Some(Duration::from_millis(50)));
+        let (mut read, write) = TcpStream::new(socket).with_config(config).into_split();
+
+        client.write_all(&MAX_LENGTH_HEADER).await.unwrap();
+        client.write_all(&[0x00; 1024]).await.unwrap();
+
+        let err = match read.read().await.unwrap() {
+            Err(doip_codec::Error::IoError(err)) => err,
+            other => panic!("Expected oversized frame error, got {other:?}"),
+        };
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(read.io.read_buffer().len() < 4096);
+
+        // The reunited stream keeps discarding the body of the frame
+        let mut stream = read.reunite(write).unwrap();
+        client.write_all(&[0x00; 8192]).await.unwrap();
+
+        let err = match stream.read().await.unwrap() {
+            Err(doip_codec::Error::IoError(err)) => err,
+            other => panic!("Expected read timeout, got {other:?}"),
+        };
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        assert!(stream.read_buffer().len() < 4096);
+    }
+
+    #[tokio::test]
+    async fn test_reunite_keeps_partial_frame() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+        let bytes = [0x02, 0xfd, 0x00, 0x07, 0x00, 0x00, 0x00, 0x00];
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = tokio::net::TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+
+        let mut config = SocketConfig::default();
+        config.set_read_timeout(Some(Duration::from_millis(50)));
+        let (mut read, write) = TcpStream::new(socket).with_config(config).into_split();
+
+        client.write_all(&bytes[..5]).await.unwrap();
+        assert!(read.read().await.unwrap().is_err());
+
+        let mut stream = read.reunite(write).unwrap();
+
+        client.write_all(&bytes[5..]).await.unwrap();
+        assert_eq!(stream.read().await.unwrap().unwrap().payload, alive_check);
+    }
+
+    #[tokio::test]
+    async fn test_reunite_mismatched() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (read1, write1) = TcpStream::connect(addr).await.unwrap().into_split();
+        let (socket1, _) = listener.accept().await.unwrap();
+        let (read2, write2) = TcpStream::connect(addr).await.unwrap().into_split();
+        let (_socket2, _) = listener.accept().await.unwrap();
+
+        let ReuniteError(read1, write2) = TcpStream::reunite(read1, write2).err().unwrap();
+        assert!(TcpStream::reunite(read2, *write2).is_ok());
+
+        let mut stream = TcpStream::reunite(*read1, write1).unwrap();
+        let mut server = TcpStream::new(socket1);
+        stream.send(alive_check.clone()).await.unwrap();
+        assert_eq!(server.read().await.unwrap().unwrap().payload, alive_check);
+    }
+
+    #[tokio::test]
+    async fn test_read_half_timeout() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+
+        let mut config = SocketConfig::default();
+        config.set_read_timeout(Some(Duration::from_millis(50)));
+        let (mut read, _write) = TcpStream::new(socket).with_config(config).into_split();
+
+        let doip_codec::Error::IoError(err) = read.read().await.unwrap().unwrap_err() else {
+            panic!("expected an io error");
+        };
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        assert!(matches!(
+            err.get_ref().and_then(|err| err.downcast_ref()),
+            Some(SocketError::ReadTimeout)
+        ));
+
+        client.send(alive_check.clone()).await.unwrap();
+        assert_eq!(read.read().await.unwrap().unwrap().payload, alive_check);
+    }
+
+    #[tokio::test]
+    async fn test_read_of_type() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut read, _write) = client.into_split();
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+        let diagnostic = DoipPayload::DiagnosticMessage(DiagnosticMessage {
+            source_address: [0x14, 0x11],
+            target_address: [0x0e, 0x80],
+            message: vec![0x50, 0x01],
+        });
+        let routing_activation_res =
+            DoipPayload::RoutingActivationResponse(RoutingActivationResponse {
+                logical_address: [0x0e, 0x80],
+                source_address: [0x14, 0x11],
+                activation_code: ActivationCode::SuccessfullyActivated,
+                buffer: [0, 0, 0, 0],
+            });
+
+        server.send(alive_check.clone()).await.unwrap();
+        server.send(diagnostic.clone()).await.unwrap();
+        server.send(routing_activation_res.clone()).await.unwrap();
+
+        let res = read
+            .read_of_type(PayloadType::RoutingActivationResponse)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(res.payload, routing_activation_res);
+
+        let first = read.read().await.unwrap().unwrap();
+        let second = read.read().await.unwrap().unwrap();
+        assert_eq!(first.payload, alive_check);
+        assert_eq!(second.payload, diagnostic);
+    }
+
+    #[tokio::test]
+    async fn test_read_filtered() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut read, _write) = client.into_split();
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        let diagnostic = |source_address| {
+            DoipPayload::DiagnosticMessage(DiagnosticMessage {
+                source_address,
+                target_address: [0x0e, 0x80],
+                message: vec![0x50, 0x01],
+            })
+        };
+
+        server.send(diagnostic([0x14, 0x11])).await.unwrap();
+        server.send(diagnostic([0x14, 0x12])).await.unwrap();
+        server.send(diagnostic([0x14, 0x12])).await.unwrap();
+
+        let from_ecu = |msg: &DoipMessage| matches!(&msg.payload, DoipPayload::DiagnosticMessage(diag) if diag.source_address == [0x14, 0x12]);
+
+        for _ in 0..2 {
+            let res = read.read_filtered(from_ecu).await.unwrap().unwrap();
+            assert_eq!(res.payload, diagnostic([0x14, 0x12]));
         }
+
+        let other = read.read().await.unwrap().unwrap();
+        assert_eq!(other.payload, diagnostic([0x14, 0x11]));
+    }
+
+    #[test]
+    fn test_spawn_heartbeat_on_handle() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+
+        let heartbeat_rt = tokio::runtime::Runtime::new().unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        let mut config = SocketConfig::default();
+        config.set_runtime(Some(heartbeat_rt.handle().clone()));
+
+        let (write, mut server) = rt.block_on(async {
+            let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+
+            let client = TcpStream::connect(listener.local_addr().unwrap())
+                .await
+                .unwrap()
+                .with_config(config);
+            let (_read, write) = client.into_split();
+
+            let (socket, _) = listener.accept().await.unwrap();
+            (write, TcpStream::new(socket))
+        });
+
+        // Spawned outside of any runtime context, so only succeeds when the
+        // configured handle is used.
+        let heartbeat = write.spawn_heartbeat(Duration::from_millis(10));
+
+        rt.block_on(async {
+            for _ in 0..2 {
+                let msg = server.read().await.unwrap().unwrap();
+                assert_eq!(
+                    msg.payload,
+                    DoipPayload::AliveCheckRequest(AliveCheckRequest {})
+                );
+            }
+        });
+
+        heartbeat.abort();
+    }
+
+    #[tokio::test]
+    async fn test_error_notified_on_reset() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut read, write) = client.into_split();
+
+        let (socket, _) = listener.accept().await.unwrap();
+        socket.set_zero_linger().unwrap();
+        drop(socket);
+
+        let watcher = tokio::spawn(async move { write.error_notified().await });
+
+        assert!(read.read().await.unwrap().is_err());
+        tokio::time::timeout(Duration::from_secs(1), watcher)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_spawn_writer_drop_oldest() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+
+        let mut config = SocketConfig::default();
+        config.set_outbound_queue(1, OverflowPolicy::DropOldest);
+
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap()
+            .with_config(config);
+        let (read, write) = client.into_split();
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        // The writer cannot run until this task yields, so every frame but the
+        // newest is dropped from the queue.
+        let (queue, writer) = write.spawn_writer();
+        for i in 0..3 {
+            queue
+                .enqueue(DoipPayload::DiagnosticMessage(DiagnosticMessage {
+                    source_address: [0x0e, 0x80],
+                    target_address: [0x14, 0x11],
+                    message: vec![i],
+                }))
+                .await
+                .unwrap();
+        }
+        assert_eq!(queue.len(), 1);
+
+        drop(queue);
+        writer.await.unwrap().unwrap();
+        drop(read);
+
+        let msg = server.read().await.unwrap().unwrap();
+        assert_eq!(
+            msg.payload,
+            DoipPayload::DiagnosticMessage(DiagnosticMessage {
+                source_address: [0x0e, 0x80],
+                target_address: [0x14, 0x11],
+                message: vec![2],
+            })
+        );
+        assert!(server.read().await.is_none());
     }
 }
@@ -1,40 +1,153 @@
-use std::io::{self};
+use std::{
+    any::Any,
+    collections::VecDeque,
+    io::{self},
+    net::SocketAddr,
+    ops::Deref,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll, Waker},
+    time::{Duration, Instant},
+};
 
-use doip_codec::{DoipCodec, Error as CodecError};
+use doip_codec::Error as CodecError;
 use doip_definitions::{
-    builder::DoipMessageBuilder, header::ProtocolVersion, message::DoipMessage,
-    payload::DoipPayload,
+    header::{DoipHeader, PayloadType, ProtocolVersion},
+    message::DoipMessage,
+    payload::{
+        ActivationCode, ActivationType, AliveCheckResponse, DiagnosticAckCode, DiagnosticMessage,
+        DiagnosticMessageAck, DiagnosticMessageNack, DiagnosticNackCode, DoipPayload, GenericNack,
+        NackCode, RoutingActivationRequest, RoutingActivationResponse, VehicleAnnouncementMessage,
+        VehicleIdentificationRequest,
+    },
+};
+use futures::{stream, Sink, SinkExt, Stream};
+use socket2::{SockRef, TcpKeepalive};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::{TcpSocket as TokioTcpSocket, TcpStream as TokioTcpStream, ToSocketAddrs},
+    sync::{OwnedSemaphorePermit, Semaphore, SemaphorePermit},
+};
+use tokio_util::{
+    bytes::BytesMut,
+    codec::{Encoder, Framed, FramedRead, FramedWrite},
 };
-use futures::{SinkExt, StreamExt};
-use tokio::net::{TcpStream as TokioTcpStream, ToSocketAddrs};
-use tokio_util::codec::{Framed, FramedRead, FramedWrite};
 
-use crate::error::SocketSendError;
+use crate::{
+    error::{
+        ActivationError, DoipError, SendAllError, SessionError, SocketError, SocketReadError,
+        SocketSendError,
+    },
+    pacing::{SendGap, SlidingWindow, TokenBucket},
+    util,
+};
 
 use super::{
-    tcp_split::{TcpStreamReadHalf, TcpStreamWriteHalf},
-    SocketConfig,
+    codec_to_io,
+    frame_codec::{Frame, FrameCodec},
+    frame_history::{Direction, FrameHistory},
+    is_recoverable, is_tcp_payload, next_frame, oversized_error,
+    retry_policy::RetryPolicy,
+    socket_metrics::SocketMetrics,
+    tcp_info::{self, TcpInfoSnapshot},
+    tcp_split::{
+        no_addrs, AddrsOf, ErrorSignal, ReuniteError, SocketAddrs, TcpStreamReadHalf,
+        TcpStreamWriteHalf,
+    },
+    truncated_frame, DoipTcpPayload, LogicalAddressTable, RoutingActivationOutcome, SocketConfig,
+    VehicleIdentificationResponse,
 };
+
+fn tcp_addrs(stream: &TokioTcpStream) -> SocketAddrs {
+    (stream.peer_addr().ok(), stream.local_addr().ok())
+}
+
+fn send_error_to_codec(err: SocketSendError) -> CodecError {
+    match err {
+        SocketSendError::EncodeError(err) => err,
+        err => CodecError::IoError(io::Error::other(err)),
+    }
+}
+
+fn alive_check_window(config: &SocketConfig) -> Option<SlidingWindow> {
+    config
+        .max_alive_check_rate
+        .map(|rate| SlidingWindow::new(rate, Duration::from_secs(1)))
+}
+
+/// Suggested idle timeout for `send_uds_stream`, ECUs answering with a
+/// response pending NRC (0x78) may need longer
+pub const DOIP_UDS_STREAM_IDLE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A DoIP frame holding a permit from the semaphore passed to
+/// `TcpStream::read_with_permit`
+///
+/// The permit is released when the frame is dropped, dereferences to the inner
+/// `DoipMessage`.
+#[derive(Debug)]
+pub struct PermittedMessage<'a> {
+    msg: DoipMessage,
+    #[allow(dead_code)]
+    permit: SemaphorePermit<'a>,
+}
+
+impl PermittedMessage<'_> {
+    /// Take the inner DoIP frame, releasing the permit
+    pub fn into_inner(self) -> DoipMessage {
+        self.msg
+    }
+}
+
+impl Deref for PermittedMessage<'_> {
+    type Target = DoipMessage;
+
+    fn deref(&self) -> &Self::Target {
+        &self.msg
+    }
+}
+
 /// Simple implementation of a TCP Stream
 ///
 /// Applying only the most simple methods on this struct it is able to act as
 /// a simple TCP stream. If extended functionality is required you can access the
 /// inner Tokio TCP Stream, or raise a Issue on GitHub.
+///
+/// The transport defaults to a Tokio TCP Stream, any other `AsyncRead` and
+/// `AsyncWrite` transport can be used through `from_io`.
 #[derive(Debug)]
-pub struct TcpStream {
-    io: Framed<TokioTcpStream, DoipCodec>,
+pub struct TcpStream<T = TokioTcpStream> {
+    io: Framed<T, FrameCodec>,
     config: SocketConfig,
+    permit: Option<Arc<OwnedSemaphorePermit>>,
+    send_pending: bool,
+    send_limiter: Option<TokenBucket>,
+    send_gap: Option<SendGap>,
+    alive_check_window: Option<SlidingWindow>,
+    history: Option<FrameHistory>,
+    metrics: SocketMetrics,
+    dry_run_bytes: BytesMut,
+    queue: VecDeque<DoipMessage>,
+    context: Option<Box<dyn Any + Send + Sync>>,
+    addrs_of: AddrsOf<T>,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
 }
 
 impl TcpStream {
     /// Creates a new TCP Stream from a Tokio TCP Stream
+    ///
+    /// Uses the config set by `set_default_socket_config`, if any.
     pub fn new(io: TokioTcpStream) -> Self {
-        TcpStream {
-            io: Framed::new(io, DoipCodec {}),
-            config: SocketConfig {
-                protocol_version: ProtocolVersion::Iso13400_2012,
-            },
-        }
+        Self::from_io(io).apply_socket_config()
+    }
+
+    /// Creates a new TCP Stream from a Tokio TCP Stream, using `config` rather
+    /// than the process default
+    ///
+    /// Useful on the server side for configuring an accepted connection before
+    /// the first frame is read.
+    pub fn from_tokio_with_config(io: TokioTcpStream, config: SocketConfig) -> Self {
+        Self::from_parts(io, config, None).apply_socket_config()
     }
 
     /// Creates a new TCP Stream given a remote address
@@ -45,31 +158,349 @@ impl TcpStream {
         }
     }
 
-    fn apply_codec(stream: TokioTcpStream) -> TcpStream {
-        TcpStream {
-            io: Framed::new(stream, DoipCodec {}),
-            config: SocketConfig {
-                protocol_version: ProtocolVersion::Iso13400_2012,
-            },
+    /// Creates a new TCP Stream given a remote address, sourcing the connection
+    /// from the local address `bind`
+    ///
+    /// Useful when a gateway filters testers by source address. Only remote
+    /// addresses of the same family as the local address are tried, and
+    /// `SO_REUSEADDR` is set so a fixed local port can be reused straight
+    /// after a previous connection closes. Returns the error of the last
+    /// attempt if every remote address fails.
+    pub async fn connect_from<A: ToSocketAddrs, B: ToSocketAddrs>(
+        bind: B,
+        remote: A,
+    ) -> io::Result<TcpStream> {
+        let local = tokio::net::lookup_host(bind).await?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "No local address to bind")
+        })?;
+
+        let mut last_err = None;
+
+        for addr in tokio::net::lookup_host(remote).await? {
+            if addr.is_ipv4() != local.is_ipv4() {
+                continue;
+            }
+
+            let socket = match local {
+                SocketAddr::V4(_) => TokioTcpSocket::new_v4()?,
+                SocketAddr::V6(_) => TokioTcpSocket::new_v6()?,
+            };
+            socket.set_reuseaddr(true)?;
+            socket.bind(local)?;
+
+            match socket.connect(addr).await {
+                Ok(stream) => return Ok(Self::apply_codec(stream)),
+                Err(err) => last_err = Some(err),
+            }
         }
+
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "No remote address of the same family as the local address",
+            )
+        }))
     }
 
-    /// Send a DoIP frame to the sink
-    pub async fn send(&mut self, payload: DoipPayload) -> Result<(), SocketSendError> {
-        let msg = DoipMessageBuilder::new()
-            .protocol_version(self.config.protocol_version)
-            .payload(payload)
-            .build();
+    /// Creates a new TCP Stream given a remote address, retrying up to
+    /// `retries` times with a fixed `backoff` between attempts
+    ///
+    /// Returns the error of the last attempt once the retries are exhausted.
+    pub async fn connect_with_retry<A: ToSocketAddrs + Clone>(
+        addr: A,
+        retries: usize,
+        backoff: Duration,
+    ) -> io::Result<TcpStream> {
+        Self::connect_with_retry_policy(addr, RetryPolicy::fixed(retries, backoff)).await
+    }
 
-        match self.io.send(msg).await {
-            Ok(_) => Ok(()),
-            Err(err) => Err(SocketSendError::EncodeError(err)),
+    /// Creates a new TCP Stream given a remote address, retrying failed
+    /// attempts according to `policy`
+    ///
+    /// Returns the error of the last attempt once the retries are exhausted.
+    pub async fn connect_with_retry_policy<A: ToSocketAddrs + Clone>(
+        addr: A,
+        policy: RetryPolicy,
+    ) -> io::Result<TcpStream> {
+        let mut retry = 0;
+
+        loop {
+            match Self::connect(addr.clone()).await {
+                Ok(stream) => return Ok(stream),
+                Err(err) if retry >= policy.retries() => return Err(err),
+                Err(_) => {
+                    tokio::time::sleep(policy.delay(retry)).await;
+                    retry += 1;
+                }
+            }
         }
     }
 
-    /// Read a DoIP frame off the stream
-    pub async fn read(&mut self) -> Option<Result<DoipMessage, CodecError>> {
-        self.io.next().await
+    /// Creates a new TCP Stream given a remote address, using `config` rather
+    /// than the process default
+    ///
+    /// Useful for setting the protocol version of a connection to an older
+    /// DoIP entity.
+    pub async fn connect_with_config<A: ToSocketAddrs>(
+        addr: A,
+        config: SocketConfig,
+    ) -> io::Result<TcpStream> {
+        let stream = TokioTcpStream::connect(addr).await?;
+        Ok(Self::from_parts(stream, config, None).apply_socket_config())
+    }
+
+    /// Creates a new TCP Stream to the DoIP entity which sent an announcement
+    ///
+    /// The announcement does not carry the entity's IP address, so `source` is
+    /// the address the announcement was received from, as returned by
+    /// `UdpSocket::recv`. Returns the stream alongside the entity's logical
+    /// address, or `io::ErrorKind::TimedOut` if the connection is not
+    /// established within `timeout`.
+    pub async fn connect_from_announcement(
+        announcement: &VehicleAnnouncementMessage,
+        source: SocketAddr,
+        port: u16,
+        timeout: Duration,
+    ) -> io::Result<(TcpStream, [u8; 2])> {
+        let addr = SocketAddr::new(source.ip(), port);
+
+        match tokio::time::timeout(timeout, Self::connect(addr)).await {
+            Ok(stream) => Ok((stream?, announcement.logical_address)),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Timed out connecting to announced DoIP entity",
+            )),
+        }
+    }
+
+    /// Creates a new TCP Stream given a remote address, resolving once the
+    /// socket is writable
+    ///
+    /// Unlike `connect`, the first `send` will not wait on the connection
+    /// completing. Returns `io::ErrorKind::TimedOut` if the socket is not
+    /// writable within `timeout`.
+    pub async fn connect_ready<A: ToSocketAddrs>(
+        addr: A,
+        timeout: Duration,
+    ) -> io::Result<TcpStream> {
+        let connect = async {
+            let stream = TokioTcpStream::connect(addr).await?;
+            stream.writable().await?;
+            Ok::<_, io::Error>(stream)
+        };
+
+        match tokio::time::timeout(timeout, connect).await {
+            Ok(stream) => Ok(Self::apply_codec(stream?)),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Timed out waiting for TCP Stream to become writable",
+            )),
+        }
+    }
+
+    /// Creates a new TCP Stream given a remote address and reads the first
+    /// frame sent by the peer
+    ///
+    /// A single `timeout` covers both connecting and the first frame arriving,
+    /// returning `io::ErrorKind::TimedOut` if it is exceeded, or
+    /// `io::ErrorKind::UnexpectedEof` if the peer closes the connection first.
+    pub async fn connect_and_read_first<A: ToSocketAddrs>(
+        addr: A,
+        timeout: Duration,
+    ) -> io::Result<(TcpStream, DoipMessage)> {
+        let connect = async {
+            let mut stream = Self::connect(addr).await?;
+
+            match stream.read().await {
+                Some(Ok(msg)) => Ok((stream, msg)),
+                Some(Err(CodecError::IoError(err))) => Err(err),
+                Some(Err(err)) => Err(io::Error::new(io::ErrorKind::InvalidData, err.to_string())),
+                None => Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Connection closed before the first frame",
+                )),
+            }
+        };
+
+        match tokio::time::timeout(timeout, connect).await {
+            Ok(res) => res,
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Timed out waiting for the first frame",
+            )),
+        }
+    }
+
+    /// Creates a new TCP Stream and activates routing, negotiating the
+    /// protocol version
+    ///
+    /// Each version in `versions` is tried in order on a fresh connection, as
+    /// ISO-13400 has the DoIP entity close the connection after rejecting a
+    /// header. A Generic Nack with `NackCode::IncorrectPatternFormat` falls
+    /// back to the next version, any other failure is returned immediately.
+    /// `timeout` bounds each attempt. The stream is returned with the accepted
+    /// version set in its config, or `ActivationError::VersionNotSupported` if
+    /// every version is rejected.
+    pub async fn connect_negotiate<A: ToSocketAddrs + Clone>(
+        addr: A,
+        versions: &[ProtocolVersion],
+        source_address: [u8; 2],
+        timeout: Duration,
+    ) -> Result<(TcpStream, RoutingActivationOutcome), ActivationError> {
+        for version in versions {
+            let attempt = async {
+                let stream = Self::connect(addr.clone())
+                    .await
+                    .map_err(ActivationError::ConnectError)?;
+
+                let mut config = stream.config();
+                config.set_protocol_version(*version);
+                let mut stream = stream.with_config(config);
+
+                let request = DoipPayload::RoutingActivationRequest(RoutingActivationRequest {
+                    source_address,
+                    activation_type: ActivationType::Default,
+                    buffer: [0, 0, 0, 0],
+                });
+                stream
+                    .send(request)
+                    .await
+                    .map_err(ActivationError::SendError)?;
+
+                loop {
+                    match stream.read().await {
+                        Some(Ok(DoipMessage {
+                            payload: DoipPayload::RoutingActivationResponse(res),
+                            ..
+                        })) => {
+                            let outcome = RoutingActivationOutcome::from_response(res)?;
+                            return Ok(Some((stream, outcome)));
+                        }
+                        Some(Ok(DoipMessage {
+                            payload:
+                                DoipPayload::GenericNack(GenericNack {
+                                    nack_code: NackCode::IncorrectPatternFormat,
+                                }),
+                            ..
+                        })) => return Ok(None),
+                        Some(Ok(_)) => continue,
+                        Some(Err(err)) => return Err(ActivationError::ReadError(err)),
+                        None => return Err(ActivationError::ConnectionClosed),
+                    }
+                }
+            };
+
+            match tokio::time::timeout(timeout, attempt).await {
+                Ok(Ok(Some(res))) => return Ok(res),
+                Ok(Ok(None)) => continue,
+                Ok(Err(err)) => return Err(err),
+                Err(_) => return Err(ActivationError::Timeout),
+            }
+        }
+
+        Err(ActivationError::VersionNotSupported)
+    }
+
+    /// Open a session, run `f` on it and close it
+    ///
+    /// Connects to `addr` and activates routing within `timeout`, then runs
+    /// `f` on the stream. The stream is shut down once `f` completes, whether
+    /// it succeeded or not, so the connection is never leaked.
+    pub async fn with_session<A, R, E>(
+        addr: A,
+        source_address: [u8; 2],
+        activation_type: ActivationType,
+        timeout: Duration,
+        f: impl AsyncFnOnce(&mut TcpStream) -> Result<R, E>,
+    ) -> Result<R, SessionError<E>>
+    where
+        A: ToSocketAddrs,
+    {
+        let open = async {
+            let mut stream = Self::connect(addr)
+                .await
+                .map_err(ActivationError::ConnectError)?;
+
+            match stream
+                .activate_routing(source_address, activation_type)
+                .await?
+            {
+                RoutingActivationOutcome::Activated(_) => Ok(stream),
+                RoutingActivationOutcome::PendingConfirmation(res) => {
+                    Err(SessionError::ConfirmationRequired(res))
+                }
+            }
+        };
+
+        let mut stream = match tokio::time::timeout(timeout, open).await {
+            Ok(stream) => stream?,
+            Err(_) => return Err(SessionError::Activation(ActivationError::Timeout)),
+        };
+
+        let res = f(&mut stream).await;
+
+        // Closing is best effort, the outcome of the session takes precedence
+        let _ = std::future::poll_fn(|cx| stream.poll_close(cx)).await;
+
+        res.map_err(SessionError::Session)
+    }
+
+    /// Probe which protocol versions the DoIP entity at `addr` supports
+    ///
+    /// Attempts a routing activation for each version in `candidates` on a
+    /// fresh connection, as in `connect_negotiate`. A version is supported if
+    /// the entity answers with a Routing Activation Response, even one
+    /// denying the activation. `timeout` bounds each attempt.
+    pub async fn detect_supported_versions<A: ToSocketAddrs + Clone>(
+        addr: A,
+        source_address: [u8; 2],
+        candidates: &[ProtocolVersion],
+        timeout: Duration,
+    ) -> Vec<ProtocolVersion> {
+        let mut supported = Vec::new();
+
+        for version in candidates {
+            let res =
+                Self::connect_negotiate(addr.clone(), &[*version], source_address, timeout).await;
+
+            if matches!(res, Ok(_) | Err(ActivationError::Denied(_))) {
+                supported.push(*version);
+            }
+        }
+
+        supported
+    }
+
+    fn apply_codec(stream: TokioTcpStream) -> TcpStream {
+        Self::new(stream)
+    }
+
+    pub(crate) fn with_listener_config(
+        io: TokioTcpStream,
+        config: SocketConfig,
+        permit: Option<OwnedSemaphorePermit>,
+    ) -> TcpStream {
+        Self::from_parts(io, config, permit).apply_socket_config()
+    }
+
+    fn apply_socket_config(mut self) -> TcpStream {
+        self.addrs_of = tcp_addrs;
+
+        if let Some(nodelay) = self.config.nodelay {
+            // Best effort, the stream is still usable with the OS default
+            let _ = self.set_nodelay(nodelay);
+        }
+
+        if let Some(idle) = self.config.keepalive {
+            let _ = self.set_keepalive(Some(idle));
+        }
+
+        #[cfg(feature = "tracing")]
+        if let Ok(peer) = self.peer_addr() {
+            self.span.record("peer", tracing::field::display(peer));
+        }
+
+        self
     }
 
     /// Converts a standard library TCP Stream to a DoIP Framed TCP Stream
@@ -78,174 +509,3812 @@ impl TcpStream {
         Ok(Self::apply_codec(stream))
     }
 
-    /// Splits the TCP Stream into a Read Half and Write Half
-    pub fn into_split(
-        self,
-    ) -> (
-        TcpStreamReadHalf<TokioTcpStream>,
-        TcpStreamWriteHalf<TokioTcpStream>,
-    ) {
-        let stream: TokioTcpStream = self.io.into_inner();
+    /// The remote address of the connection
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.io.get_ref().peer_addr()
+    }
 
-        let (r_half, w_half) = tokio::io::split(stream);
+    /// The local address of the connection
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.io.get_ref().local_addr()
+    }
 
-        let read = FramedRead::new(r_half, DoipCodec {});
-        let write = FramedWrite::new(w_half, DoipCodec {});
+    /// Enable or disable Nagle's algorithm (`TCP_NODELAY`) on the connection
+    ///
+    /// Disabling Nagle's algorithm sends small frames, such as back to back
+    /// Diagnostic Messages, without waiting to coalesce them.
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        self.io.get_ref().set_nodelay(nodelay)
+    }
 
-        (
-            TcpStreamReadHalf::new(read, Some(self.config)),
-            TcpStreamWriteHalf::new(write, Some(self.config)),
-        )
+    /// Whether Nagle's algorithm is disabled on the connection
+    pub fn nodelay(&self) -> io::Result<bool> {
+        self.io.get_ref().nodelay()
     }
 
-    /// Get a reference to the inner Tokio TCP Stream
-    pub fn get_stream_ref(&self) -> &TokioTcpStream {
-        self.io.get_ref()
+    /// Enable TCP keepalive (`SO_KEEPALIVE`), probing after the connection has
+    /// been idle for `idle`, or disable it with `None`
+    ///
+    /// Keeps long idle diagnostic sessions alive behind NAT and firewalls. The
+    /// probe interval is also set to `idle` on Linux, Android, macOS, iOS,
+    /// Windows, FreeBSD and NetBSD, other platforms keep the OS default
+    /// interval. OpenBSD and Haiku only support the on/off toggle.
+    pub fn set_keepalive(&self, idle: Option<Duration>) -> io::Result<()> {
+        let socket = SockRef::from(self.io.get_ref());
+
+        let Some(idle) = idle else {
+            return socket.set_keepalive(false);
+        };
+
+        let params = TcpKeepalive::new().with_time(idle);
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "windows",
+            target_os = "freebsd",
+            target_os = "netbsd",
+        ))]
+        let params = params.with_interval(idle);
+
+        socket.set_tcp_keepalive(&params)
     }
 
-    /// Access the inner Tokio TCP Stream, consumes the DoIP TCP Stream
-    pub fn into_socket(self) -> TokioTcpStream {
-        self.io.into_inner()
+    /// Whether TCP keepalive is enabled on the connection
+    pub fn keepalive(&self) -> io::Result<bool> {
+        SockRef::from(self.io.get_ref()).keepalive()
+    }
+
+    /// Read a snapshot of the kernel `TCP_INFO` for the connection
+    ///
+    /// Returns `io::ErrorKind::Unsupported` on platforms other than Linux.
+    pub fn tcp_info(&self) -> io::Result<TcpInfoSnapshot> {
+        tcp_info::tcp_info(self.io.get_ref())
     }
 }
 
-#[cfg(test)]
-mod test_tcp_stream {
-    use doip_definitions::{
-        builder::DoipMessageBuilder,
-        payload::{
-            ActivationCode, ActivationType, DoipPayload, RoutingActivationRequest,
-            RoutingActivationResponse,
-        },
-    };
-    use tokio::io::AsyncReadExt;
-    use tokio_util::codec::Encoder;
+impl<T> TcpStream<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Creates a new DoIP Stream over any transport, such as a Unix domain
+    /// socket or an in-memory duplex
+    ///
+    /// Uses the config set by `set_default_socket_config`, if any.
+    pub fn from_io(io: T) -> Self {
+        let config = SocketConfig::process_default().unwrap_or_default();
 
-    use crate::tcp::tcp_stream::TcpStream;
+        Self::from_parts(io, config, None)
+    }
 
-    #[tokio::test]
-    async fn test_connect() {
-        const TESTER_ADDR: &str = "127.0.0.1:0";
+    fn from_parts(
+        io: T,
+        config: SocketConfig,
+        permit: Option<OwnedSemaphorePermit>,
+    ) -> TcpStream<T> {
+        let codec = FrameCodec::from_config(&config);
 
-        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
-        let addr = listener.local_addr().unwrap();
+        Self::from_framed(Framed::new(io, codec), config, permit.map(Arc::new))
+    }
 
-        let stream = TcpStream::connect(addr).await;
-        dbg!(&stream);
+    pub(crate) fn from_framed(
+        io: Framed<T, FrameCodec>,
+        config: SocketConfig,
+        permit: Option<Arc<OwnedSemaphorePermit>>,
+    ) -> TcpStream<T> {
+        TcpStream {
+            io,
+            send_limiter: config.send_rate_limit.map(TokenBucket::new),
+            send_gap: config.min_send_gap.map(SendGap::new),
+            alive_check_window: alive_check_window(&config),
+            history: config.frame_history.map(FrameHistory::new),
+            config,
+            permit,
+            send_pending: false,
+            metrics: SocketMetrics::default(),
+            dry_run_bytes: BytesMut::new(),
+            queue: VecDeque::new(),
+            context: None,
+            addrs_of: no_addrs,
+            #[cfg(feature = "tracing")]
+            span: tracing::debug_span!("doip_tcp_stream", peer = tracing::field::Empty),
+        }
+    }
 
-        assert!(stream.is_ok());
+    pub(crate) fn with_queue(mut self, queue: VecDeque<DoipMessage>) -> Self {
+        self.queue = queue;
+        self
+    }
 
-        // Cleanup
-        drop(listener);
+    pub(crate) fn with_addrs_of(mut self, addrs_of: AddrsOf<T>) -> Self {
+        self.addrs_of = addrs_of;
+        self
     }
 
-    #[tokio::test]
-    async fn test_send() {
-        const TESTER_ADDR: &str = "127.0.0.1:0";
+    pub(crate) fn with_dry_run_bytes(mut self, dry_run_bytes: BytesMut) -> Self {
+        self.dry_run_bytes = dry_run_bytes;
+        self
+    }
 
-        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await;
-        let listener = listener.unwrap();
+    /// Applies a config to the TCP Stream, replacing the current config
+    pub fn with_config(mut self, config: SocketConfig) -> Self {
+        self.send_limiter = config.send_rate_limit.map(TokenBucket::new);
+        self.send_gap = config.min_send_gap.map(SendGap::new);
+        self.alive_check_window = alive_check_window(&config);
+        self.history = config.frame_history.map(FrameHistory::new);
+        self.io
+            .codec_mut()
+            .set_max_payload_length(config.max_message_size);
+        self.io.codec_mut().set_oem_specific(config.oem_specific);
+        self.config = config;
+        self
+    }
 
-        let stream = TcpStream::connect(listener.local_addr().unwrap()).await;
+    /// Returns a clone of the config currently applied to the TCP Stream
+    pub fn config(&self) -> SocketConfig {
+        self.config.clone()
+    }
 
-        let mut stream = stream.unwrap();
+    /// Answer Alive Check Requests inside `read` rather than returning them
+    ///
+    /// Each request is answered with an Alive Check Response carrying
+    /// `source_address`, keeping the connection from being torn down while
+    /// the application is busy elsewhere.
+    pub fn enable_alive_check_autoresponse(&mut self, source_address: [u8; 2]) {
+        self.config.auto_alive_check = Some(source_address);
+    }
 
-        let (mut socket, _) = listener.accept().await.unwrap();
+    /// Return Alive Check Requests from `read` rather than answering them
+    pub fn disable_alive_check_autoresponse(&mut self) {
+        self.config.auto_alive_check = None;
+    }
 
-        let routing_activation_payload =
-            DoipPayload::RoutingActivationRequest(RoutingActivationRequest {
-                source_address: [0x0e, 0x80],
-                activation_type: ActivationType::Default,
-                buffer: [0, 0, 0, 0],
-            });
-        let routing_activation = DoipMessageBuilder::new()
-            .protocol_version(stream.config.protocol_version)
-            .payload(routing_activation_payload.clone())
-            .build();
+    /// Change the protocol version used by subsequent sends
+    pub fn set_protocol_version(&mut self, version: ProtocolVersion) {
+        self.config.protocol_version = version;
+    }
 
-        let mut codec = doip_codec::DoipCodec {};
-        let mut bytes = tokio_util::bytes::BytesMut::new();
-        codec.encode(routing_activation, &mut bytes).unwrap();
+    /// The protocol version used when sending
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.config.protocol_version
+    }
 
-        let _ = &stream.send(routing_activation_payload).await;
+    /// Attach user context to the TCP Stream, replacing any existing context
+    ///
+    /// Useful for keeping per-connection state, such as the authenticated
+    /// tester or vehicle VIN, alongside the stream.
+    pub fn set_context<C: Any + Send + Sync>(&mut self, ctx: C) {
+        self.context = Some(Box::new(ctx));
+    }
 
-        let mut buffer = [0; 64];
-        let read = socket.read(&mut buffer).await.unwrap();
+    /// Get the user context attached to the TCP Stream
+    ///
+    /// Returns `None` if no context is set or it is not of type `C`.
+    pub fn context<C: Any + Send + Sync>(&self) -> Option<&C> {
+        self.context.as_ref()?.downcast_ref()
+    }
 
-        assert_eq!(&buffer[8..read], &bytes[8..read]);
+    /// Send a DoIP frame to the sink
+    ///
+    /// If a send rate limit or minimum send gap is configured this waits until
+    /// the frame may be sent. In dry run mode the frame is encoded into the
+    /// bytes returned by `take_dry_run_bytes` instead of being sent.
+    pub async fn send(&mut self, payload: DoipPayload) -> Result<(), SocketSendError> {
+        let msg = self.config.build_message(payload)?;
+        self.send_frame(msg).await
+    }
 
-        // Cleanup
-        drop(socket);
+    /// Send a fully formed DoIP message to the sink
+    ///
+    /// The header is sent as given, so unlike `send` the payload length is not
+    /// corrected and neither the payload contents nor the payload version are
+    /// checked. Only the payload type is checked at runtime, returning
+    /// `SocketSendError::InvalidTcpPayload` for payloads only valid over UDP
+    /// unless relay mode is enabled. Pacing, dry run and history apply as for
+    /// `send`.
+    pub async fn send_message(&mut self, msg: DoipMessage) -> Result<(), SocketSendError> {
+        if !self.config.relay_mode && !is_tcp_payload(msg.header.payload_type) {
+            return Err(SocketSendError::InvalidTcpPayload);
+        }
+
+        self.send_frame(msg).await
     }
 
-    #[tokio::test]
-    async fn test_read() {
-        const TESTER_ADDR: &str = "127.0.0.1:0";
-        let routing_activation = DoipPayload::RoutingActivationRequest(RoutingActivationRequest {
-            source_address: [0x0e, 0x80],
-            activation_type: ActivationType::Default,
-            buffer: [0, 0, 0, 0],
-        });
+    /// Send a payload only valid over UDP, for the responses the config has
+    /// opted in to sending over TCP
+    async fn send_udp_payload(&mut self, payload: DoipPayload) -> Result<(), SocketSendError> {
+        let msg = self.config.build_frame(payload)?;
+        self.send_frame(msg).await
+    }
 
-        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await;
-        let listener = listener.unwrap();
+    async fn send_frame(&mut self, msg: DoipMessage) -> Result<(), SocketSendError> {
+        if self.config.dry_run {
+            return self.start_frame(msg);
+        }
 
-        let client = TcpStream::connect(listener.local_addr().unwrap()).await;
-        let mut client = client.unwrap();
+        if let Some(limiter) = &mut self.send_limiter {
+            limiter.acquire().await;
+        }
 
-        let (socket, _) = listener.accept().await.unwrap();
-        let mut server = TcpStream::new(socket);
+        if let Some(gap) = &self.send_gap {
+            gap.wait().await;
+        }
 
-        let _ = &client.send(routing_activation).await;
-        let _ = server.read().await.unwrap().unwrap();
+        if let Some(history) = &mut self.history {
+            history.record(Direction::Sent, &msg);
+        }
 
-        let routing_activation_res =
-            DoipPayload::RoutingActivationResponse(RoutingActivationResponse {
-                logical_address: [0x0e, 0x80],
-                source_address: [0x14, 0x11],
-                activation_code: ActivationCode::SuccessfullyActivated,
-                buffer: [0, 0, 0, 0],
-            });
+        let header = msg.header.clone();
+        let res = self.io.send(msg).await;
 
-        let _ = server.send(routing_activation_res.clone()).await;
-        let echo = client.read().await.unwrap().unwrap();
+        if res.is_ok() {
+            self.record_sent(&header);
+        }
 
-        assert_eq!(echo.payload, routing_activation_res)
+        if let Some(gap) = &mut self.send_gap {
+            gap.mark();
+        }
+
+        match res {
+            Ok(_) => Ok(()),
+            Err(err) => Err(SocketSendError::from(err)),
+        }
     }
 
-    #[tokio::test]
-    async fn test_into_split() {
-        const TESTER_ADDR: &str = "127.0.0.1:0";
-        let routing_activation = DoipPayload::RoutingActivationRequest(RoutingActivationRequest {
-            source_address: [0x0e, 0x80],
-            activation_type: ActivationType::Default,
-            buffer: [0, 0, 0, 0],
-        });
+    /// Buffer a frame on the sink, recording it as sent
+    ///
+    /// In dry run mode the frame is encoded into the bytes returned by
+    /// `take_dry_run_bytes` instead, so every send path leaves the transport
+    /// untouched.
+    fn start_frame(&mut self, msg: DoipMessage) -> Result<(), SocketSendError> {
+        if let Some(history) = &mut self.history {
+            history.record(Direction::Sent, &msg);
+        }
 
-        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await;
-        let listener = listener.unwrap();
+        if self.config.dry_run {
+            return self
+                .io
+                .codec_mut()
+                .encode(msg, &mut self.dry_run_bytes)
+                .map_err(SocketSendError::from);
+        }
 
-        let client = TcpStream::connect(listener.local_addr().unwrap()).await;
-        let client = client.unwrap();
-        let (mut read, mut write) = client.into_split();
+        let header = msg.header.clone();
+        Pin::new(&mut self.io)
+            .start_send(msg)
+            .map_err(SocketSendError::from)?;
+        self.record_sent(&header);
 
-        let (socket, _) = listener.accept().await.unwrap();
-        let mut server = TcpStream::new(socket);
+        Ok(())
+    }
 
-        let _ = write.send(routing_activation).await;
-        let _ = server.read().await.unwrap().unwrap();
+    /// Send a batch of DoIP frames, flushing once after the last
+    ///
+    /// Each payload is fed to the sink as by the `Sink` impl, so the send rate
+    /// limit and minimum send gap are not applied. On failure the error carries
+    /// the index of the payload which failed, so the batch can be resumed.
+    pub async fn send_all<I>(&mut self, payloads: I) -> Result<(), SendAllError>
+    where
+        I: IntoIterator<Item = DoipPayload>,
+    {
+        let mut count = 0;
 
-        let routing_activation_res =
-            DoipPayload::RoutingActivationResponse(RoutingActivationResponse {
-                logical_address: [0x0e, 0x80],
-                source_address: [0x14, 0x11],
-                activation_code: ActivationCode::SuccessfullyActivated,
-                buffer: [0, 0, 0, 0],
-            });
+        for (index, payload) in payloads.into_iter().enumerate() {
+            SinkExt::feed(self, payload)
+                .await
+                .map_err(|error| SendAllError { index, error })?;
+            count = index + 1;
+        }
 
-        let _ = server.send(routing_activation_res.clone()).await;
-        let echo = read.read().await.unwrap().unwrap();
+        self.flush().await.map_err(|error| SendAllError {
+            index: count,
+            error,
+        })
+    }
 
-        assert_eq!(echo.payload, routing_activation_res)
+    /// Buffer a DoIP frame without flushing it
+    ///
+    /// As the `Sink` impl, the send rate limit and minimum send gap are not
+    /// applied. Buffered frames are written once the buffer fills or on
+    /// `flush`, giving the caller control over batching. `shutdown` flushes
+    /// them first, but frames still buffered when the stream is dropped are
+    /// lost.
+    pub async fn feed<A: DoipTcpPayload>(&mut self, payload: A) -> Result<(), SocketSendError> {
+        SinkExt::feed(self, payload.into_payload()).await
+    }
+
+    /// Write any buffered frames to the OS
+    pub async fn flush(&mut self) -> Result<(), SocketSendError> {
+        SinkExt::<DoipPayload>::flush(self).await
+    }
+
+    /// Send a DoIP frame and wait until it has been written to the OS
+    ///
+    /// Frames buffered beforehand, such as with `feed`, are written
+    /// first. Once this returns `write_buffer_len` is zero.
+    pub async fn send_and_confirm(&mut self, payload: DoipPayload) -> Result<(), SocketSendError> {
+        self.send(payload).await?;
+        self.io.flush().await.map_err(SocketSendError::from)
+    }
+
+    /// Number of encoded bytes buffered but not yet written to the OS
+    pub fn write_buffer_len(&self) -> usize {
+        self.io.write_buffer().len()
+    }
+
+    /// Bytes received but not yet decoded into a frame
+    ///
+    /// A read only view of the `Framed` read buffer, useful for measuring the
+    /// inbound backlog. Frames already decoded, such as those queued by
+    /// `send_request`, are not included.
+    pub fn read_buffer(&self) -> &BytesMut {
+        self.io.read_buffer()
+    }
+
+    /// Encoded bytes buffered but not yet written to the OS
+    ///
+    /// A read only view of the `Framed` write buffer, see `write_buffer_len`.
+    pub fn write_buffer(&self) -> &BytesMut {
+        self.io.write_buffer()
+    }
+
+    /// Take the bytes encoded in dry run mode since the last call
+    pub fn take_dry_run_bytes(&mut self) -> Vec<u8> {
+        self.dry_run_bytes.split().to_vec()
+    }
+
+    /// Attempt to send a DoIP frame to the sink without an await point
+    ///
+    /// Intended for manual executors and hand written futures. Once the frame
+    /// has been buffered, calls returning `Poll::Pending` only continue the
+    /// flush, so the same payload must be passed until `Poll::Ready` is
+    /// returned.
+    ///
+    /// As the `Sink` impl, the send rate limit and minimum send gap are not
+    /// applied. The frame is recorded in the frame history and metrics once
+    /// the flush completes. In dry run mode it is encoded into the bytes
+    /// returned by `take_dry_run_bytes` and `Poll::Ready` is returned at once.
+    pub fn poll_send(
+        &mut self,
+        cx: &mut Context<'_>,
+        payload: &DoipPayload,
+    ) -> Poll<Result<(), SocketSendError>> {
+        if self.config.dry_run {
+            let msg = self.config.build_message(payload.clone())?;
+            return Poll::Ready(self.start_frame(msg));
+        }
+
+        if !self.send_pending {
+            ready!(Pin::new(&mut self.io).poll_ready(cx)).map_err(SocketSendError::from)?;
+
+            let msg = self.config.build_message(payload.clone())?;
+            Pin::new(&mut self.io)
+                .start_send(msg)
+                .map_err(SocketSendError::from)?;
+            self.send_pending = true;
+        }
+
+        let res = ready!(Pin::new(&mut self.io).poll_flush(cx));
+        self.send_pending = false;
+        res.map_err(SocketSendError::from)?;
+
+        // The payload is unchanged across polls, so the frame is rebuilt
+        // rather than kept between them
+        let msg = self.config.build_message(payload.clone())?;
+        if let Some(history) = &mut self.history {
+            history.record(Direction::Sent, &msg);
+        }
+        self.record_sent(&msg.header);
+
+        Poll::Ready(Ok(()))
+    }
+
+    /// Attempt to flush any buffered frames to the socket
+    pub fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), SocketSendError>> {
+        let res = ready!(Pin::new(&mut self.io).poll_flush(cx));
+        self.send_pending = false;
+
+        Poll::Ready(res.map_err(SocketSendError::from))
+    }
+
+    /// Attempt to flush any buffered frames and shut down the write side of
+    /// the socket
+    ///
+    /// The peer reads EOF once this returns `Poll::Ready(Ok(()))`.
+    pub fn poll_close(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), SocketSendError>> {
+        let res = ready!(Pin::new(&mut self.io).poll_close(cx));
+        self.send_pending = false;
+
+        Poll::Ready(res.map_err(SocketSendError::from))
+    }
+
+    /// Attempt to read a DoIP frame off the stream without awaiting
+    ///
+    /// Returns `Poll::Pending` until a complete frame is buffered, including
+    /// while only part of a frame has arrived, and wakes `cx` once more data is
+    /// available. Frames are handled as by the `Stream` impl, so none are
+    /// answered automatically and the read timeout is not applied.
+    pub fn poll_read(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<DoipMessage, CodecError>>> {
+        Pin::new(self).poll_next(cx)
+    }
+
+    /// Take every frame which can be read without waiting
+    ///
+    /// Polls the stream as `poll_read` with a no-op waker, collecting frames
+    /// until it would block. Bytes already waiting on the socket are read, but
+    /// a partial frame is left buffered. Returns an empty `Vec` if no complete
+    /// frame is ready. Collection stops after the first error, or when the
+    /// stream is closed.
+    pub fn drain_ready(&mut self) -> Vec<Result<DoipMessage, CodecError>> {
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut frames = Vec::new();
+
+        while let Poll::Ready(Some(res)) = self.poll_read(&mut cx) {
+            let failed = res.is_err();
+            frames.push(res);
+
+            if failed {
+                break;
+            }
+        }
+
+        frames
+    }
+
+    /// Flush any buffered frames then shut down the write side of the socket
+    ///
+    /// Unlike dropping the stream this guarantees the last frame reaches the
+    /// OS before the FIN, so the peer sees a clean close rather than a reset.
+    pub async fn shutdown(&mut self) -> io::Result<()> {
+        SinkExt::<DoipMessage>::flush(&mut self.io)
+            .await
+            .map_err(codec_to_io)?;
+        self.send_pending = false;
+
+        self.io.get_mut().shutdown().await
+    }
+
+    /// Answer a protocol error with a Generic Nack then close the connection
+    ///
+    /// Sends a Generic Nack carrying `code`, flushes it and shuts down the
+    /// write side of the socket. ISO 13400-2 requires the connection to be
+    /// closed after `NackCode::IncorrectPatternFormat` and
+    /// `NackCode::InvalidPayloadLength`. For `NackCode::UnknownPayloadType`,
+    /// `NackCode::MessageTooLarge` and `NackCode::OutOfMemory` the message is
+    /// only discarded, so answer those with `send` and keep the connection open.
+    pub async fn nack_and_close(&mut self, code: NackCode) -> io::Result<()> {
+        self.send(DoipPayload::GenericNack(GenericNack { nack_code: code }))
+            .await
+            .map_err(|err| codec_to_io(send_error_to_codec(err)))?;
+
+        self.shutdown().await
+    }
+
+    /// Read a DoIP frame off the stream
+    ///
+    /// If the config has an auto alive check source address set, Alive Check
+    /// Requests are answered transparently and not returned. If a max alive check
+    /// rate is also set, requests over it are not answered and a
+    /// `SocketError::AliveCheckFlood` is returned instead.
+    ///
+    /// If a max message size is set, frames over it are discarded. They are
+    /// answered with a Generic Nack when an oversize nack code is set, otherwise
+    /// returned as an `io::ErrorKind::InvalidData` error. The stream remains
+    /// usable either way.
+    ///
+    /// If strict version checking is enabled, frames of another protocol
+    /// version are discarded and returned as a `SocketError::VersionMismatch`.
+    ///
+    /// Frames queued by `send_request` are returned first, in the order they
+    /// arrived.
+    pub async fn read(&mut self) -> Option<Result<DoipMessage, CodecError>> {
+        if let Some(msg) = self.queue.pop_front() {
+            return Some(Ok(msg));
+        }
+
+        self.read_frame().await
+    }
+
+    /// Read a DoIP frame off the stream, as `read` does, with a structured
+    /// error
+    ///
+    /// A clean close is returned as `SocketReadError::Closed`, so callers can
+    /// tell a failed connection apart from a frame which failed to decode.
+    pub async fn read_message(&mut self) -> Result<DoipMessage, SocketReadError> {
+        SocketReadError::from_read(self.read().await)
+    }
+
+    async fn read_frame(&mut self) -> Option<Result<DoipMessage, CodecError>> {
+        loop {
+            let msg = match next_frame(&mut self.io, self.config.read_timeout).await? {
+                Ok(Frame::Message(msg)) => msg,
+                Ok(Frame::Oversized(payload_length)) => {
+                    let Some(nack_code) = self.config.oversize_nack else {
+                        return Some(Err(oversized_error(payload_length)));
+                    };
+
+                    let response = DoipPayload::GenericNack(GenericNack { nack_code });
+
+                    match self.send(response).await {
+                        Ok(()) => continue,
+                        Err(err) => return Some(Err(send_error_to_codec(err))),
+                    }
+                }
+                Err(err) => return Some(Err(truncated_frame(err, self.io.read_buffer().len()))),
+            };
+
+            self.record_received(&msg);
+
+            if let Err(err) = self.config.check_version(&msg) {
+                return Some(Err(err));
+            }
+
+            if let (Some(source_address), DoipPayload::AliveCheckRequest(_)) =
+                (self.config.auto_alive_check, &msg.payload)
+            {
+                if let Some(window) = &mut self.alive_check_window {
+                    if !window.record() {
+                        return Some(Err(CodecError::IoError(io::Error::other(
+                            SocketError::AliveCheckFlood,
+                        ))));
+                    }
+                }
+
+                let response =
+                    DoipPayload::AliveCheckResponse(AliveCheckResponse { source_address });
+
+                match self.send(response).await {
+                    Ok(()) => continue,
+                    Err(err) => return Some(Err(send_error_to_codec(err))),
+                }
+            }
+
+            let response = match (
+                &msg.payload,
+                self.config.auto_status_response,
+                self.config.auto_power_mode,
+            ) {
+                (DoipPayload::EntityStatusRequest(_), Some(response), _) => {
+                    Some(DoipPayload::EntityStatusResponse(response))
+                }
+                (DoipPayload::PowerInformationRequest(_), _, Some(response)) => {
+                    Some(DoipPayload::PowerInformationResponse(response))
+                }
+                _ => None,
+            };
+
+            if let Some(response) = response {
+                match self.send_udp_payload(response).await {
+                    Ok(()) => continue,
+                    Err(err) => return Some(Err(send_error_to_codec(err))),
+                }
+            }
+
+            return Some(Ok(msg));
+        }
+    }
+
+    fn record_received(&mut self, msg: &DoipMessage) {
+        if let Some(history) = &mut self.history {
+            history.record(Direction::Received, msg);
+        }
+        self.metrics.record_received(msg.header.payload_type);
+
+        #[cfg(feature = "tracing")]
+        self.span.in_scope(|| {
+            tracing::debug!(
+                payload_type = ?msg.header.payload_type,
+                payload_length = msg.header.payload_length,
+                "Received DoIP frame"
+            )
+        });
+    }
+
+    fn record_sent(&mut self, header: &DoipHeader) {
+        self.metrics.record_sent(header.payload_type);
+
+        #[cfg(feature = "tracing")]
+        self.span.in_scope(|| {
+            tracing::debug!(
+                payload_type = ?header.payload_type,
+                payload_length = header.payload_length,
+                "Sent DoIP frame"
+            )
+        });
+    }
+
+    /// Counts of the frames sent and received on the TCP Stream
+    ///
+    /// Includes frames sent automatically, such as Alive Check Responses.
+    pub fn metrics(&self) -> &SocketMetrics {
+        &self.metrics
+    }
+
+    /// The most recent frames sent and received, oldest first
+    ///
+    /// Each frame is paired with its direction and the time it was sent or
+    /// received. Empty unless a frame history is set in the config.
+    pub fn recent_frames(&self) -> Vec<(Direction, Instant, DoipMessage)> {
+        self.history
+            .as_ref()
+            .map(FrameHistory::frames)
+            .unwrap_or_default()
+    }
+
+    /// Read up to `max` frames, returning early once `max_wait` has elapsed
+    ///
+    /// The batch is returned as soon as `max` frames are collected, the wait
+    /// elapses or the stream closes, whichever comes first. Frames arriving
+    /// after the wait are left for the next read.
+    pub async fn read_batch_timed(
+        &mut self,
+        max: usize,
+        max_wait: Duration,
+    ) -> Vec<Result<DoipMessage, CodecError>> {
+        let deadline = tokio::time::Instant::now() + max_wait;
+        let mut batch = Vec::with_capacity(max);
+
+        while batch.len() < max {
+            match tokio::time::timeout_at(deadline, self.read()).await {
+                Ok(Some(res)) => batch.push(res),
+                Ok(None) | Err(_) => break,
+            }
+        }
+
+        batch
+    }
+
+    /// Read a DoIP frame once a permit is available from `permits`
+    ///
+    /// The stream is not polled until a permit is acquired, so exhausting the
+    /// permits applies backpressure to the peer. The permit is held by the
+    /// returned frame until it is dropped. Returns `None` if the stream or the
+    /// semaphore is closed.
+    pub async fn read_with_permit<'a>(
+        &mut self,
+        permits: &'a Semaphore,
+    ) -> Option<Result<PermittedMessage<'a>, CodecError>> {
+        let permit = permits.acquire().await.ok()?;
+
+        Some(
+            self.read()
+                .await?
+                .map(|msg| PermittedMessage { msg, permit }),
+        )
+    }
+
+    /// Request the identity of a gateway over the TCP Stream
+    ///
+    /// Some gateways answer a Vehicle Identification Request on TCP before
+    /// routing is activated. The request is only valid over UDP, so `send`
+    /// rejects it and this is the one path that puts it on a TCP Stream. It is
+    /// checked against the configured protocol version regardless of
+    /// `enforce_payload_version`, returning `io::ErrorKind::InvalidInput`
+    /// wrapping `SocketSendError::PayloadNotAllowedForVersion` if the version
+    /// does not allow it.
+    ///
+    /// Any other frames received before the response are discarded. Returns
+    /// `io::ErrorKind::TimedOut` if no response arrives within `timeout`, or
+    /// `io::ErrorKind::InvalidData` if the gateway answers with a Generic Nack.
+    pub async fn identify(
+        &mut self,
+        timeout: Duration,
+    ) -> io::Result<VehicleIdentificationResponse> {
+        let version = self.config.protocol_version();
+
+        if !util::is_payload_allowed(version, PayloadType::VehicleIdentificationRequest) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                SocketSendError::PayloadNotAllowedForVersion(
+                    version,
+                    PayloadType::VehicleIdentificationRequest,
+                ),
+            ));
+        }
+
+        self.send_udp_payload(DoipPayload::VehicleIdentificationRequest(
+            VehicleIdentificationRequest {},
+        ))
+        .await
+        .map_err(io::Error::other)?;
+
+        let response = async {
+            loop {
+                match self.read().await {
+                    Some(Ok(msg)) => match msg.payload {
+                        DoipPayload::VehicleAnnouncementMessage(announcement) => {
+                            return Ok(announcement)
+                        }
+                        DoipPayload::GenericNack(nack) => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!(
+                                    "Vehicle Identification Request rejected: {:?}",
+                                    nack.nack_code
+                                ),
+                            ))
+                        }
+                        _ => continue,
+                    },
+                    Some(Err(CodecError::IoError(err))) => return Err(err),
+                    Some(Err(err)) => {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+                    }
+                    None => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "Connection closed before Vehicle Identification Response",
+                        ))
+                    }
+                }
+            }
+        };
+
+        match tokio::time::timeout(timeout, response).await {
+            Ok(res) => res,
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Timed out waiting for Vehicle Identification Response",
+            )),
+        }
+    }
+
+    /// Perform a routing activation handshake
+    ///
+    /// Sends a Routing Activation Request and waits for the Routing Activation
+    /// Response. Any other frames received before the response are discarded.
+    /// An activation which requires confirmation is surfaced as
+    /// `RoutingActivationOutcome::PendingConfirmation` so the caller can perform
+    /// the follow-up step, any other non-successful code is an
+    /// `ActivationError::Denied`.
+    pub async fn activate_routing(
+        &mut self,
+        source_address: [u8; 2],
+        activation_type: ActivationType,
+    ) -> Result<RoutingActivationOutcome, ActivationError> {
+        let request = DoipPayload::RoutingActivationRequest(RoutingActivationRequest {
+            source_address,
+            activation_type,
+            buffer: [0, 0, 0, 0],
+        });
+
+        self.send(request)
+            .await
+            .map_err(ActivationError::SendError)?;
+
+        loop {
+            match self.read().await {
+                Some(Ok(DoipMessage {
+                    payload: DoipPayload::RoutingActivationResponse(res),
+                    ..
+                })) => return RoutingActivationOutcome::from_response(res),
+                Some(Ok(_)) => continue,
+                Some(Err(err)) => return Err(ActivationError::ReadError(err)),
+                None => return Err(ActivationError::ConnectionClosed),
+            }
+        }
+    }
+
+    /// Perform a routing activation handshake requiring immediate success
+    ///
+    /// As `activate_routing`, discarding frames received before the response,
+    /// but returns the Routing Activation Response only if it is
+    /// `ActivationCode::SuccessfullyActivated`. Any other code, including one
+    /// requiring confirmation, is an `ActivationError::Denied`.
+    pub async fn activate(
+        &mut self,
+        source_address: [u8; 2],
+        activation_type: ActivationType,
+    ) -> Result<RoutingActivationResponse, ActivationError> {
+        match self
+            .activate_routing(source_address, activation_type)
+            .await?
+        {
+            RoutingActivationOutcome::Activated(res) => Ok(res),
+            RoutingActivationOutcome::PendingConfirmation(res) => Err(ActivationError::Denied(res)),
+        }
+    }
+
+    /// The OEM specific bytes of the last Routing Activation Request or
+    /// Response read off the stream
+    ///
+    /// `None` if the last activation frame carried no OEM specific field. Pairs
+    /// with `activate_routing` and `respond_activation` for handshakes which
+    /// validate or echo these bytes.
+    pub fn received_oem_specific(&self) -> Option<[u8; 4]> {
+        self.io.codec().received_oem_specific()
+    }
+
+    /// Send a Diagnostic Message and stream the UDS responses to it
+    ///
+    /// Diagnostic Message Acks are consumed, each Diagnostic Message from
+    /// `target_address` to `source_address` is yielded until no response
+    /// arrives within `idle_timeout`, the connection closes, or an error
+    /// occurs. A Diagnostic Message Nack between the same addresses is yielded
+    /// as `DoipError::Nack` and ends the stream. Any other frames, such as
+    /// Alive Checks or traffic of other ECUs, are queued and returned by
+    /// subsequent calls to `read`. See `DOIP_UDS_STREAM_IDLE_TIMEOUT` for a
+    /// suggested timeout.
+    pub fn send_uds_stream(
+        &mut self,
+        source_address: [u8; 2],
+        target_address: [u8; 2],
+        data: Vec<u8>,
+        idle_timeout: Duration,
+    ) -> impl Stream<Item = Result<DiagnosticMessage, DoipError>> + '_ {
+        let request = DoipPayload::DiagnosticMessage(DiagnosticMessage {
+            source_address,
+            target_address,
+            message: data,
+        });
+
+        stream::unfold(Some((self, Some(request))), move |state| async move {
+            let (stream, request) = state?;
+
+            if let Some(request) = request {
+                if let Err(err) = stream.send(request).await {
+                    return Some((Err(DoipError::SendError(err)), None));
+                }
+            }
+
+            let item = stream
+                .read_uds_response(source_address, target_address, idle_timeout)
+                .await?;
+            let next = item.is_ok().then_some((stream, None));
+
+            Some((item, next))
+        })
+    }
+
+    async fn read_uds_response(
+        &mut self,
+        source_address: [u8; 2],
+        target_address: [u8; 2],
+        idle_timeout: Duration,
+    ) -> Option<Result<DiagnosticMessage, DoipError>> {
+        let mirrors =
+            |source: [u8; 2], target: [u8; 2]| source == target_address && target == source_address;
+
+        loop {
+            let msg = match tokio::time::timeout(idle_timeout, self.read_frame()).await {
+                Ok(Some(Ok(msg))) => msg,
+                Ok(Some(Err(err))) => return Some(Err(DoipError::ReadError(err))),
+                Ok(None) | Err(_) => return None,
+            };
+
+            match msg.payload {
+                DoipPayload::DiagnosticMessage(res)
+                    if mirrors(res.source_address, res.target_address) =>
+                {
+                    return Some(Ok(res))
+                }
+                DoipPayload::DiagnosticMessageAck(ack)
+                    if mirrors(ack.source_address, ack.target_address) =>
+                {
+                    continue
+                }
+                DoipPayload::DiagnosticMessageNack(nack)
+                    if mirrors(nack.source_address, nack.target_address) =>
+                {
+                    return Some(Err(DoipError::Nack(nack)))
+                }
+                _ => self.queue.push_back(msg),
+            }
+        }
+    }
+
+    /// Send a Diagnostic Message and await the response to it
+    ///
+    /// The response is the first Diagnostic Message read after the send whose
+    /// source and target addresses mirror those of `msg`, frames already
+    /// queued are never taken as the response. Its Diagnostic Message Ack is
+    /// consumed, a Diagnostic Message Nack is returned as `DoipError::Nack`.
+    /// Any other frames are queued and returned by subsequent calls to `read`,
+    /// so no frames are lost. Returns `DoipError::Timeout` if no response
+    /// arrives within `timeout`.
+    pub async fn send_request(
+        &mut self,
+        msg: DiagnosticMessage,
+        timeout: Duration,
+    ) -> Result<DiagnosticMessage, DoipError> {
+        let (source_address, target_address) = (msg.source_address, msg.target_address);
+        let mirrors =
+            |source: [u8; 2], target: [u8; 2]| source == target_address && target == source_address;
+
+        self.send(DoipPayload::DiagnosticMessage(msg))
+            .await
+            .map_err(DoipError::SendError)?;
+
+        let recv = async {
+            loop {
+                let msg = match self.read_frame().await {
+                    Some(Ok(msg)) => msg,
+                    Some(Err(err)) => return Err(DoipError::ReadError(err)),
+                    None => return Err(DoipError::ConnectionClosed),
+                };
+
+                match msg.payload {
+                    DoipPayload::DiagnosticMessage(res)
+                        if mirrors(res.source_address, res.target_address) =>
+                    {
+                        return Ok(res)
+                    }
+                    DoipPayload::DiagnosticMessageAck(ack)
+                        if mirrors(ack.source_address, ack.target_address) =>
+                    {
+                        continue
+                    }
+                    DoipPayload::DiagnosticMessageNack(nack)
+                        if mirrors(nack.source_address, nack.target_address) =>
+                    {
+                        return Err(DoipError::Nack(nack))
+                    }
+                    _ => self.queue.push_back(msg),
+                }
+            }
+        };
+
+        tokio::time::timeout(timeout, recv)
+            .await
+            .unwrap_or(Err(DoipError::Timeout))
+    }
+
+    /// Send a batch of Diagnostic Messages and await the acknowledgement of each
+    ///
+    /// Every message is sent before any acknowledgement is read. Acks and Nacks
+    /// are matched back to their message by source and target address, in send
+    /// order for messages sharing both. The results are in the order of `msgs`,
+    /// messages left unacknowledged after `timeout` are `DoipError::Timeout`.
+    /// Read errors which leave the stream usable, such as a read timeout or a
+    /// version mismatch, are skipped. If the connection fails the first
+    /// outstanding message carries the read error and the rest
+    /// `DoipError::ConnectionClosed`. Any other frames, such as the responses
+    /// to messages already acknowledged, are queued and returned by subsequent
+    /// calls to `read`.
+    pub async fn send_diagnostics_await_all(
+        &mut self,
+        msgs: Vec<DiagnosticMessage>,
+        timeout: Duration,
+    ) -> Vec<Result<DiagnosticMessageAck, DoipError>> {
+        let mut results: Vec<Option<Result<DiagnosticMessageAck, DoipError>>> =
+            msgs.iter().map(|_| None).collect();
+        let mut pending = Vec::with_capacity(msgs.len());
+
+        for (idx, msg) in msgs.into_iter().enumerate() {
+            let key = (msg.target_address, msg.source_address);
+
+            match self.send(DoipPayload::DiagnosticMessage(msg)).await {
+                Ok(()) => pending.push((idx, key)),
+                Err(err) => results[idx] = Some(Err(DoipError::SendError(err))),
+            }
+        }
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut closed = false;
+
+        while !pending.is_empty() {
+            let msg = match tokio::time::timeout_at(deadline, self.read_frame()).await {
+                Ok(Some(Ok(msg))) => msg,
+                Ok(Some(Err(err))) if is_recoverable(&err) => continue,
+                Ok(Some(Err(err))) => {
+                    let (idx, _) = pending.remove(0);
+                    results[idx] = Some(Err(DoipError::ReadError(err)));
+                    closed = true;
+                    break;
+                }
+                Ok(None) => {
+                    closed = true;
+                    break;
+                }
+                Err(_) => break,
+            };
+
+            let (key, res) = match msg.payload {
+                DoipPayload::DiagnosticMessageAck(ack) => {
+                    ((ack.source_address, ack.target_address), Ok(ack))
+                }
+                DoipPayload::DiagnosticMessageNack(nack) => (
+                    (nack.source_address, nack.target_address),
+                    Err(DoipError::Nack(nack)),
+                ),
+                _ => {
+                    self.queue.push_back(msg);
+                    continue;
+                }
+            };
+
+            match pending.iter().position(|(_, pending)| *pending == key) {
+                Some(pos) => {
+                    let (idx, _) = pending.remove(pos);
+                    results[idx] = Some(res);
+                }
+                None => self.queue.push_back(msg),
+            }
+        }
+
+        results
+            .into_iter()
+            .map(|res| match res {
+                Some(res) => res,
+                None if closed => Err(DoipError::ConnectionClosed),
+                None => Err(DoipError::Timeout),
+            })
+            .collect()
+    }
+
+    /// Respond to a Routing Activation Request as a DoIP entity
+    ///
+    /// The activation succeeds if the tester's source address is registered in
+    /// `testers`, otherwise it is denied with an unknown source address. The
+    /// OEM specific field is filled from the config, if set. Returns whether
+    /// the activation was accepted.
+    pub async fn respond_activation<H>(
+        &mut self,
+        request: &RoutingActivationRequest,
+        entity_address: [u8; 2],
+        testers: &LogicalAddressTable<H>,
+    ) -> Result<bool, SocketSendError> {
+        let accepted = testers.contains(request.source_address);
+        let activation_code = if accepted {
+            ActivationCode::SuccessfullyActivated
+        } else {
+            ActivationCode::DeniedUnknownSourceAddress
+        };
+
+        self.send(DoipPayload::RoutingActivationResponse(
+            RoutingActivationResponse {
+                logical_address: request.source_address,
+                source_address: entity_address,
+                activation_code,
+                buffer: [0, 0, 0, 0],
+            },
+        ))
+        .await?;
+
+        Ok(accepted)
+    }
+
+    /// Acknowledge a Diagnostic Message received as a DoIP entity
+    ///
+    /// If the target address is registered in `table` a Diagnostic Message Ack
+    /// is sent and the target's handler returned, otherwise a Diagnostic
+    /// Message Nack with an unknown target address is sent and `None` returned.
+    pub async fn route_diagnostic<'a, H>(
+        &mut self,
+        msg: &DiagnosticMessage,
+        table: &'a LogicalAddressTable<H>,
+    ) -> Result<Option<&'a H>, SocketSendError> {
+        let handler = table.lookup(msg.target_address);
+
+        let response = match handler {
+            Some(_) => DoipPayload::DiagnosticMessageAck(DiagnosticMessageAck {
+                source_address: msg.target_address,
+                target_address: msg.source_address,
+                ack_code: DiagnosticAckCode::Acknowledged,
+            }),
+            None => DoipPayload::DiagnosticMessageNack(DiagnosticMessageNack {
+                source_address: msg.target_address,
+                target_address: msg.source_address,
+                nack_code: DiagnosticNackCode::UnknownTargetAddress,
+            }),
+        };
+
+        self.send(response).await?;
+
+        Ok(handler)
+    }
+
+    /// Join a Read Half and Write Half split from the same TCP Stream
+    ///
+    /// Equivalent to `TcpStreamReadHalf::reunite`, returning both halves in a
+    /// `ReuniteError` if they were split from different streams.
+    pub fn reunite(
+        read: TcpStreamReadHalf<T>,
+        write: TcpStreamWriteHalf<T>,
+    ) -> Result<TcpStream<T>, ReuniteError<T>> {
+        read.reunite(write)
+    }
+
+    /// Splits the TCP Stream into a Read Half and Write Half
+    ///
+    /// Bytes already buffered on the TCP Stream are carried over to the halves,
+    /// so splitting after a partial frame has been received or frames have
+    /// been fed but not flushed loses no data. Frames queued by `send_request`
+    /// are returned first by the read half. The halves can be joined again
+    /// with `TcpStream::reunite`.
+    pub fn into_split(self) -> (TcpStreamReadHalf<T>, TcpStreamWriteHalf<T>) {
+        let addrs = (self.addrs_of)(self.io.get_ref());
+        let parts = self.io.into_parts();
+
+        let (r_half, w_half) = tokio::io::split(parts.io);
+
+        // The read half keeps the codec so a frame being discarded or the OEM
+        // specific bytes received carry over
+        let mut write = FramedWrite::new(w_half, parts.codec.encoder());
+        *write.write_buffer_mut() = parts.write_buf;
+        let mut read = FramedRead::new(r_half, parts.codec);
+        *read.read_buffer_mut() = parts.read_buf;
+        let error = Arc::new(ErrorSignal::default());
+
+        (
+            TcpStreamReadHalf::from_framed(read, self.config.clone())
+                .with_queue(self.queue)
+                .with_permit(self.permit.clone())
+                .with_error_signal(error.clone())
+                .with_addrs(addrs, self.addrs_of),
+            TcpStreamWriteHalf::from_framed(write, self.config)
+                .with_permit(self.permit)
+                .with_error_signal(error)
+                .with_addrs(addrs)
+                .with_dry_run_bytes(self.dry_run_bytes),
+        )
+    }
+
+    /// Replace the inner transport, returning the previous one
+    ///
+    /// Frames already sent are flushed to the previous socket first, the
+    /// config and any user context are kept, as is the codec state such as the
+    /// OEM specific bytes last received. Bytes received from the previous
+    /// socket which have not yet formed a complete frame are discarded, along
+    /// with the remainder of an oversized frame being skipped.
+    pub async fn replace_io(&mut self, new: T) -> io::Result<T> {
+        self.io.flush().await.map_err(|err| match err {
+            CodecError::IoError(err) => err,
+            err => io::Error::other(err.to_string()),
+        })?;
+
+        let codec = FrameCodec::from_config(&self.config);
+        let old = std::mem::replace(&mut self.io, Framed::new(new, codec)).into_parts();
+        self.send_pending = false;
+
+        let mut codec = old.codec;
+        codec.clear_discard();
+        *self.io.codec_mut() = codec;
+
+        Ok(old.io)
+    }
+
+    /// Get a reference to the inner transport, usually a Tokio TCP Stream
+    pub fn get_stream_ref(&self) -> &T {
+        self.io.get_ref()
+    }
+
+    /// Run `f` with mutable access to the inner transport
+    ///
+    /// Intended for socket options and other setup the crate does not expose.
+    /// Bytes read or written through the transport bypass the codec, so doing
+    /// so while frames are buffered corrupts the framing, see `into_async_io`
+    /// to switch to raw IO cleanly.
+    pub fn with_raw<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        f(self.io.get_mut())
+    }
+
+    /// Consume the DoIP TCP Stream, returning the inner transport for raw
+    /// async IO
+    ///
+    /// An alias of `into_socket` for layering another codec or TLS over a
+    /// connection set up by this crate. To switch without losing data, `flush`
+    /// first and check `read_buffer` is empty, any bytes still buffered by the
+    /// codec are dropped.
+    pub fn into_async_io(self) -> T {
+        self.into_socket()
+    }
+
+    /// Access the inner transport, consumes the DoIP TCP Stream
+    ///
+    /// Bytes buffered by the codec are dropped, this includes partially
+    /// received frames and frames fed but not yet flushed.
+    pub fn into_socket(self) -> T {
+        self.io.into_inner()
+    }
+}
+
+/// Yields each frame read off the stream
+///
+/// Unlike `read`, no frames are answered automatically and the read timeout is
+/// not applied, so Alive Check Requests and status queries are yielded like
+/// any other frame and oversized frames are always an error. Frames are still
+/// recorded in the frame history and metrics, and checked against the
+/// protocol version as in `read` if strict version checking is enabled.
+impl<T> Stream for TcpStream<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    type Item = Result<DoipMessage, CodecError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        if let Some(msg) = this.queue.pop_front() {
+            return Poll::Ready(Some(Ok(msg)));
+        }
+
+        let res = match ready!(Pin::new(&mut this.io).poll_next(cx)) {
+            Some(Ok(Frame::Message(msg))) => Ok(msg),
+            Some(Ok(Frame::Oversized(payload_length))) => Err(oversized_error(payload_length)),
+            Some(Err(err)) => Err(truncated_frame(err, this.io.read_buffer().len())),
+            None => return Poll::Ready(None),
+        };
+
+        let res = res.and_then(|msg| {
+            this.record_received(&msg);
+            this.config.check_version(&msg).map(|()| msg)
+        });
+
+        Poll::Ready(Some(res))
+    }
+}
+
+/// Sends each payload as a DoIP frame using the configured protocol version
+///
+/// Payloads are checked as in `send`, but the send rate limit and minimum send
+/// gap are not applied. Useful for forwarding a stream of payloads with
+/// `SinkExt::send_all`, see `TcpStream::send_all` for an iterator.
+impl<T> Sink<DoipPayload> for TcpStream<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    type Error = SocketSendError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.get_mut().io)
+            .poll_ready(cx)
+            .map_err(SocketSendError::from)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: DoipPayload) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        let msg = this.config.build_message(item)?;
+        this.start_frame(msg)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        TcpStream::poll_flush(self.get_mut(), cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        TcpStream::poll_close(self.get_mut(), cx)
+    }
+}
+
+/// Wraps a Tokio TCP Stream, as `TcpStream::new`
+impl From<TokioTcpStream> for TcpStream {
+    fn from(io: TokioTcpStream) -> Self {
+        TcpStream::new(io)
+    }
+}
+
+/// Unwraps the Tokio TCP Stream, as `TcpStream::into_socket`
+///
+/// Bytes buffered by the codec are dropped.
+impl From<TcpStream> for TokioTcpStream {
+    fn from(stream: TcpStream) -> Self {
+        stream.into_socket()
+    }
+}
+
+#[cfg(test)]
+mod test_tcp_stream {
+    use doip_codec::Error as CodecError;
+    use doip_definitions::{
+        builder::DoipMessageBuilder,
+        header::{PayloadType, ProtocolVersion},
+        message::DoipMessage,
+        payload::{
+            ActionCode, ActivationCode, ActivationType, AliveCheckRequest, AliveCheckResponse,
+            DiagnosticAckCode, DiagnosticMessage, DiagnosticMessageAck, DiagnosticMessageNack,
+            DiagnosticNackCode, DoipPayload, EntityStatusRequest, EntityStatusResponse,
+            GenericNack, NackCode, NodeType, RoutingActivationRequest, RoutingActivationResponse,
+            SyncStatus, VehicleAnnouncementMessage, VehicleIdentificationRequest,
+        },
+    };
+    use futures::StreamExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_util::codec::Encoder;
+
+    use crate::{
+        error::{SocketError, SocketReadError, SocketSendError},
+        tcp::{
+            tcp_stream::{TcpStream, DOIP_UDS_STREAM_IDLE_TIMEOUT},
+            Direction, LogicalAddressTable, RetryPolicy, RoutingActivationOutcome,
+        },
+        SocketConfig,
+    };
+
+    #[tokio::test]
+    async fn test_connect() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let stream = TcpStream::connect(addr).await;
+        dbg!(&stream);
+
+        assert!(stream.is_ok());
+
+        // Cleanup
+        drop(listener);
+    }
+
+    #[tokio::test]
+    async fn test_send() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await;
+        let listener = listener.unwrap();
+
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).await;
+
+        let mut stream = stream.unwrap();
+
+        let (mut socket, _) = listener.accept().await.unwrap();
+
+        let routing_activation_payload =
+            DoipPayload::RoutingActivationRequest(RoutingActivationRequest {
+                source_address: [0x0e, 0x80],
+                activation_type: ActivationType::Default,
+                buffer: [0, 0, 0, 0],
+            });
+        let routing_activation = DoipMessageBuilder::new()
+            .protocol_version(stream.config.protocol_version)
+            .payload(routing_activation_payload.clone())
+            .build();
+
+        let mut codec = doip_codec::DoipCodec {};
+        let mut bytes = tokio_util::bytes::BytesMut::new();
+        codec.encode(routing_activation, &mut bytes).unwrap();
+
+        let _ = &stream.send(routing_activation_payload).await;
+
+        let mut buffer = [0; 64];
+        let read = socket.read(&mut buffer).await.unwrap();
+
+        assert_eq!(&buffer[8..read], &bytes[8..read]);
+
+        // Cleanup
+        drop(socket);
+    }
+
+    #[tokio::test]
+    async fn test_read() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let routing_activation = DoipPayload::RoutingActivationRequest(RoutingActivationRequest {
+            source_address: [0x0e, 0x80],
+            activation_type: ActivationType::Default,
+            buffer: [0, 0, 0, 0],
+        });
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await;
+        let listener = listener.unwrap();
+
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await;
+        let mut client = client.unwrap();
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        let _ = &client.send(routing_activation).await;
+        let _ = server.read().await.unwrap().unwrap();
+
+        let routing_activation_res =
+            DoipPayload::RoutingActivationResponse(RoutingActivationResponse {
+                logical_address: [0x0e, 0x80],
+                source_address: [0x14, 0x11],
+                activation_code: ActivationCode::SuccessfullyActivated,
+                buffer: [0, 0, 0, 0],
+            });
+
+        let _ = server.send(routing_activation_res.clone()).await;
+        let echo = client.read().await.unwrap().unwrap();
+
+        assert_eq!(echo.payload, routing_activation_res)
+    }
+
+    #[tokio::test]
+    async fn test_into_split() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let routing_activation = DoipPayload::RoutingActivationRequest(RoutingActivationRequest {
+            source_address: [0x0e, 0x80],
+            activation_type: ActivationType::Default,
+            buffer: [0, 0, 0, 0],
+        });
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await;
+        let listener = listener.unwrap();
+
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await;
+        let client = client.unwrap();
+        let (mut read, mut write) = client.into_split();
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        let _ = write.send(routing_activation).await;
+        let _ = server.read().await.unwrap().unwrap();
+
+        let routing_activation_res =
+            DoipPayload::RoutingActivationResponse(RoutingActivationResponse {
+                logical_address: [0x0e, 0x80],
+                source_address: [0x14, 0x11],
+                activation_code: ActivationCode::SuccessfullyActivated,
+                buffer: [0, 0, 0, 0],
+            });
+
+        let _ = server.send(routing_activation_res.clone()).await;
+        let echo = read.read().await.unwrap().unwrap();
+
+        assert_eq!(echo.payload, routing_activation_res)
+    }
+
+    #[tokio::test]
+    async fn test_activate_routing_pending_confirmation() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        let routing_activation_res = RoutingActivationResponse {
+            logical_address: [0x0e, 0x80],
+            source_address: [0x14, 0x11],
+            activation_code: ActivationCode::ActivatedConfirmationRequired,
+            buffer: [0, 0, 0, 0],
+        };
+
+        let gateway = tokio::spawn(async move {
+            let _ = server.read().await.unwrap().unwrap();
+            server
+                .send(DoipPayload::RoutingActivationResponse(
+                    routing_activation_res,
+                ))
+                .await
+                .unwrap();
+        });
+
+        let outcome = client
+            .activate_routing([0x0e, 0x80], ActivationType::Default)
+            .await
+            .unwrap();
+
+        gateway.await.unwrap();
+        assert_eq!(
+            outcome,
+            RoutingActivationOutcome::PendingConfirmation(routing_activation_res)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_activate() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        let response = |activation_code| RoutingActivationResponse {
+            logical_address: [0x0e, 0x80],
+            source_address: [0x14, 0x11],
+            activation_code,
+            buffer: [0, 0, 0, 0],
+        };
+
+        let gateway = tokio::spawn(async move {
+            for activation_code in [
+                ActivationCode::ActivatedConfirmationRequired,
+                ActivationCode::SuccessfullyActivated,
+            ] {
+                let _ = server.read().await.unwrap().unwrap();
+                server
+                    .send(DoipPayload::AliveCheckRequest(AliveCheckRequest {}))
+                    .await
+                    .unwrap();
+                server
+                    .send(DoipPayload::RoutingActivationResponse(response(
+                        activation_code,
+                    )))
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let err = client
+            .activate([0x0e, 0x80], ActivationType::Default)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.activation_code(),
+            Some(ActivationCode::ActivatedConfirmationRequired)
+        );
+
+        let res = client
+            .activate([0x0e, 0x80], ActivationType::Default)
+            .await
+            .unwrap();
+        assert_eq!(res, response(ActivationCode::SuccessfullyActivated));
+
+        gateway.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_respond_activation_oem_specific() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        const OEM_SPECIFIC: [u8; 4] = [0xde, 0xad, 0xbe, 0xef];
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut config = SocketConfig::default();
+        config.set_oem_specific(Some(OEM_SPECIFIC));
+        let mut server = TcpStream::new(socket).with_config(config);
+
+        let gateway = tokio::spawn(async move {
+            let mut testers = LogicalAddressTable::new();
+            testers.register([0x0e, 0x80], ());
+
+            let msg = server.read().await.unwrap().unwrap();
+            let DoipPayload::RoutingActivationRequest(req) = msg.payload else {
+                panic!("Expected Routing Activation Request, got {msg:?}");
+            };
+
+            assert_eq!(server.received_oem_specific(), None);
+            server
+                .respond_activation(&req, [0x14, 0x11], &testers)
+                .await
+                .unwrap()
+        });
+
+        let outcome = client
+            .activate_routing([0x0e, 0x80], ActivationType::Default)
+            .await
+            .unwrap();
+
+        assert!(gateway.await.unwrap());
+        assert!(matches!(outcome, RoutingActivationOutcome::Activated(_)));
+        assert_eq!(client.received_oem_specific(), Some(OEM_SPECIFIC));
+    }
+
+    #[tokio::test]
+    async fn test_activation_oem_specific_split() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        const OEM_SPECIFIC: [u8; 4] = [0xde, 0xad, 0xbe, 0xef];
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+
+        let mut config = SocketConfig::default();
+        config.set_oem_specific(Some(OEM_SPECIFIC));
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap()
+            .with_config(config.clone());
+        let (mut client_read, mut client_write) = client.into_split();
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let server = TcpStream::new(socket).with_config(config);
+        let (mut server_read, mut server_write) = server.into_split();
+
+        client_write
+            .send(DoipPayload::RoutingActivationRequest(
+                RoutingActivationRequest {
+                    source_address: [0x0e, 0x80],
+                    activation_type: ActivationType::Default,
+                    buffer: [0, 0, 0, 0],
+                },
+            ))
+            .await
+            .unwrap();
+
+        server_read.read().await.unwrap().unwrap();
+        assert_eq!(server_read.received_oem_specific(), Some(OEM_SPECIFIC));
+
+        server_write
+            .send(DoipPayload::RoutingActivationResponse(
+                RoutingActivationResponse {
+                    logical_address: [0x0e, 0x80],
+                    source_address: [0x14, 0x11],
+                    activation_code: ActivationCode::SuccessfullyActivated,
+                    buffer: [0, 0, 0, 0],
+                },
+            ))
+            .await
+            .unwrap();
+
+        let msg = client_read.read().await.unwrap().unwrap();
+        assert_eq!(
+            msg.header.payload_type,
+            PayloadType::RoutingActivationResponse
+        );
+        assert_eq!(client_read.received_oem_specific(), Some(OEM_SPECIFIC));
+
+        let client = client_read.reunite(client_write).unwrap();
+        assert_eq!(client.received_oem_specific(), Some(OEM_SPECIFIC));
+    }
+
+    #[tokio::test]
+    async fn test_connect_negotiate() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let gateway = tokio::spawn(async move {
+            let mut testers = LogicalAddressTable::new();
+            testers.register([0x0e, 0x80], ());
+
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                let mut server = TcpStream::new(socket);
+
+                let msg = server.read().await.unwrap().unwrap();
+                if msg.header.protocol_version != ProtocolVersion::Iso13400_2012 {
+                    server
+                        .send(DoipPayload::GenericNack(GenericNack {
+                            nack_code: NackCode::IncorrectPatternFormat,
+                        }))
+                        .await
+                        .unwrap();
+                    continue;
+                }
+
+                let DoipPayload::RoutingActivationRequest(req) = msg.payload else {
+                    panic!("Expected Routing Activation Request, got {msg:?}");
+                };
+                server
+                    .respond_activation(&req, [0x14, 0x11], &testers)
+                    .await
+                    .unwrap();
+
+                return server;
+            }
+        });
+
+        let (client, outcome) = TcpStream::connect_negotiate(
+            addr,
+            &[
+                ProtocolVersion::Iso13400_2019,
+                ProtocolVersion::Iso13400_2012,
+            ],
+            [0x0e, 0x80],
+            std::time::Duration::from_secs(1),
+        )
+        .await
+        .unwrap();
+
+        let _server = gateway.await.unwrap();
+        assert!(matches!(outcome, RoutingActivationOutcome::Activated(_)));
+        assert_eq!(
+            client.config().protocol_version(),
+            ProtocolVersion::Iso13400_2012
+        );
+    }
+
+    #[tokio::test]
+    async fn test_alive_check_autoresponse() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+        let diagnostic = DoipPayload::DiagnosticMessage(DiagnosticMessage {
+            source_address: [0x14, 0x11],
+            target_address: [0x0e, 0x80],
+            message: vec![0x50, 0x01],
+        });
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        client.enable_alive_check_autoresponse([0x0e, 0x80]);
+
+        server.send(alive_check.clone()).await.unwrap();
+        server.send(diagnostic.clone()).await.unwrap();
+
+        assert_eq!(client.read().await.unwrap().unwrap().payload, diagnostic);
+        assert_eq!(
+            server.read().await.unwrap().unwrap().payload,
+            DoipPayload::AliveCheckResponse(AliveCheckResponse {
+                source_address: [0x0e, 0x80]
+            })
+        );
+
+        client.disable_alive_check_autoresponse();
+
+        server.send(alive_check.clone()).await.unwrap();
+        assert_eq!(client.read().await.unwrap().unwrap().payload, alive_check);
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_config() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+
+        let mut config = SocketConfig::default();
+        config.set_protocol_version(ProtocolVersion::Iso13400_2010);
+        let mut client = TcpStream::connect_with_config(listener.local_addr().unwrap(), config)
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        assert_eq!(client.protocol_version(), ProtocolVersion::Iso13400_2010);
+        client.send(alive_check.clone()).await.unwrap();
+        assert_eq!(
+            server
+                .read()
+                .await
+                .unwrap()
+                .unwrap()
+                .header
+                .protocol_version,
+            ProtocolVersion::Iso13400_2010
+        );
+
+        client.set_protocol_version(ProtocolVersion::Iso13400_2019);
+        client.send(alive_check).await.unwrap();
+        assert_eq!(
+            server
+                .read()
+                .await
+                .unwrap()
+                .unwrap()
+                .header
+                .protocol_version,
+            ProtocolVersion::Iso13400_2019
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_and_confirm() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        futures::SinkExt::feed(&mut client, alive_check.clone())
+            .await
+            .unwrap();
+        assert!(client.write_buffer_len() > 0);
+
+        client.send_and_confirm(alive_check.clone()).await.unwrap();
+        assert_eq!(client.write_buffer_len(), 0);
+
+        for _ in 0..2 {
+            assert_eq!(server.read().await.unwrap().unwrap().payload, alive_check);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_nodelay() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+
+        client.set_nodelay(true).unwrap();
+        assert!(client.nodelay().unwrap());
+        client.set_nodelay(false).unwrap();
+        assert!(!client.nodelay().unwrap());
+
+        let mut config = SocketConfig::default();
+        config.set_nodelay(Some(true));
+        let server = TcpStream::with_listener_config(socket, config, None);
+        assert!(server.nodelay().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_sink_send_all() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        let mut payloads = futures::stream::iter(vec![alive_check.clone(); 3]).map(Ok);
+        futures::SinkExt::send_all(&mut client, &mut payloads)
+            .await
+            .unwrap();
+
+        for _ in 0..3 {
+            assert_eq!(server.read().await.unwrap().unwrap().payload, alive_check);
+        }
+        assert_eq!(client.metrics().frames_sent(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_stream_combinators() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let server = TcpStream::new(socket);
+
+        for _ in 0..3 {
+            client.send(alive_check.clone()).await.unwrap();
+        }
+        drop(client);
+
+        let payloads: Vec<_> = server
+            .filter_map(|res| async move { res.ok() })
+            .map(|msg| msg.payload)
+            .collect()
+            .await;
+
+        assert_eq!(payloads, vec![alive_check; 3]);
+    }
+
+    #[tokio::test]
+    async fn test_with_session_closes() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let gateway = tokio::spawn(async move {
+            let mut testers = LogicalAddressTable::new();
+            testers.register([0x0e, 0x80], ());
+
+            for _ in 0..2 {
+                let (socket, _) = listener.accept().await.unwrap();
+                let mut server = TcpStream::new(socket);
+
+                let msg = server.read().await.unwrap().unwrap();
+                let DoipPayload::RoutingActivationRequest(req) = msg.payload else {
+                    panic!("Expected Routing Activation Request, got {msg:?}");
+                };
+                server
+                    .respond_activation(&req, [0x14, 0x11], &testers)
+                    .await
+                    .unwrap();
+
+                assert!(server.read().await.unwrap().is_ok());
+                assert!(server.read().await.is_none());
+            }
+        });
+
+        let res = TcpStream::with_session(
+            addr,
+            [0x0e, 0x80],
+            ActivationType::Default,
+            std::time::Duration::from_secs(1),
+            async |stream| stream.send(alive_check.clone()).await,
+        )
+        .await;
+        assert!(res.is_ok());
+
+        let res: Result<(), _> = TcpStream::with_session(
+            addr,
+            [0x0e, 0x80],
+            ActivationType::Default,
+            std::time::Duration::from_secs(1),
+            async |stream| {
+                stream.send(alive_check.clone()).await.unwrap();
+                Err("session failed")
+            },
+        )
+        .await;
+        assert!(matches!(
+            res,
+            Err(crate::error::SessionError::Session("session failed"))
+        ));
+
+        gateway.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_detect_supported_versions() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let gateway = tokio::spawn(async move {
+            let mut testers = LogicalAddressTable::new();
+            testers.register([0x0e, 0x80], ());
+
+            for _ in 0..3 {
+                let (socket, _) = listener.accept().await.unwrap();
+                let mut server = TcpStream::new(socket);
+
+                let msg = server.read().await.unwrap().unwrap();
+                if msg.header.protocol_version == ProtocolVersion::Iso13400_2010 {
+                    server
+                        .send(DoipPayload::GenericNack(GenericNack {
+                            nack_code: NackCode::IncorrectPatternFormat,
+                        }))
+                        .await
+                        .unwrap();
+                    continue;
+                }
+
+                let DoipPayload::RoutingActivationRequest(req) = msg.payload else {
+                    panic!("Expected Routing Activation Request, got {msg:?}");
+                };
+                server
+                    .respond_activation(&req, [0x14, 0x11], &testers)
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let supported = TcpStream::detect_supported_versions(
+            addr,
+            [0x0e, 0x80],
+            &[
+                ProtocolVersion::Iso13400_2010,
+                ProtocolVersion::Iso13400_2012,
+                ProtocolVersion::Iso13400_2019,
+            ],
+            std::time::Duration::from_secs(1),
+        )
+        .await;
+
+        gateway.await.unwrap();
+        assert_eq!(
+            supported,
+            vec![
+                ProtocolVersion::Iso13400_2012,
+                ProtocolVersion::Iso13400_2019
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_poll_send() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let routing_activation = DoipPayload::RoutingActivationRequest(RoutingActivationRequest {
+            source_address: [0x0e, 0x80],
+            activation_type: ActivationType::Default,
+            buffer: [0, 0, 0, 0],
+        });
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        std::future::poll_fn(|cx| client.poll_send(cx, &routing_activation))
+            .await
+            .unwrap();
+
+        let msg = server.read().await.unwrap().unwrap();
+        assert_eq!(msg.payload, routing_activation);
+    }
+
+    #[tokio::test]
+    async fn test_poll_send_records_after_flush() {
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let (io, mut peer) = tokio::io::duplex(4);
+        let mut config = SocketConfig::default();
+        config.set_frame_history(Some(4));
+        let mut client = TcpStream::from_io(io).with_config(config);
+
+        let poll =
+            std::future::poll_fn(|cx| std::task::Poll::Ready(client.poll_send(cx, &alive_check)))
+                .await;
+        assert!(poll.is_pending());
+        assert!(client.recent_frames().is_empty());
+        assert_eq!(client.metrics().sent_of(PayloadType::AliveCheckRequest), 0);
+
+        let mut buf = [0u8; 8];
+        let (res, read) = tokio::join!(
+            std::future::poll_fn(|cx| client.poll_send(cx, &alive_check)),
+            peer.read_exact(&mut buf)
+        );
+        res.unwrap();
+        read.unwrap();
+
+        assert_eq!(client.recent_frames().len(), 1);
+        assert_eq!(client.metrics().sent_of(PayloadType::AliveCheckRequest), 1);
+    }
+
+    #[tokio::test]
+    async fn test_send_connection_closed() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let routing_activation = DoipPayload::RoutingActivationRequest(RoutingActivationRequest {
+            source_address: [0x0e, 0x80],
+            activation_type: ActivationType::Default,
+            buffer: [0, 0, 0, 0],
+        });
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        let (socket, _) = listener.accept().await.unwrap();
+        drop(socket);
+
+        // The first writes may still be accepted by the OS before the reset
+        // from the closed peer arrives.
+        let mut result = Ok(());
+        for _ in 0..10 {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            result = client.send(routing_activation.clone()).await;
+
+            if result.is_err() {
+                break;
+            }
+        }
+
+        assert!(matches!(result, Err(SocketSendError::ConnectionClosed)));
+    }
+
+    #[tokio::test]
+    async fn test_connect_from_announcement() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let announcement = VehicleAnnouncementMessage {
+            vin: [0x31; 17],
+            logical_address: [0x10, 0x01],
+            eid: [0x00, 0x01, 0x02, 0x03, 0x04, 0x05],
+            gid: [0x00, 0x01, 0x02, 0x03, 0x04, 0x05],
+            further_action: ActionCode::RoutingActivationRequired,
+            vin_gid_sync: Some(SyncStatus::VinGidSynchronized),
+        };
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let source = "127.0.0.1:13400".parse().unwrap();
+
+        let (stream, logical_address) = TcpStream::connect_from_announcement(
+            &announcement,
+            source,
+            port,
+            std::time::Duration::from_secs(1),
+        )
+        .await
+        .unwrap();
+
+        let (_, peer) = listener.accept().await.unwrap();
+
+        assert_eq!(logical_address, [0x10, 0x01]);
+        assert_eq!(stream.get_stream_ref().local_addr().unwrap(), peer);
+    }
+
+    #[tokio::test]
+    async fn test_send_rate_limit() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        const RATE: u32 = 10;
+        let routing_activation = DoipPayload::RoutingActivationRequest(RoutingActivationRequest {
+            source_address: [0x0e, 0x80],
+            activation_type: ActivationType::Default,
+            buffer: [0, 0, 0, 0],
+        });
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+
+        let mut config = SocketConfig::default();
+        config.set_send_rate_limit(Some(RATE));
+
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap()
+            .with_config(config);
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        let start = std::time::Instant::now();
+
+        // The first second's worth of frames are a burst, the remaining three
+        // are each delayed by 1 / RATE.
+        for _ in 0..RATE + 3 {
+            client.send(routing_activation.clone()).await.unwrap();
+        }
+
+        assert!(start.elapsed() >= std::time::Duration::from_millis(250));
+
+        for _ in 0..RATE + 3 {
+            server.read().await.unwrap().unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_config_propagation() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut config = SocketConfig::default();
+        config.set_protocol_version(ProtocolVersion::Iso13400_2010);
+
+        let first = TcpStream::connect(addr).await.unwrap().with_config(config);
+        let (_first_server, _) = listener.accept().await.unwrap();
+
+        let cloned = first.config();
+        assert_eq!(cloned.protocol_version(), ProtocolVersion::Iso13400_2010);
+
+        let mut second = TcpStream::connect(addr).await.unwrap().with_config(cloned);
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        second.send(alive_check).await.unwrap();
+        let msg = server.read().await.unwrap().unwrap();
+
+        assert_eq!(msg.header.protocol_version, ProtocolVersion::Iso13400_2010);
+    }
+
+    #[tokio::test]
+    async fn test_send_diagnostics_await_all() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        const TESTER: [u8; 2] = [0x0e, 0x80];
+        const ENGINE: [u8; 2] = [0x14, 0x11];
+        const GEARBOX: [u8; 2] = [0x14, 0x12];
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let server = tokio::spawn(async move {
+            let mut server = TcpStream::new(socket);
+            server.read().await.unwrap().unwrap();
+            server.read().await.unwrap().unwrap();
+
+            // Answered out of order, so the acks must be matched by address.
+            for ecu in [GEARBOX, ENGINE] {
+                server
+                    .send(DoipPayload::DiagnosticMessageAck(DiagnosticMessageAck {
+                        source_address: ecu,
+                        target_address: TESTER,
+                        ack_code: DiagnosticAckCode::Acknowledged,
+                    }))
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let msgs = [ENGINE, GEARBOX]
+            .into_iter()
+            .map(|ecu| DiagnosticMessage {
+                source_address: TESTER,
+                target_address: ecu,
+                message: vec![0x3e, 0x00],
+            })
+            .collect();
+
+        let results = client
+            .send_diagnostics_await_all(msgs, std::time::Duration::from_secs(1))
+            .await;
+        server.await.unwrap();
+
+        let acked: Vec<_> = results
+            .into_iter()
+            .map(|res| res.unwrap().source_address)
+            .collect();
+        assert_eq!(acked, vec![ENGINE, GEARBOX]);
+    }
+
+    #[tokio::test]
+    async fn test_send_diagnostics_await_all_keeps_frames() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        const TESTER: [u8; 2] = [0x0e, 0x80];
+        const ENGINE: [u8; 2] = [0x14, 0x11];
+        const GEARBOX: [u8; 2] = [0x14, 0x12];
+        let response = DoipPayload::DiagnosticMessage(DiagnosticMessage {
+            source_address: ENGINE,
+            target_address: TESTER,
+            message: vec![0x7e, 0x00],
+        });
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut config = SocketConfig::default();
+        config.set_strict_version(true);
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap()
+            .with_config(config);
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let sent = response.clone();
+        let server = tokio::spawn(async move {
+            let mut server = TcpStream::new(socket);
+            server.read().await.unwrap().unwrap();
+            server.read().await.unwrap().unwrap();
+
+            let ack = |ecu| {
+                DoipPayload::DiagnosticMessageAck(DiagnosticMessageAck {
+                    source_address: ecu,
+                    target_address: TESTER,
+                    ack_code: DiagnosticAckCode::Acknowledged,
+                })
+            };
+
+            // The engine answers before the gearbox acks, and a frame of the
+            // wrong version arrives in between
+            server.send(ack(ENGINE)).await.unwrap();
+            server.send(sent).await.unwrap();
+            server.set_protocol_version(ProtocolVersion::Iso13400_2010);
+            server
+                .send(DoipPayload::AliveCheckRequest(AliveCheckRequest {}))
+                .await
+                .unwrap();
+            server.set_protocol_version(ProtocolVersion::Iso13400_2012);
+            server.send(ack(GEARBOX)).await.unwrap();
+        });
+
+        let msgs = [ENGINE, GEARBOX]
+            .into_iter()
+            .map(|ecu| DiagnosticMessage {
+                source_address: TESTER,
+                target_address: ecu,
+                message: vec![0x3e, 0x00],
+            })
+            .collect();
+
+        let results = client
+            .send_diagnostics_await_all(msgs, std::time::Duration::from_secs(1))
+            .await;
+        server.await.unwrap();
+
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(client.read().await.unwrap().unwrap().payload, response);
+    }
+
+    #[tokio::test]
+    async fn test_send_request_ignores_queued_response() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        const TESTER: [u8; 2] = [0x0e, 0x80];
+        const ECU: [u8; 2] = [0x14, 0x11];
+        let response = |message| {
+            DoipPayload::DiagnosticMessage(DiagnosticMessage {
+                source_address: ECU,
+                target_address: TESTER,
+                message,
+            })
+        };
+        let ack = DoipPayload::DiagnosticMessageAck(DiagnosticMessageAck {
+            source_address: ECU,
+            target_address: TESTER,
+            ack_code: DiagnosticAckCode::Acknowledged,
+        });
+        let request = DiagnosticMessage {
+            source_address: TESTER,
+            target_address: ECU,
+            message: vec![0x3e, 0x00],
+        };
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let server = tokio::spawn(async move {
+            let mut server = TcpStream::new(socket);
+
+            // The response to the first request overtakes its ack, so is queued
+            server.read().await.unwrap().unwrap();
+            server.send(response(vec![0x7e, 0x00])).await.unwrap();
+            server.send(ack.clone()).await.unwrap();
+
+            server.read().await.unwrap().unwrap();
+            server.send(ack).await.unwrap();
+            server.send(response(vec![0x7e, 0x01])).await.unwrap();
+        });
+
+        let results = client
+            .send_diagnostics_await_all(vec![request.clone()], std::time::Duration::from_secs(1))
+            .await;
+        assert!(results[0].is_ok());
+
+        let res = client
+            .send_request(request, std::time::Duration::from_secs(1))
+            .await
+            .unwrap();
+        server.await.unwrap();
+
+        assert_eq!(res.message, vec![0x7e, 0x01]);
+        assert_eq!(
+            client.read().await.unwrap().unwrap().payload,
+            response(vec![0x7e, 0x00])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_uds_stream() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        const TESTER: [u8; 2] = [0x0e, 0x80];
+        const ECU: [u8; 2] = [0x14, 0x11];
+        const OTHER_ECU: [u8; 2] = [0x14, 0x12];
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let server = tokio::spawn(async move {
+            let mut server = TcpStream::new(socket);
+            let req = server.read().await.unwrap().unwrap();
+
+            server
+                .send(DoipPayload::DiagnosticMessageAck(DiagnosticMessageAck {
+                    source_address: ECU,
+                    target_address: TESTER,
+                    ack_code: DiagnosticAckCode::Acknowledged,
+                }))
+                .await
+                .unwrap();
+
+            // A Nack for another request must not end the stream
+            server
+                .send(DoipPayload::DiagnosticMessageNack(DiagnosticMessageNack {
+                    source_address: OTHER_ECU,
+                    target_address: TESTER,
+                    nack_code: DiagnosticNackCode::UnknownTargetAddress,
+                }))
+                .await
+                .unwrap();
+
+            for message in [vec![0x62, 0xf1, 0x90], vec![0x62, 0xf1, 0x91]] {
+                server
+                    .send(DoipPayload::DiagnosticMessage(DiagnosticMessage {
+                        source_address: ECU,
+                        target_address: TESTER,
+                        message,
+                    }))
+                    .await
+                    .unwrap();
+            }
+
+            req.payload
+        });
+
+        let responses: Vec<_> = client
+            .send_uds_stream(
+                TESTER,
+                ECU,
+                vec![0x22, 0xf1, 0x90],
+                DOIP_UDS_STREAM_IDLE_TIMEOUT,
+            )
+            .collect()
+            .await;
+
+        let messages: Vec<_> = responses
+            .into_iter()
+            .map(|res| res.unwrap().message)
+            .collect();
+
+        assert_eq!(
+            messages,
+            vec![vec![0x62, 0xf1, 0x90], vec![0x62, 0xf1, 0x91]]
+        );
+        assert_eq!(
+            client.read().await.unwrap().unwrap().payload,
+            DoipPayload::DiagnosticMessageNack(DiagnosticMessageNack {
+                source_address: OTHER_ECU,
+                target_address: TESTER,
+                nack_code: DiagnosticNackCode::UnknownTargetAddress,
+            })
+        );
+        assert_eq!(
+            server.await.unwrap(),
+            DoipPayload::DiagnosticMessage(DiagnosticMessage {
+                source_address: TESTER,
+                target_address: ECU,
+                message: vec![0x22, 0xf1, 0x90],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connect_ready() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+
+        let mut client = TcpStream::connect_ready(
+            listener.local_addr().unwrap(),
+            std::time::Duration::from_secs(1),
+        )
+        .await
+        .unwrap();
+
+        let start = std::time::Instant::now();
+        client.send(alive_check.clone()).await.unwrap();
+        assert!(start.elapsed() < std::time::Duration::from_millis(50));
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        assert_eq!(server.read().await.unwrap().unwrap().payload, alive_check);
+    }
+
+    #[tokio::test]
+    async fn test_context() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        assert!(client.context::<String>().is_none());
+
+        client.set_context(String::from("WVWZZZ1JZXW000001"));
+
+        assert_eq!(
+            client.context::<String>().map(String::as_str),
+            Some("WVWZZZ1JZXW000001")
+        );
+        assert!(client.context::<u32>().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_read_truncated_frame() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let routing_activation = DoipMessageBuilder::new()
+            .payload(DoipPayload::RoutingActivationRequest(
+                RoutingActivationRequest {
+                    source_address: [0x0e, 0x80],
+                    activation_type: ActivationType::Default,
+                    buffer: [0, 0, 0, 0],
+                },
+            ))
+            .build();
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut bytes = tokio_util::bytes::BytesMut::new();
+        doip_codec::DoipCodec {}
+            .encode(routing_activation, &mut bytes)
+            .unwrap();
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let (mut socket, _) = listener.accept().await.unwrap();
+
+        socket.write_all(&bytes[..bytes.len() / 2]).await.unwrap();
+        socket.shutdown().await.unwrap();
+
+        let err = match client.read().await {
+            Some(Err(doip_codec::Error::IoError(err))) => err,
+            other => panic!("Expected truncated frame error, got {other:?}"),
+        };
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+
+        let mut clean = TcpStream::connect(addr).await.unwrap();
+        let (mut socket, _) = listener.accept().await.unwrap();
+        socket.shutdown().await.unwrap();
+
+        assert!(clean.read().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_route_diagnostic() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        const TESTER: [u8; 2] = [0x0e, 0x80];
+        const ECU: [u8; 2] = [0x14, 0x11];
+        const UNKNOWN_ECU: [u8; 2] = [0x14, 0x12];
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        let mut table = LogicalAddressTable::new();
+        table.register(ECU, "engine");
+
+        for target_address in [ECU, UNKNOWN_ECU] {
+            client
+                .send(DoipPayload::DiagnosticMessage(DiagnosticMessage {
+                    source_address: TESTER,
+                    target_address,
+                    message: vec![0x3e, 0x00],
+                }))
+                .await
+                .unwrap();
+        }
+
+        for expected in [Some(&"engine"), None] {
+            let msg = match server.read().await.unwrap().unwrap().payload {
+                DoipPayload::DiagnosticMessage(msg) => msg,
+                other => panic!("Expected Diagnostic Message, got {other:?}"),
+            };
+
+            assert_eq!(
+                server.route_diagnostic(&msg, &table).await.unwrap(),
+                expected
+            );
+        }
+
+        assert_eq!(
+            client.read().await.unwrap().unwrap().payload,
+            DoipPayload::DiagnosticMessageAck(DiagnosticMessageAck {
+                source_address: ECU,
+                target_address: TESTER,
+                ack_code: DiagnosticAckCode::Acknowledged,
+            })
+        );
+        assert_eq!(
+            client.read().await.unwrap().unwrap().payload,
+            DoipPayload::DiagnosticMessageNack(DiagnosticMessageNack {
+                source_address: UNKNOWN_ECU,
+                target_address: TESTER,
+                nack_code: DiagnosticNackCode::UnknownTargetAddress,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_oversize_nack() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let oversized = DoipPayload::DiagnosticMessage(DiagnosticMessage {
+            source_address: [0x0e, 0x80],
+            target_address: [0x14, 0x11],
+            message: vec![0x36; 64],
+        });
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+
+        let mut config = SocketConfig::default();
+        config.set_max_message_size(Some(16));
+        config.set_oversize_nack(Some(NackCode::MessageTooLarge));
+        let mut server = TcpStream::new(socket).with_config(config.clone());
+
+        client.send(oversized.clone()).await.unwrap();
+        client.send(alive_check.clone()).await.unwrap();
+
+        assert_eq!(server.read().await.unwrap().unwrap().payload, alive_check);
+        assert_eq!(
+            client.read().await.unwrap().unwrap().payload,
+            DoipPayload::GenericNack(GenericNack {
+                nack_code: NackCode::MessageTooLarge,
+            })
+        );
+
+        config.set_oversize_nack(None);
+        let mut server = server.with_config(config);
+
+        client.send(oversized).await.unwrap();
+        client.send(alive_check.clone()).await.unwrap();
+
+        assert!(server.read().await.unwrap().is_err());
+        assert_eq!(server.read().await.unwrap().unwrap().payload, alive_check);
+    }
+
+    #[tokio::test]
+    async fn test_oversize_length_header() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        const MAX_LENGTH_HEADER: [u8; 8] = [0x02, 0xfd, 0x80, 0x01, 0xff, 0xff, 0xff, 0xff];
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = tokio::net::TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+
+        let mut config = SocketConfig::default();
+        config.set_max_message_size(Some(4096));
+        let mut server = TcpStream::new(socket).with_config(config);
+
+        client.write_all(&MAX_LENGTH_HEADER).await.unwrap();
+        client.write_all(&[0x00; 1024]).await.unwrap();
+
+        let err = match server.read().await.unwrap() {
+            Err(CodecError::IoError(err)) => err,
+            other => panic!("Expected oversized frame error, got {other:?}"),
+        };
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(server.io.read_buffer().len() < 4096);
+    }
+
+    #[tokio::test]
+    async fn test_alive_check_flood() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+
+        let mut config = SocketConfig {
+            auto_alive_check: Some([0x14, 0x11]),
+            ..Default::default()
+        };
+        config.set_max_alive_check_rate(Some(2));
+        let mut server = TcpStream::new(socket).with_config(config);
+
+        for _ in 0..3 {
+            client.send(alive_check.clone()).await.unwrap();
+        }
+
+        let err = match server.read().await.unwrap() {
+            Err(CodecError::IoError(err)) => err,
+            other => panic!("Expected alive check flood, got {other:?}"),
+        };
+        assert!(matches!(
+            err.get_ref().and_then(|err| err.downcast_ref()),
+            Some(SocketError::AliveCheckFlood)
+        ));
+
+        for _ in 0..2 {
+            assert_eq!(
+                client.read().await.unwrap().unwrap().payload,
+                DoipPayload::AliveCheckResponse(AliveCheckResponse {
+                    source_address: [0x14, 0x11],
+                })
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_with_permit() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        client.send(alive_check.clone()).await.unwrap();
+        client.send(alive_check.clone()).await.unwrap();
+
+        let permits = tokio::sync::Semaphore::new(1);
+        let first = server.read_with_permit(&permits).await.unwrap().unwrap();
+        assert_eq!(first.payload, alive_check);
+
+        let blocked = tokio::time::timeout(
+            std::time::Duration::from_millis(50),
+            server.read_with_permit(&permits),
+        )
+        .await;
+        assert!(blocked.is_err());
+
+        drop(first);
+        let second = server.read_with_permit(&permits).await.unwrap().unwrap();
+        assert_eq!(second.into_inner().payload, alive_check);
+        assert_eq!(permits.available_permits(), 1);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_tcp_info() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        client.send(alive_check.clone()).await.unwrap();
+        server.read().await.unwrap().unwrap();
+        server.send(alive_check).await.unwrap();
+        client.read().await.unwrap().unwrap();
+
+        let info = client.tcp_info().unwrap();
+        assert!(info.rtt > std::time::Duration::ZERO);
+        assert!(info.snd_mss > 0);
+    }
+
+    #[tokio::test]
+    async fn test_recent_frames() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        let mut config = client.config();
+        config.set_frame_history(Some(3));
+        let mut client = client.with_config(config);
+
+        for _ in 0..2 {
+            client.send(alive_check.clone()).await.unwrap();
+            server.read().await.unwrap().unwrap();
+            server.send(alive_check.clone()).await.unwrap();
+            client.read().await.unwrap().unwrap();
+        }
+
+        let frames = client.recent_frames();
+        let directions: Vec<_> = frames.iter().map(|(direction, _, _)| *direction).collect();
+        assert_eq!(
+            directions,
+            vec![Direction::Received, Direction::Sent, Direction::Received]
+        );
+        assert!(frames.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+        assert!(frames.iter().all(|(_, _, msg)| msg.payload == alive_check));
+    }
+
+    #[tokio::test]
+    async fn test_connect_and_read_first() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let banner = alive_check.clone();
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut server = TcpStream::new(socket);
+            server.send(banner).await.unwrap();
+            server
+        });
+
+        let (_client, msg) =
+            TcpStream::connect_and_read_first(addr, std::time::Duration::from_secs(1))
+                .await
+                .unwrap();
+        assert_eq!(msg.payload, alive_check);
+
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_timeout() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+
+        let mut config = SocketConfig::default();
+        config.set_read_timeout(Some(std::time::Duration::from_millis(50)));
+        let mut server = TcpStream::new(socket).with_config(config);
+
+        let CodecError::IoError(err) = server.read().await.unwrap().unwrap_err() else {
+            panic!("expected an io error");
+        };
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+
+        client.send(alive_check.clone()).await.unwrap();
+        assert_eq!(server.read().await.unwrap().unwrap().payload, alive_check);
+    }
+
+    #[tokio::test]
+    async fn test_auto_status_response() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let status = EntityStatusResponse {
+            node_type: NodeType::DoipNode,
+            max_concurrent_sockets: [0x04],
+            currently_open_sockets: [0x01],
+            max_data_size: [0x00, 0x00, 0x0f, 0xff],
+        };
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+
+        // The Entity Status Request is only valid over UDP
+        let mut relay = SocketConfig::default();
+        relay.set_relay_mode(true);
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap()
+            .with_config(relay);
+        let (socket, _) = listener.accept().await.unwrap();
+
+        let mut config = SocketConfig::default();
+        config.set_auto_status_response(Some(status));
+        let mut server = TcpStream::new(socket).with_config(config);
+
+        client
+            .send(DoipPayload::EntityStatusRequest(EntityStatusRequest {}))
+            .await
+            .unwrap();
+        client
+            .send(DoipPayload::AliveCheckRequest(AliveCheckRequest {}))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            server.read().await.unwrap().unwrap().payload,
+            DoipPayload::AliveCheckRequest(AliveCheckRequest {})
+        );
+
+        // doip-definitions misreads the fields of a decoded Entity Status
+        // Response, so only the header is checked
+        let response = client.read().await.unwrap().unwrap();
+        assert_eq!(
+            response.header.payload_type,
+            PayloadType::EntityStatusResponse
+        );
+        assert_eq!(response.header.payload_length, 7);
+    }
+
+    #[tokio::test]
+    async fn test_identify() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let announcement = VehicleAnnouncementMessage {
+            vin: [0x31; 17],
+            logical_address: [0x10, 0x01],
+            eid: [0x00, 0x01, 0x02, 0x03, 0x04, 0x05],
+            gid: [0x00, 0x01, 0x02, 0x03, 0x04, 0x05],
+            further_action: ActionCode::RoutingActivationRequired,
+            vin_gid_sync: Some(SyncStatus::VinGidSynchronized),
+        };
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+
+        let server = tokio::spawn(async move {
+            // The mock gateway answers with a payload only valid over UDP
+            let mut relay = SocketConfig::default();
+            relay.set_relay_mode(true);
+            let mut server = TcpStream::new(socket).with_config(relay);
+            let request = server.read().await.unwrap().unwrap();
+            assert_eq!(
+                request.payload,
+                DoipPayload::VehicleIdentificationRequest(VehicleIdentificationRequest {})
+            );
+            server
+                .send(DoipPayload::VehicleAnnouncementMessage(announcement))
+                .await
+                .unwrap();
+            server
+        });
+
+        let identity = client
+            .identify(std::time::Duration::from_secs(1))
+            .await
+            .unwrap();
+
+        assert_eq!(identity.vin, [0x31; 17]);
+        assert_eq!(identity.logical_address, [0x10, 0x01]);
+        assert_eq!(identity.eid, [0x00, 0x01, 0x02, 0x03, 0x04, 0x05]);
+        assert_eq!(
+            identity.further_action,
+            ActionCode::RoutingActivationRequired
+        );
+
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_identify_version_not_allowed() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+
+        let mut config = SocketConfig::default();
+        config.set_protocol_version(ProtocolVersion::ReservedVer);
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap()
+            .with_config(config);
+
+        let err = client
+            .identify(std::time::Duration::from_secs(1))
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(matches!(
+            err.get_ref().unwrap().downcast_ref::<SocketSendError>(),
+            Some(SocketSendError::PayloadNotAllowedForVersion(
+                ProtocolVersion::ReservedVer,
+                PayloadType::VehicleIdentificationRequest
+            ))
+        ));
+        assert_eq!(client.metrics().frames_sent(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_per_type_metrics() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let diag = DoipPayload::DiagnosticMessage(DiagnosticMessage {
+            source_address: [0x0e, 0x80],
+            target_address: [0x14, 0x11],
+            message: vec![0x3e, 0x00],
+        });
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        client.send(diag.clone()).await.unwrap();
+        client.send(diag).await.unwrap();
+        client
+            .send(DoipPayload::AliveCheckRequest(AliveCheckRequest {}))
+            .await
+            .unwrap();
+
+        for _ in 0..3 {
+            server.read().await.unwrap().unwrap();
+        }
+
+        let sent = client.metrics();
+        assert_eq!(sent.sent_of(PayloadType::DiagnosticMessage), 2);
+        assert_eq!(sent.sent_of(PayloadType::AliveCheckRequest), 1);
+
+        let received = server.metrics();
+        assert_eq!(received.received_of(PayloadType::DiagnosticMessage), 2);
+        assert_eq!(received.received_of(PayloadType::AliveCheckRequest), 1);
+
+        assert_eq!(client.metrics().frames_sent(), 3);
+        assert_eq!(server.metrics().frames_received(), 3);
+        assert!(client.metrics().per_type_received().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        let (io, peer) = tokio::io::duplex(64);
+        drop(peer);
+
+        let mut stream = TcpStream::from_io(io);
+        let mut config = stream.config();
+        config.set_dry_run(true);
+        stream = stream.with_config(config);
+
+        stream
+            .send(DoipPayload::AliveCheckRequest(AliveCheckRequest {}))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            stream.take_dry_run_bytes(),
+            vec![0x02, 0xfd, 0x00, 0x07, 0x00, 0x00, 0x00, 0x00]
+        );
+        assert!(stream.take_dry_run_bytes().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_feed_and_send_all() {
+        const ALIVE_CHECK: [u8; 8] = [0x02, 0xfd, 0x00, 0x07, 0x00, 0x00, 0x00, 0x00];
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let (io, mut peer) = tokio::io::duplex(64);
+        let mut config = SocketConfig::default();
+        config.set_dry_run(true);
+        let mut stream = TcpStream::from_io(io).with_config(config);
+
+        stream.feed(AliveCheckRequest {}).await.unwrap();
+        stream
+            .send_all([alive_check.clone(), alive_check])
+            .await
+            .unwrap();
+        stream.flush().await.unwrap();
+
+        assert_eq!(stream.take_dry_run_bytes(), ALIVE_CHECK.repeat(3));
+        assert_eq!(stream.write_buffer_len(), 0);
+
+        let mut buf = [0u8; 8];
+        let read =
+            tokio::time::timeout(std::time::Duration::from_millis(50), peer.read(&mut buf)).await;
+        assert!(read.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_min_send_gap() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        const GAP: std::time::Duration = std::time::Duration::from_millis(100);
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        let mut config = client.config();
+        config.set_min_send_gap(Some(GAP));
+        let mut client = client.with_config(config);
+
+        client.send(alive_check.clone()).await.unwrap();
+        let start = std::time::Instant::now();
+        client.send(alive_check).await.unwrap();
+        assert!(start.elapsed() >= GAP);
+
+        server.read().await.unwrap().unwrap();
+        server.read().await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replace_io() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let old_listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let new_listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+
+        let mut client = TcpStream::connect(old_listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = old_listener.accept().await.unwrap();
+        let mut old_server = TcpStream::new(socket);
+
+        let new_socket = tokio::net::TcpStream::connect(new_listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = new_listener.accept().await.unwrap();
+        let mut new_server = TcpStream::new(socket);
+
+        let old = client.replace_io(new_socket).await.unwrap();
+        drop(old);
+
+        client.send(alive_check.clone()).await.unwrap();
+
+        assert_eq!(
+            new_server.read().await.unwrap().unwrap().payload,
+            alive_check
+        );
+        assert!(old_server.read().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_replace_io_keeps_codec_state() {
+        const OEM_SPECIFIC: [u8; 4] = [0xde, 0xad, 0xbe, 0xef];
+        const ACTIVATION_RES: [u8; 21] = [
+            0x02, 0xfd, 0x00, 0x06, 0x00, 0x00, 0x00, 0x0d, 0x0e, 0x80, 0x14, 0x11, 0x10, 0x00,
+            0x00, 0x00, 0x00, 0xde, 0xad, 0xbe, 0xef,
+        ];
+
+        let (io, mut peer) = tokio::io::duplex(64);
+        let mut client = TcpStream::from_io(io);
+
+        peer.write_all(&ACTIVATION_RES).await.unwrap();
+        client.read().await.unwrap().unwrap();
+        assert_eq!(client.received_oem_specific(), Some(OEM_SPECIFIC));
+
+        let (new_io, _new_peer) = tokio::io::duplex(64);
+        client.replace_io(new_io).await.unwrap();
+
+        assert_eq!(client.received_oem_specific(), Some(OEM_SPECIFIC));
+    }
+
+    #[tokio::test]
+    async fn test_send_payload_not_allowed_for_version() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+
+        let mut config = SocketConfig::default();
+        config.set_protocol_version(ProtocolVersion::Iso13400_2010);
+        config.set_enforce_payload_version(true);
+
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap()
+            .with_config(config);
+
+        let err = client
+            .send(DoipPayload::EntityStatusRequest(EntityStatusRequest {}))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            SocketSendError::PayloadNotAllowedForVersion(
+                ProtocolVersion::Iso13400_2010,
+                PayloadType::EntityStatusRequest
+            )
+        ));
+        assert!(client
+            .send(DoipPayload::AliveCheckRequest(AliveCheckRequest {}))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_relay_mode() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let entity_status = DoipPayload::EntityStatusRequest(EntityStatusRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+
+        let mut config = SocketConfig::default();
+        config.set_protocol_version(ProtocolVersion::Iso13400_2010);
+        config.set_enforce_payload_version(true);
+        config.set_relay_mode(true);
+
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap()
+            .with_config(config);
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        client.send(entity_status.clone()).await.unwrap();
+
+        let msg = server.read().await.unwrap().unwrap();
+        assert_eq!(msg.header.protocol_version, ProtocolVersion::Iso13400_2010);
+        assert_eq!(msg.payload, entity_status);
+    }
+
+    #[tokio::test]
+    async fn test_read_batch_timed() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        const MAX_WAIT: std::time::Duration = std::time::Duration::from_millis(100);
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        server.send(alive_check.clone()).await.unwrap();
+
+        let start = std::time::Instant::now();
+        let batch = client.read_batch_timed(4, MAX_WAIT).await;
+
+        assert!(start.elapsed() >= MAX_WAIT);
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch[0].as_ref().unwrap().payload, alive_check);
+    }
+
+    #[tokio::test]
+    async fn test_poll_flush_close() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        std::future::poll_fn(|cx| client.poll_send(cx, &alive_check))
+            .await
+            .unwrap();
+        std::future::poll_fn(|cx| client.poll_flush(cx))
+            .await
+            .unwrap();
+        std::future::poll_fn(|cx| client.poll_close(cx))
+            .await
+            .unwrap();
+
+        assert_eq!(server.read().await.unwrap().unwrap().payload, alive_check);
+        assert!(server.read().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_send_message_rejects_udp_payload() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        let vehicle_id = DoipMessageBuilder::new()
+            .payload(DoipPayload::VehicleIdentificationRequest(
+                VehicleIdentificationRequest {},
+            ))
+            .build();
+
+        let res = client.send_message(vehicle_id).await;
+        assert!(matches!(res, Err(SocketSendError::InvalidTcpPayload)));
+        assert_eq!(client.metrics().frames_sent(), 0);
+
+        let msg = DoipMessageBuilder::new()
+            .payload(alive_check.clone())
+            .build();
+        client.send_message(msg).await.unwrap();
+
+        assert_eq!(server.read().await.unwrap().unwrap().payload, alive_check);
+    }
+
+    #[tokio::test]
+    async fn test_send_rejects_udp_payload() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let vehicle_id = DoipPayload::VehicleIdentificationRequest(VehicleIdentificationRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        let res = client.send(vehicle_id.clone()).await;
+        assert!(matches!(res, Err(SocketSendError::InvalidTcpPayload)));
+
+        let res = futures::SinkExt::send(&mut client, vehicle_id.clone()).await;
+        assert!(matches!(res, Err(SocketSendError::InvalidTcpPayload)));
+        assert_eq!(client.metrics().frames_sent(), 0);
+
+        let mut config = SocketConfig::default();
+        config.set_relay_mode(true);
+        let mut client = client.with_config(config);
+
+        client.send(vehicle_id.clone()).await.unwrap();
+
+        assert_eq!(server.read().await.unwrap().unwrap().payload, vehicle_id);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        futures::SinkExt::feed(&mut client, alive_check.clone())
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+
+        assert_eq!(server.read().await.unwrap().unwrap().payload, alive_check);
+        assert!(server.read().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_poll_read() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = tokio::net::TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        let mut bytes = tokio_util::bytes::BytesMut::new();
+        doip_codec::DoipCodec {}
+            .encode(
+                DoipMessageBuilder::new()
+                    .payload(alive_check.clone())
+                    .build(),
+                &mut bytes,
+            )
+            .unwrap();
+
+        let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+        assert!(server.poll_read(&mut cx).is_pending());
+
+        client.write_all(&bytes[..4]).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        assert!(server.poll_read(&mut cx).is_pending());
+
+        client.write_all(&bytes[4..]).await.unwrap();
+        let msg = std::future::poll_fn(|cx| server.poll_read(cx))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg.payload, alive_check);
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_retry() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+
+        let addr = tokio::net::TcpListener::bind(TESTER_ADDR)
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let err = TcpStream::connect_with_retry(addr, 2, std::time::Duration::from_millis(10))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::ConnectionRefused);
+
+        let server = tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+            listener.accept().await.unwrap();
+        });
+
+        let policy = RetryPolicy::exponential(8, std::time::Duration::from_millis(10))
+            .with_max_delay(std::time::Duration::from_millis(50));
+        TcpStream::connect_with_retry_policy(addr, policy)
+            .await
+            .unwrap();
+
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_send_request() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        const TESTER: [u8; 2] = [0x0e, 0x80];
+        const ECU: [u8; 2] = [0x14, 0x11];
+        const OTHER_ECU: [u8; 2] = [0x14, 0x12];
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+        let unrelated = DoipPayload::DiagnosticMessage(DiagnosticMessage {
+            source_address: OTHER_ECU,
+            target_address: TESTER,
+            message: vec![0x7f, 0x22, 0x31],
+        });
+        let response = DiagnosticMessage {
+            source_address: ECU,
+            target_address: TESTER,
+            message: vec![0x62, 0xf1, 0x90],
+        };
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        let frames = [
+            alive_check.clone(),
+            DoipPayload::DiagnosticMessageAck(DiagnosticMessageAck {
+                source_address: ECU,
+                target_address: TESTER,
+                ack_code: DiagnosticAckCode::Acknowledged,
+            }),
+            unrelated.clone(),
+            DoipPayload::DiagnosticMessage(response.clone()),
+        ];
+        let ecu = tokio::spawn(async move {
+            server.read().await.unwrap().unwrap();
+            for frame in frames {
+                server.send(frame).await.unwrap();
+            }
+            server
+        });
+
+        let request = DiagnosticMessage {
+            source_address: TESTER,
+            target_address: ECU,
+            message: vec![0x22, 0xf1, 0x90],
+        };
+        let res = client
+            .send_request(request.clone(), std::time::Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(res, response);
+
+        assert_eq!(client.read().await.unwrap().unwrap().payload, alive_check);
+        assert_eq!(client.read().await.unwrap().unwrap().payload, unrelated);
+
+        let _server = ecu.await.unwrap();
+        let res = client
+            .send_request(request, std::time::Duration::from_millis(50))
+            .await;
+        assert!(matches!(res, Err(crate::error::DoipError::Timeout)));
+    }
+
+    #[tokio::test]
+    async fn test_from_tokio_with_config() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+
+        let config = SocketConfig::builder()
+            .protocol_version(ProtocolVersion::Iso13400_2010)
+            .nodelay(true)
+            .build();
+        let mut server = TcpStream::from_tokio_with_config(socket, config);
+
+        assert!(server.nodelay().unwrap());
+        assert_eq!(server.protocol_version(), ProtocolVersion::Iso13400_2010);
+
+        server.send(alive_check.clone()).await.unwrap();
+
+        let msg = client.read().await.unwrap().unwrap();
+        assert_eq!(msg.header.protocol_version, ProtocolVersion::Iso13400_2010);
+        assert_eq!(msg.payload, alive_check);
+    }
+
+    #[tokio::test]
+    async fn test_read_write_buffer() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+        let bytes = [0x02, 0xfd, 0x00, 0x07, 0x00, 0x00, 0x00, 0x00];
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = tokio::net::TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+
+        let mut config = SocketConfig::default();
+        config.set_read_timeout(Some(std::time::Duration::from_millis(50)));
+        let mut server = TcpStream::new(socket).with_config(config);
+
+        client.write_all(&bytes[..5]).await.unwrap();
+        assert!(server.read().await.unwrap().is_err());
+        assert_eq!(&server.read_buffer()[..], &bytes[..5]);
+
+        futures::SinkExt::feed(&mut server, alive_check)
+            .await
+            .unwrap();
+        assert_eq!(server.write_buffer().len(), server.write_buffer_len());
+        assert_eq!(&server.write_buffer()[..], &bytes[..]);
+    }
+
+    #[tokio::test]
+    async fn test_send_all() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+        let empty = DoipPayload::DiagnosticMessage(DiagnosticMessage {
+            source_address: [0x0e, 0x80],
+            target_address: [0x14, 0x11],
+            message: vec![],
+        });
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        client.send_all(vec![alive_check.clone(); 3]).await.unwrap();
+        assert_eq!(client.write_buffer_len(), 0);
+        for _ in 0..3 {
+            assert_eq!(server.read().await.unwrap().unwrap().payload, alive_check);
+        }
+
+        let err = client
+            .send_all(vec![alive_check.clone(), empty, alive_check.clone()])
+            .await
+            .unwrap_err();
+        assert_eq!(err.index, 1);
+        assert!(err.error.is_invalid_payload());
+    }
+
+    #[tokio::test]
+    async fn test_from_tokio_stream() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let io = tokio::net::TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server: TcpStream = socket.into();
+
+        let mut client = TcpStream::from(io);
+        client.send(alive_check.clone()).await.unwrap();
+        assert_eq!(server.read().await.unwrap().unwrap().payload, alive_check);
+
+        let mut io = tokio::net::TcpStream::from(client);
+        server.send(alive_check).await.unwrap();
+        let mut buf = [0u8; 8];
+        io.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf[2..4], [0x00, 0x07]);
+    }
+
+    #[tokio::test]
+    async fn test_drain_ready() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        assert!(client.drain_ready().is_empty());
+
+        server.send_all(vec![alive_check.clone(); 3]).await.unwrap();
+        assert_eq!(client.read().await.unwrap().unwrap().payload, alive_check);
+
+        let frames = client.drain_ready();
+        assert_eq!(frames.len(), 2);
+        for frame in frames {
+            assert_eq!(frame.unwrap().payload, alive_check);
+        }
+        assert!(client.drain_ready().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_with_raw_into_async_io() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        client.with_raw(|io| io.set_ttl(8)).unwrap();
+        assert_eq!(client.get_stream_ref().ttl().unwrap(), 8);
+
+        client.feed(AliveCheckRequest {}).await.unwrap();
+        client.flush().await.unwrap();
+        assert!(client.read_buffer().is_empty());
+
+        let mut io = client.into_async_io();
+        io.write_all(&[0x02, 0xfd, 0x00, 0x07, 0x00, 0x00, 0x00, 0x00])
+            .await
+            .unwrap();
+
+        for _ in 0..2 {
+            assert_eq!(server.read().await.unwrap().unwrap().payload, alive_check);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_nack_and_close() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        server
+            .nack_and_close(NackCode::IncorrectPatternFormat)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            client.read().await.unwrap().unwrap().payload,
+            DoipPayload::GenericNack(GenericNack {
+                nack_code: NackCode::IncorrectPatternFormat
+            })
+        );
+        assert!(client.read().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_strict_version() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        let mut config = SocketConfig::default();
+        config.set_strict_version(true);
+        let mut client = client.with_config(config);
+
+        server.set_protocol_version(ProtocolVersion::Iso13400_2019);
+        server.send(alive_check.clone()).await.unwrap();
+        server.set_protocol_version(ProtocolVersion::Iso13400_2012);
+        server.send(alive_check.clone()).await.unwrap();
+
+        let Some(Err(CodecError::IoError(err))) = client.read().await else {
+            panic!("expected a version mismatch");
+        };
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(matches!(
+            err.get_ref().unwrap().downcast_ref::<SocketError>(),
+            Some(SocketError::VersionMismatch {
+                expected: ProtocolVersion::Iso13400_2012,
+                observed: ProtocolVersion::Iso13400_2019,
+            })
+        ));
+
+        assert_eq!(client.read().await.unwrap().unwrap().payload, alive_check);
+
+        let is_mismatch = |res: Option<Result<DoipMessage, CodecError>>| {
+            matches!(res, Some(Err(CodecError::IoError(err)))
+                if err.kind() == std::io::ErrorKind::InvalidData)
+        };
+
+        server.set_protocol_version(ProtocolVersion::Iso13400_2019);
+        server.send(alive_check.clone()).await.unwrap();
+        assert!(is_mismatch(client.next().await));
+
+        let (mut read, _write) = client.into_split();
+
+        for _ in 0..2 {
+            server.send(alive_check.clone()).await.unwrap();
+        }
+        assert!(is_mismatch(
+            read.read_of_type(PayloadType::AliveCheckRequest).await
+        ));
+        assert!(is_mismatch(read.next().await));
+    }
+
+    #[tokio::test]
+    async fn test_feed_flush() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        client.feed(AliveCheckRequest {}).await.unwrap();
+        client.feed(AliveCheckRequest {}).await.unwrap();
+        assert_eq!(client.write_buffer_len(), 16);
+
+        client.flush().await.unwrap();
+        assert_eq!(client.write_buffer_len(), 0);
+        for _ in 0..2 {
+            assert_eq!(server.read().await.unwrap().unwrap().payload, alive_check);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connect_from() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let local = std::net::TcpListener::bind(TESTER_ADDR)
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let client = TcpStream::connect_from(local, listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (_socket, peer) = listener.accept().await.unwrap();
+
+        assert_eq!(client.local_addr().unwrap(), local);
+        assert_eq!(peer, local);
+    }
+
+    #[tokio::test]
+    async fn test_keepalive() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let config = SocketConfig::builder()
+            .keepalive(std::time::Duration::from_secs(30))
+            .build();
+        let client = TcpStream::connect_with_config(listener.local_addr().unwrap(), config)
+            .await
+            .unwrap();
+
+        assert!(client.keepalive().unwrap());
+
+        client.set_keepalive(None).unwrap();
+        assert!(!client.keepalive().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_read_message() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+
+        let mut config = SocketConfig::default();
+        config.set_max_message_size(Some(16));
+        let mut server = TcpStream::new(socket).with_config(config);
+
+        client
+            .send(DoipPayload::DiagnosticMessage(DiagnosticMessage {
+                source_address: [0x0e, 0x80],
+                target_address: [0x14, 0x11],
+                message: vec![0x36; 64],
+            }))
+            .await
+            .unwrap();
+        client.send(alive_check.clone()).await.unwrap();
+
+        assert!(matches!(
+            server.read_message().await,
+            Err(SocketReadError::Decode(_))
+        ));
+        assert_eq!(server.read_message().await.unwrap().payload, alive_check);
+
+        drop(client);
+        assert!(matches!(
+            server.read_message().await,
+            Err(SocketReadError::Closed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_read_message_after_malformed_header() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        // Payload type 0x1234 is not defined
+        const MALFORMED_HEADER: [u8; 8] = [0x02, 0xfd, 0x12, 0x34, 0x00, 0x00, 0x00, 0x00];
+        const ALIVE_CHECK: [u8; 8] = [0x02, 0xfd, 0x00, 0x07, 0x00, 0x00, 0x00, 0x00];
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let mut client = tokio::net::TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::new(socket);
+
+        client.write_all(&MALFORMED_HEADER).await.unwrap();
+        client.write_all(&ALIVE_CHECK).await.unwrap();
+        assert!(matches!(
+            server.read_message().await,
+            Err(SocketReadError::Decode(_))
+        ));
+
+        // The codec does not resynchronise, so the frame after it is lost
+        assert!(matches!(
+            server.read_message().await,
+            Err(SocketReadError::Closed)
+        ));
     }
 }
@@ -0,0 +1,123 @@
+use std::{io, path::Path};
+
+use tokio::net::{unix::SocketAddr, UnixListener, UnixStream};
+
+use super::{SocketConfig, TcpStream};
+
+impl TcpStream<UnixStream> {
+    /// Creates a new DoIP Stream over the Unix domain socket at `path`
+    ///
+    /// Useful for tunnelling DoIP between processes on a test rig without
+    /// going through the TCP stack.
+    pub async fn connect_unix<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let stream = UnixStream::connect(path).await?;
+        Ok(Self::from_io(stream))
+    }
+}
+
+/// A Unix domain socket server, listening for DoIP connections
+///
+/// The Unix domain socket equivalent of the `TcpListener`.
+#[derive(Debug)]
+pub struct DoipUnixListener {
+    io: UnixListener,
+    config: SocketConfig,
+}
+
+impl DoipUnixListener {
+    /// Initialises a new listener from a Tokio Unix Listener
+    ///
+    /// Accepted streams use the config set by `set_default_socket_config`, if
+    /// any.
+    pub fn new(io: UnixListener) -> Self {
+        DoipUnixListener {
+            io,
+            config: SocketConfig::process_default().unwrap_or_default(),
+        }
+    }
+
+    /// Binds a new listener to the Unix domain socket at `path`
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(UnixListener::bind(path)?))
+    }
+
+    /// Applies a config to every stream accepted by the listener
+    pub fn with_config(mut self, config: SocketConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Accepts a new incoming connection from the listener
+    pub async fn accept(&self) -> io::Result<(TcpStream<UnixStream>, SocketAddr)> {
+        let (stream, addr) = self.io.accept().await?;
+
+        Ok((
+            TcpStream::from_io(stream).with_config(self.config.clone()),
+            addr,
+        ))
+    }
+
+    /// Returns the reference for the internal listener
+    pub fn get_ref(&self) -> &UnixListener {
+        &self.io
+    }
+}
+
+#[cfg(test)]
+mod test_unix_stream {
+    use doip_definitions::payload::{ActivationType, AliveCheckRequest, DoipPayload};
+    use tokio::net::UnixStream;
+
+    use crate::tcp::{LogicalAddressTable, RoutingActivationOutcome, TcpStream};
+
+    use super::DoipUnixListener;
+
+    #[tokio::test]
+    async fn test_routing_activation_over_pair() {
+        let (tester, gateway) = UnixStream::pair().unwrap();
+        let mut client = TcpStream::from_io(tester);
+        let mut server = TcpStream::from_io(gateway);
+
+        let server = tokio::spawn(async move {
+            let mut testers = LogicalAddressTable::new();
+            testers.register([0x0e, 0x80], ());
+
+            let msg = server.read().await.unwrap().unwrap();
+            let DoipPayload::RoutingActivationRequest(req) = msg.payload else {
+                panic!("Expected Routing Activation Request, got {msg:?}");
+            };
+
+            server
+                .respond_activation(&req, [0x14, 0x11], &testers)
+                .await
+                .unwrap()
+        });
+
+        let outcome = client
+            .activate_routing([0x0e, 0x80], ActivationType::Default)
+            .await
+            .unwrap();
+
+        assert!(server.await.unwrap());
+        assert!(matches!(
+            outcome,
+            RoutingActivationOutcome::Activated(res) if res.source_address == [0x14, 0x11]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_connect_unix() {
+        let path = std::env::temp_dir().join(format!("doip-sockets-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let listener = DoipUnixListener::bind(&path).unwrap();
+        let mut client = TcpStream::connect_unix(&path).await.unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+
+        let alive_check = DoipPayload::AliveCheckRequest(AliveCheckRequest {});
+        client.send(alive_check.clone()).await.unwrap();
+        assert_eq!(server.read().await.unwrap().unwrap().payload, alive_check);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,251 @@
+use std::io;
+
+use doip_codec::{DoipCodec, Error as CodecError};
+use doip_definitions::{header::ProtocolVersion, message::DoipMessage, payload::DoipPayload};
+use futures::{SinkExt, StreamExt};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream as TokioTcpStream,
+};
+use tokio_tungstenite::{
+    tungstenite::{client::IntoClientRequest, Error as WsError, Message},
+    MaybeTlsStream, WebSocketStream,
+};
+use tokio_util::{
+    bytes::BytesMut,
+    codec::{Decoder, Encoder},
+};
+
+use crate::{error::SocketSendError, util::build_message};
+
+use super::SocketConfig;
+
+/// Simple implementation of a DoIP Stream tunnelled over WebSocket
+///
+/// Each DoIP frame is carried in exactly one binary WebSocket message, as the
+/// WebSocket protocol already provides message boundaries. Text, ping and pong
+/// messages are ignored on read.
+#[derive(Debug)]
+pub struct DoIpWsStream<S> {
+    io: WebSocketStream<S>,
+    codec: DoipCodec,
+    config: SocketConfig,
+}
+
+impl DoIpWsStream<MaybeTlsStream<TokioTcpStream>> {
+    /// Creates a new WebSocket Stream given a `ws://` or `wss://` URL
+    pub async fn connect_ws<R>(url: R) -> io::Result<Self>
+    where
+        R: IntoClientRequest + Unpin,
+    {
+        let (io, _) = tokio_tungstenite::connect_async(url)
+            .await
+            .map_err(io::Error::other)?;
+
+        Ok(Self::new(io))
+    }
+}
+
+impl<S> DoIpWsStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Creates a new WebSocket Stream from an established Tungstenite WebSocket Stream
+    pub fn new(io: WebSocketStream<S>) -> Self {
+        DoIpWsStream {
+            io,
+            codec: DoipCodec {},
+            config: SocketConfig::process_default().unwrap_or_default(),
+        }
+    }
+
+    /// Applies a config to the WebSocket Stream, replacing the current config
+    pub fn with_config(mut self, config: SocketConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Returns a clone of the config currently applied to the WebSocket Stream
+    pub fn config(&self) -> SocketConfig {
+        self.config.clone()
+    }
+
+    /// Change the protocol version used by subsequent sends
+    pub fn set_protocol_version(&mut self, version: ProtocolVersion) {
+        self.config.protocol_version = version;
+    }
+
+    /// The protocol version used when sending
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.config.protocol_version
+    }
+
+    /// Performs the server side of the WebSocket handshake on an accepted stream
+    pub async fn accept(stream: S) -> io::Result<Self> {
+        let io = tokio_tungstenite::accept_async(stream)
+            .await
+            .map_err(io::Error::other)?;
+
+        Ok(Self::new(io))
+    }
+
+    /// Send a DoIP frame as a single binary WebSocket message
+    pub async fn send(&mut self, payload: DoipPayload) -> Result<(), SocketSendError> {
+        let msg = build_message(self.config.protocol_version, payload)?;
+
+        let mut bytes = BytesMut::new();
+        self.codec
+            .encode(msg, &mut bytes)
+            .map_err(SocketSendError::EncodeError)?;
+
+        match self.io.send(Message::Binary(bytes.freeze())).await {
+            Ok(_) => Ok(()),
+            Err(WsError::ConnectionClosed | WsError::AlreadyClosed) => {
+                Err(SocketSendError::ConnectionClosed)
+            }
+            Err(WsError::Io(err)) => Err(SocketSendError::from(CodecError::IoError(err))),
+            Err(err) => Err(SocketSendError::EncodeError(CodecError::IoError(
+                io::Error::other(err),
+            ))),
+        }
+    }
+
+    /// Read a DoIP frame off the stream
+    ///
+    /// Returns `None` once the WebSocket is closed. A binary message holding
+    /// anything other than exactly one DoIP frame is returned as an error.
+    pub async fn read(&mut self) -> Option<Result<DoipMessage, CodecError>> {
+        loop {
+            let data = match self.io.next().await? {
+                Ok(Message::Binary(data)) => data,
+                Ok(Message::Close(_)) => return None,
+                Ok(_) => continue,
+                Err(err) => return Some(Err(CodecError::IoError(io::Error::other(err)))),
+            };
+
+            let mut bytes = BytesMut::from(&data[..]);
+
+            return match self.codec.decode(&mut bytes) {
+                Ok(Some(_)) if !bytes.is_empty() => Some(Err(CodecError::IoError(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "WebSocket message contains bytes after the DoIP frame",
+                )))),
+                Ok(Some(msg)) => Some(Ok(msg)),
+                Ok(None) => Some(Err(CodecError::IoError(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "WebSocket message does not contain a complete DoIP frame",
+                )))),
+                Err(err) => Some(Err(err)),
+            };
+        }
+    }
+
+    /// Get a reference to the inner Tungstenite WebSocket Stream
+    pub fn get_stream_ref(&self) -> &WebSocketStream<S> {
+        &self.io
+    }
+
+    /// Access the inner Tungstenite WebSocket Stream, consumes the DoIP WebSocket Stream
+    pub fn into_socket(self) -> WebSocketStream<S> {
+        self.io
+    }
+}
+
+#[cfg(test)]
+mod test_ws_stream {
+    use std::io;
+
+    use doip_codec::Error as CodecError;
+    use doip_definitions::payload::{
+        ActivationCode, ActivationType, AliveCheckRequest, DoipPayload, RoutingActivationRequest,
+        RoutingActivationResponse,
+    };
+    use futures::SinkExt;
+    use tokio_tungstenite::tungstenite::Message;
+
+    use super::DoIpWsStream;
+
+    #[tokio::test]
+    async fn test_routing_activation_round_trip() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let routing_activation = DoipPayload::RoutingActivationRequest(RoutingActivationRequest {
+            source_address: [0x0e, 0x80],
+            activation_type: ActivationType::Default,
+            buffer: [0, 0, 0, 0],
+        });
+        let routing_activation_res =
+            DoipPayload::RoutingActivationResponse(RoutingActivationResponse {
+                logical_address: [0x0e, 0x80],
+                source_address: [0x14, 0x11],
+                activation_code: ActivationCode::SuccessfullyActivated,
+                buffer: [0, 0, 0, 0],
+            });
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let res = routing_activation_res.clone();
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut server = DoIpWsStream::accept(socket).await.unwrap();
+
+            let req = server.read().await.unwrap().unwrap();
+            server.send(res).await.unwrap();
+
+            req.payload
+        });
+
+        let mut client = DoIpWsStream::connect_ws(format!("ws://{addr}"))
+            .await
+            .unwrap();
+
+        client.send(routing_activation.clone()).await.unwrap();
+        let echo = client.read().await.unwrap().unwrap();
+
+        assert_eq!(echo.payload, routing_activation_res);
+        assert_eq!(server.await.unwrap(), routing_activation);
+    }
+
+    #[tokio::test]
+    async fn test_trailing_bytes_rejected() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        const ALIVE_CHECK: [u8; 8] = [0x02, 0xfd, 0x00, 0x07, 0x00, 0x00, 0x00, 0x00];
+
+        let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = tokio::spawn(async move {
+            let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{addr}"))
+                .await
+                .unwrap();
+
+            client
+                .send(Message::Binary(ALIVE_CHECK.repeat(2).into()))
+                .await
+                .unwrap();
+            client
+                .send(Message::Binary(ALIVE_CHECK.to_vec().into()))
+                .await
+                .unwrap();
+
+            client
+        });
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = DoIpWsStream::accept(socket).await.unwrap();
+
+        let err = match server.read().await.unwrap() {
+            Err(CodecError::IoError(err)) => err,
+            res => panic!("expected an IO error, got {res:?}"),
+        };
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let msg = server.read().await.unwrap().unwrap();
+        assert_eq!(
+            msg.payload,
+            DoipPayload::AliveCheckRequest(AliveCheckRequest {})
+        );
+
+        drop(client.await.unwrap());
+    }
+}
@@ -1,23 +1,87 @@
-use doip_definitions::payload::{
-    EntityStatusRequest, EntityStatusResponse, GenericNack, PowerInformationRequest,
-    PowerInformationResponse, VehicleAnnouncementMessage, VehicleIdentificationRequest,
-    VehicleIdentificationRequestEid, VehicleIdentificationRequestVin,
+use doip_definitions::{
+    header::ProtocolVersion,
+    message::DoipMessage,
+    payload::{
+        DoipPayload, EntityStatusRequest, EntityStatusResponse, GenericNack,
+        PowerInformationRequest, PowerInformationResponse, VehicleAnnouncementMessage,
+        VehicleIdentificationRequest, VehicleIdentificationRequestEid,
+        VehicleIdentificationRequestVin,
+    },
 };
 
+use crate::{error::SocketSendError, util::build_message};
+
+mod datagram_codec;
 mod udp_socket;
+mod udp_split;
+mod vehicle_identity;
 
+pub use crate::udp::datagram_codec::UnknownPayloadPolicy;
 pub use crate::udp::udp_socket::*;
+pub use crate::udp::udp_split::*;
+pub use crate::udp::vehicle_identity::*;
 
 /// Helper Trait which assists in applying LSP hints to the send and receive of
 /// sockets.
-pub trait DoipUdpPayload {}
-
-impl DoipUdpPayload for GenericNack {}
-impl DoipUdpPayload for VehicleIdentificationRequest {}
-impl DoipUdpPayload for VehicleIdentificationRequestEid {}
-impl DoipUdpPayload for VehicleIdentificationRequestVin {}
-impl DoipUdpPayload for VehicleAnnouncementMessage {}
-impl DoipUdpPayload for EntityStatusRequest {}
-impl DoipUdpPayload for EntityStatusResponse {}
-impl DoipUdpPayload for PowerInformationRequest {}
-impl DoipUdpPayload for PowerInformationResponse {}
+///
+/// Implemented for the payloads valid over UDP, so `UdpSocket::send_to` cannot
+/// be given a payload such as a Diagnostic Message.
+pub trait DoipUdpPayload {
+    /// Wrap the payload in its `DoipPayload` variant
+    fn into_payload(self) -> DoipPayload;
+}
+
+macro_rules! impl_udp_payload {
+    ($($payload:ident),*) => {
+        $(
+            impl DoipUdpPayload for $payload {
+                fn into_payload(self) -> DoipPayload {
+                    DoipPayload::$payload(self)
+                }
+            }
+        )*
+    };
+}
+
+impl_udp_payload!(
+    GenericNack,
+    VehicleIdentificationRequest,
+    VehicleIdentificationRequestEid,
+    VehicleIdentificationRequestVin,
+    VehicleAnnouncementMessage,
+    EntityStatusRequest,
+    EntityStatusResponse,
+    PowerInformationRequest,
+    PowerInformationResponse
+);
+
+/// Returns whether a payload may be carried in a UDP datagram
+///
+/// The runtime counterpart to `DoipUdpPayload`.
+pub(crate) fn is_udp_payload(payload: &DoipPayload) -> bool {
+    matches!(
+        payload,
+        DoipPayload::GenericNack(_)
+            | DoipPayload::VehicleIdentificationRequest(_)
+            | DoipPayload::VehicleIdentificationRequestEid(_)
+            | DoipPayload::VehicleIdentificationRequestVin(_)
+            | DoipPayload::VehicleAnnouncementMessage(_)
+            | DoipPayload::EntityStatusRequest(_)
+            | DoipPayload::EntityStatusResponse(_)
+            | DoipPayload::PowerInformationRequest(_)
+            | DoipPayload::PowerInformationResponse(_)
+    )
+}
+
+/// Builds a DoIP frame for a UDP send, rejecting payloads only valid over TCP
+/// with `SocketSendError::InvalidUdpPayload`
+pub(crate) fn build_udp_message(
+    protocol_version: ProtocolVersion,
+    payload: DoipPayload,
+) -> Result<DoipMessage, SocketSendError> {
+    if !is_udp_payload(&payload) {
+        return Err(SocketSendError::InvalidUdpPayload);
+    }
+
+    build_message(protocol_version, payload)
+}
@@ -0,0 +1,109 @@
+use doip_codec::{DoipCodec, Error as CodecError};
+use doip_definitions::{
+    definitions::{DOIP_HEADER_LEN, DOIP_LENGTH_OFFSET},
+    error::Error as DefinitionError,
+    message::DoipMessage,
+};
+use tokio_util::{
+    bytes::{Buf, BytesMut},
+    codec::{Decoder, Encoder},
+};
+
+/// Handling of inbound frames with a reserved or unknown payload type
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum UnknownPayloadPolicy {
+    /// Discard the frame and continue with the next frame
+    #[default]
+    Skip,
+
+    /// Discard the frame and return the decode error
+    Error,
+}
+
+/// Codec used by UDP Sockets, wrapping the `DoipCodec`
+///
+/// Frames with an unknown payload type are removed from the datagram so the
+/// frames following them can still be decoded.
+#[derive(Debug)]
+pub(crate) struct DatagramCodec {
+    inner: DoipCodec,
+    on_unknown_payload: UnknownPayloadPolicy,
+}
+
+impl DatagramCodec {
+    pub(crate) fn new(on_unknown_payload: UnknownPayloadPolicy) -> Self {
+        DatagramCodec {
+            inner: DoipCodec {},
+            on_unknown_payload,
+        }
+    }
+
+    pub(crate) fn set_on_unknown_payload(&mut self, on_unknown_payload: UnknownPayloadPolicy) {
+        self.on_unknown_payload = on_unknown_payload;
+    }
+}
+
+impl Decoder for DatagramCodec {
+    type Item = DoipMessage;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        loop {
+            match self.inner.decode(src) {
+                Err(CodecError::DefinitionError(DefinitionError::InvalidPayloadType { value })) => {
+                    let mut length = [0u8; 4];
+                    length.copy_from_slice(&src[DOIP_LENGTH_OFFSET..DOIP_HEADER_LEN]);
+                    let frame_len = DOIP_HEADER_LEN + u32::from_be_bytes(length) as usize;
+                    src.advance(frame_len.min(src.len()));
+
+                    if self.on_unknown_payload == UnknownPayloadPolicy::Error {
+                        return Err(CodecError::DefinitionError(
+                            DefinitionError::InvalidPayloadType { value },
+                        ));
+                    }
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+impl Encoder<DoipMessage> for DatagramCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, item: DoipMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.inner.encode(item, dst)
+    }
+}
+
+#[cfg(test)]
+mod test_datagram_codec {
+    use doip_definitions::payload::{DoipPayload, VehicleIdentificationRequest};
+    use tokio_util::{bytes::BytesMut, codec::Decoder};
+
+    use super::{DatagramCodec, UnknownPayloadPolicy};
+
+    const UNKNOWN_THEN_VIR: [u8; 18] = [
+        0x02, 0xfd, 0x12, 0x34, 0x00, 0x00, 0x00, 0x02, 0xaa, 0xbb, // unknown type
+        0x02, 0xfd, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, // vehicle identification request
+    ];
+
+    #[test]
+    fn test_unknown_payload_policy() {
+        let mut codec = DatagramCodec::new(UnknownPayloadPolicy::Skip);
+        let mut bytes = BytesMut::from(&UNKNOWN_THEN_VIR[..]);
+
+        let msg = codec.decode(&mut bytes).unwrap().unwrap();
+        assert_eq!(
+            msg.payload,
+            DoipPayload::VehicleIdentificationRequest(VehicleIdentificationRequest {})
+        );
+        assert!(bytes.is_empty());
+
+        codec.set_on_unknown_payload(UnknownPayloadPolicy::Error);
+        let mut bytes = BytesMut::from(&UNKNOWN_THEN_VIR[..]);
+
+        assert!(codec.decode(&mut bytes).is_err());
+        assert!(codec.decode(&mut bytes).unwrap().is_some());
+    }
+}
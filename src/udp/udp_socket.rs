@@ -1,22 +1,73 @@
-use crate::SocketConfig;
-use doip_codec::{DoipCodec, Error as CodecError};
+use crate::{error::SocketSendError, udp::build_udp_message, SocketConfig};
+
+use super::{
+    datagram_codec::DatagramCodec, DoipUdpPayload, UdpRecvHalf, UdpSendHalf, UnknownPayloadPolicy,
+    VehicleIdentity,
+};
+use doip_codec::Error as CodecError;
 use doip_definitions::{
-    builder::DoipMessageBuilder, header::ProtocolVersion, message::DoipMessage,
-    payload::DoipPayload,
+    header::{DoipHeader, ProtocolVersion},
+    message::DoipMessage,
+    payload::{
+        DoipPayload, EntityStatusRequest, EntityStatusResponse, PowerInformationRequest,
+        PowerInformationResponse, VehicleAnnouncementMessage, VehicleIdentificationRequest,
+        VehicleIdentificationRequestEid, VehicleIdentificationRequestVin,
+    },
 };
 use futures::{SinkExt, StreamExt};
-use std::{io, net::SocketAddr};
-use tokio::net::{ToSocketAddrs, UdpSocket as TokioUdpSocket};
+use socket2::SockRef;
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    ops::Range,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    net::{ToSocketAddrs, UdpSocket as TokioUdpSocket},
+    time::Instant,
+};
 use tokio_util::udp::UdpFramed;
 
+use tokio_util::{bytes::BytesMut, codec::Decoder};
+
+#[cfg(target_os = "linux")]
+use std::os::fd::AsRawFd;
+#[cfg(target_os = "linux")]
+use tokio::io::Interest;
+
+/// Number of Vehicle Announcement Messages sent by `announce` (A_DoIP_Announce_Num)
+pub const DOIP_ANNOUNCE_NUM: usize = 3;
+
+/// Interval between consecutive Vehicle Announcement Messages (A_DoIP_Announce_Interval)
+pub const DOIP_ANNOUNCE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Upper bound of the initial random announcement delay (A_DoIP_Announce_Wait)
+pub const DOIP_ANNOUNCE_WAIT: Duration = Duration::from_millis(500);
+
+/// Default capacity of the buffer each datagram is received into, large enough
+/// for any UDP datagram
+pub const DOIP_UDP_RECV_CAPACITY: usize = u16::MAX as usize;
+
+/// UDP port DoIP entities listen on for discovery (UDP_DISCOVERY)
+pub const DOIP_UDP_DISCOVERY_PORT: u16 = 13400;
+
 /// Simple implementation of a UDP Socket with DoIP Frames
 ///
 /// Applying only the most simple methods on this struct it is able to act as
 /// a simple UDP socket. If extended functionality is required you can access the
 /// inner Tokio UDP Socket, or raise a Issue on GitHub.
+///
+/// DoIP entities receive discovery requests on `DOIP_UDP_DISCOVERY_PORT`
+/// (13400), so a tester normally binds an ephemeral port and sends to port
+/// 13400, while an entity binds port 13400 itself. Broadcast is disabled until
+/// `set_broadcast` or `discover` enables it.
 pub struct UdpSocket {
-    io: UdpFramed<DoipCodec, TokioUdpSocket>,
+    io: UdpFramed<DatagramCodec, TokioUdpSocket>,
     config: SocketConfig,
+    recv_capacity: usize,
 }
 
 impl UdpSocket {
@@ -25,39 +76,355 @@ impl UdpSocket {
     pub fn from_std(sock: std::net::UdpSocket) -> io::Result<UdpSocket> {
         let sock = TokioUdpSocket::from_std(sock)?;
 
-        Ok(UdpSocket {
-            io: UdpFramed::new(sock, DoipCodec {}),
-            config: SocketConfig::default(),
-        })
+        Ok(UdpSocket::from_tokio(sock))
+    }
+
+    fn from_tokio(sock: TokioUdpSocket) -> UdpSocket {
+        let config = SocketConfig::process_default().unwrap_or_default();
+
+        UdpSocket {
+            io: UdpFramed::new(sock, DatagramCodec::new(config.on_unknown_payload)),
+            config,
+            recv_capacity: DOIP_UDP_RECV_CAPACITY,
+        }
     }
 
     /// Bind the socket to a local address
+    ///
+    /// Uses the config set by `set_default_socket_config`, if any.
     pub async fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<UdpSocket> {
         let sock = TokioUdpSocket::bind(addr).await?;
 
-        Ok(UdpSocket {
-            io: UdpFramed::new(sock, DoipCodec {}),
-            config: SocketConfig::default(),
-        })
+        Ok(UdpSocket::from_tokio(sock))
     }
 
     /// Connect to a remote address
+    ///
+    /// Fixes the peer used by `send_connected` and `recv_connected`, the OS then
+    /// discards datagrams from any other address.
     pub async fn connect<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
         self.io.get_ref().connect(addr).await
     }
 
+    /// The remote address the socket is connected to
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.io.get_ref().peer_addr()
+    }
+
+    /// Send a DoIP Frame to the connected peer
+    ///
+    /// Behaves as `send`, returning `SocketSendError::ConnectionClosed` if the
+    /// socket is not connected.
+    pub async fn send_connected(&mut self, payload: DoipPayload) -> Result<(), SocketSendError> {
+        let addr = self
+            .peer_addr()
+            .map_err(|err| SocketSendError::from(CodecError::IoError(err)))?;
+
+        self.send(payload, addr).await
+    }
+
+    /// Receive a DoIP Frame from the connected peer
+    ///
+    /// Behaves as `recv` without the sender address, which is always the peer
+    /// once the socket is connected.
+    pub async fn recv_connected(&mut self) -> Option<Result<DoipMessage, CodecError>> {
+        Some(self.recv().await?.map(|(msg, _)| msg))
+    }
+
     /// Receive a DoIP Frame from the socket queue
+    ///
+    /// Frames with an unknown payload type are skipped or returned as an error
+    /// according to the `UnknownPayloadPolicy`, the frames after them are still
+    /// returned.
     pub async fn recv(&mut self) -> Option<Result<(DoipMessage, SocketAddr), CodecError>> {
         self.io.next().await
     }
 
+    /// Receive the next datagram without removing it from the socket queue
+    ///
+    /// Delegates to Tokio's `peek_from`. A datagram larger than `buf` is
+    /// truncated to `buf.len()` bytes, the remainder is only discarded once the
+    /// datagram is received. This reads directly from the inner socket, so
+    /// frames `recv` has already buffered from an earlier datagram are not seen.
+    pub async fn peek_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.io.get_ref().peek_from(buf).await
+    }
+
+    /// Decode the header of the next datagram without removing it from the
+    /// socket queue
+    ///
+    /// Only the first 8 bytes are peeked, so the payload is neither copied nor
+    /// validated. A datagram too short to hold a header or with an invalid
+    /// header is returned as `io::ErrorKind::InvalidData` and left queued. As
+    /// `peek_from`, frames `recv` has already buffered are not seen.
+    pub async fn peek_frame(&self) -> io::Result<(DoipHeader, SocketAddr)> {
+        let mut buf = [0u8; 8];
+        let (len, addr) = self.peek_from(&mut buf).await?;
+
+        if len < buf.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Datagram of {len} bytes is too short for a DoIP header"),
+            ));
+        }
+
+        let header = DoipHeader::try_from(buf)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+
+        Ok((header, addr))
+    }
+
+    /// Receive every DoIP Frame contained in the next datagram
+    ///
+    /// Some implementations batch several frames into a single datagram, `recv`
+    /// only yields these one at a time. Trailing bytes which do not form a
+    /// complete frame are discarded, frames with an unknown payload type are
+    /// handled as in `recv`. Decoding stops at the first frame which fails to
+    /// decode, its error is only returned if no frame precedes it in the
+    /// datagram, otherwise the frames decoded so far are returned. A datagram
+    /// larger than `recv_capacity` is rejected with
+    /// `io::ErrorKind::InvalidData`. This reads directly from the inner
+    /// socket, so it should not be interleaved with `recv` while a datagram is
+    /// partially decoded.
+    pub async fn recv_all_from(&mut self) -> io::Result<(Vec<DoipMessage>, SocketAddr)> {
+        let (buf, addr) = self.recv_datagram().await?;
+
+        let mut bytes = BytesMut::from(&buf[..]);
+        let mut codec = DatagramCodec::new(self.config.on_unknown_payload);
+        let mut msgs = Vec::new();
+
+        loop {
+            match codec.decode(&mut bytes) {
+                Ok(Some(msg)) => msgs.push(msg),
+                Ok(None) => break,
+                Err(_) if !msgs.is_empty() => break,
+                Err(CodecError::IoError(err)) => return Err(err),
+                Err(err) => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+                }
+            }
+        }
+
+        Ok((msgs, addr))
+    }
+
+    /// Receive the next datagram whole into a buffer of `recv_capacity`
+    #[cfg(target_os = "linux")]
+    async fn recv_datagram(&self) -> io::Result<(Vec<u8>, SocketAddr)> {
+        let sock = self.io.get_ref();
+        let mut buf = vec![0u8; self.recv_capacity];
+
+        let (len, addr, _) = sock
+            .async_io(Interest::READABLE, || {
+                recvmsg_timestamp(sock.as_raw_fd(), &mut buf)
+            })
+            .await?;
+
+        buf.truncate(len);
+        Ok((buf, addr))
+    }
+
+    /// Receive the next datagram whole into a buffer of `recv_capacity`
+    ///
+    /// Without `MSG_TRUNC` a datagram is received into one spare byte, which
+    /// is only filled when it exceeds the capacity.
+    #[cfg(not(target_os = "linux"))]
+    async fn recv_datagram(&self) -> io::Result<(Vec<u8>, SocketAddr)> {
+        let mut buf = vec![0u8; self.recv_capacity + 1];
+        let (len, addr) = self.io.get_ref().recv_from(&mut buf).await?;
+
+        if len > self.recv_capacity {
+            return Err(truncated_datagram());
+        }
+
+        buf.truncate(len);
+        Ok((buf, addr))
+    }
+
     /// Send a DoIP Frame
-    pub async fn send(&mut self, payload: DoipPayload, addr: SocketAddr) -> Result<(), CodecError> {
-        let msg = DoipMessageBuilder::new()
-            .protocol_version(self.config.protocol_version)
-            .payload(payload)
-            .build();
-        self.io.send((msg, addr)).await
+    ///
+    /// Payloads only valid over TCP, such as a Diagnostic Message, are rejected
+    /// with `SocketSendError::InvalidUdpPayload`.
+    ///
+    /// If the socket is connected and the peer has reported itself unreachable,
+    /// such as with an ICMP port unreachable, this returns
+    /// `SocketSendError::PeerUnreachable`.
+    pub async fn send(
+        &mut self,
+        payload: DoipPayload,
+        addr: SocketAddr,
+    ) -> Result<(), SocketSendError> {
+        let msg = build_udp_message(self.config.protocol_version, payload)?;
+        self.io
+            .send((msg, addr))
+            .await
+            .map_err(SocketSendError::from)
+    }
+
+    /// Send a DoIP Frame carrying a payload valid over UDP
+    ///
+    /// The typed counterpart to `send`, which rejects payloads only valid over
+    /// TCP at runtime with `SocketSendError::InvalidUdpPayload`.
+    pub async fn send_to<A: DoipUdpPayload>(
+        &mut self,
+        payload: A,
+        addr: SocketAddr,
+    ) -> Result<(), SocketSendError> {
+        self.send(payload.into_payload(), addr).await
+    }
+
+    /// Send a Vehicle Identification Request filtered by Entity Identification
+    ///
+    /// Only the DoIP entity with a matching EID is expected to respond.
+    pub async fn send_identification_by_eid(
+        &mut self,
+        target: SocketAddr,
+        eid: [u8; 6],
+    ) -> Result<(), SocketSendError> {
+        let payload =
+            DoipPayload::VehicleIdentificationRequestEid(VehicleIdentificationRequestEid { eid });
+        self.send(payload, target).await
+    }
+
+    /// Send a Vehicle Identification Request filtered by Vehicle Identification Number
+    ///
+    /// Only DoIP entities of the vehicle with a matching VIN are expected to respond.
+    pub async fn send_identification_by_vin(
+        &mut self,
+        target: SocketAddr,
+        vin: [u8; 17],
+    ) -> Result<(), SocketSendError> {
+        let payload =
+            DoipPayload::VehicleIdentificationRequestVin(VehicleIdentificationRequestVin { vin });
+        self.send(payload, target).await
+    }
+
+    /// Query the status of the DoIP entity at `target`
+    ///
+    /// Sends an Entity Status Request and waits for the response. Datagrams
+    /// from any other address or of any other payload type are ignored, so a
+    /// stale or unrelated datagram is not mistaken for the response. Returns
+    /// `io::ErrorKind::TimedOut` if no response arrives within `timeout`.
+    pub async fn query_entity_status(
+        &mut self,
+        target: SocketAddr,
+        timeout: Duration,
+    ) -> io::Result<EntityStatusResponse> {
+        let request = DoipPayload::EntityStatusRequest(EntityStatusRequest {});
+
+        self.query(request, target, timeout, |payload| match payload {
+            DoipPayload::EntityStatusResponse(res) => Some(res),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Query the diagnostic power mode of the DoIP entity at `target`
+    ///
+    /// Sends a Diagnostic Power Mode Information Request and waits for the
+    /// response, ignoring other datagrams as in `query_entity_status`.
+    pub async fn query_power_mode(
+        &mut self,
+        target: SocketAddr,
+        timeout: Duration,
+    ) -> io::Result<PowerInformationResponse> {
+        let request = DoipPayload::PowerInformationRequest(PowerInformationRequest {});
+
+        self.query(request, target, timeout, |payload| match payload {
+            DoipPayload::PowerInformationResponse(res) => Some(res),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Discover the DoIP entities on the network
+    ///
+    /// Broadcasts a Vehicle Identification Request to the limited broadcast
+    /// address on `DOIP_UDP_DISCOVERY_PORT` and collects the announcements
+    /// received until `timeout` elapses, as in `discover_at`.
+    pub async fn discover(
+        &mut self,
+        timeout: Duration,
+    ) -> io::Result<Vec<(VehicleAnnouncementMessage, SocketAddr)>> {
+        let target = SocketAddr::from((Ipv4Addr::BROADCAST, DOIP_UDP_DISCOVERY_PORT));
+        self.discover_at(target, timeout).await
+    }
+
+    /// Discover the DoIP entities reachable at `target`
+    ///
+    /// Sends a Vehicle Identification Request to `target`, such as a subnet
+    /// broadcast address, enabling `SO_BROADCAST` if it is not already set.
+    /// Every Vehicle Announcement Message received until `timeout` elapses is
+    /// returned with the address of its sender, other datagrams and datagrams
+    /// which fail to decode are ignored.
+    pub async fn discover_at(
+        &mut self,
+        target: SocketAddr,
+        timeout: Duration,
+    ) -> io::Result<Vec<(VehicleAnnouncementMessage, SocketAddr)>> {
+        if !self.broadcast()? {
+            self.set_broadcast(true)?;
+        }
+
+        let request = DoipPayload::VehicleIdentificationRequest(VehicleIdentificationRequest {});
+        self.send(request, target).await.map_err(io::Error::other)?;
+
+        let deadline = Instant::now() + timeout;
+        let mut announcements = Vec::new();
+
+        loop {
+            match tokio::time::timeout_at(deadline, self.recv()).await {
+                Err(_) | Ok(None) => return Ok(announcements),
+                Ok(Some(Ok((msg, addr)))) => {
+                    if let DoipPayload::VehicleAnnouncementMessage(announcement) = msg.payload {
+                        announcements.push((announcement, addr));
+                    }
+                }
+                Ok(Some(Err(CodecError::IoError(err)))) => return Err(err),
+                Ok(Some(Err(_))) => continue,
+            }
+        }
+    }
+
+    async fn query<R>(
+        &mut self,
+        request: DoipPayload,
+        target: SocketAddr,
+        timeout: Duration,
+        response: impl Fn(DoipPayload) -> Option<R>,
+    ) -> io::Result<R> {
+        self.send(request, target).await.map_err(io::Error::other)?;
+
+        let recv = async {
+            loop {
+                match self.recv().await {
+                    Some(Ok((msg, addr))) if addr == target => {
+                        if let Some(res) = response(msg.payload) {
+                            return Ok(res);
+                        }
+                    }
+                    Some(Ok(_)) => continue,
+                    Some(Err(CodecError::IoError(err))) => return Err(err),
+                    Some(Err(err)) => {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+                    }
+                    None => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "Socket closed before response",
+                        ))
+                    }
+                }
+            }
+        };
+
+        match tokio::time::timeout(timeout, recv).await {
+            Ok(res) => res,
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Timed out waiting for response",
+            )),
+        }
     }
 
     /// Get a reference to the inner Tokio UDP Socket
@@ -70,22 +437,633 @@ impl UdpSocket {
         self.io.into_inner()
     }
 
+    /// Splits the UDP Socket into a Receive Half and Send Half sharing the
+    /// inner Tokio UDP Socket
+    ///
+    /// Any frames already buffered by the socket from a partially decoded
+    /// datagram are discarded.
+    pub fn into_split(self) -> (UdpRecvHalf, UdpSendHalf) {
+        let sock = Arc::new(self.io.into_inner());
+
+        (
+            UdpRecvHalf::new(sock.clone(), &self.config),
+            UdpSendHalf::new(sock, self.config),
+        )
+    }
+
     /// Change the protocol version on the socket
     pub fn set_protocol_version(&mut self, version: ProtocolVersion) {
         self.config.protocol_version = version
     }
+
+    /// Applies a config to the UDP Socket, replacing the current config
+    pub fn with_config(mut self, config: SocketConfig) -> Self {
+        self.io
+            .codec_mut()
+            .set_on_unknown_payload(config.on_unknown_payload);
+        self.config = config;
+        self
+    }
+
+    /// Change how frames with a reserved or unknown payload type are received
+    pub fn set_on_unknown_payload(&mut self, policy: UnknownPayloadPolicy) {
+        self.config.on_unknown_payload = policy;
+        self.io.codec_mut().set_on_unknown_payload(policy);
+    }
+
+    /// Set the capacity of the buffer `recv_all_from` and `recv_from_ts`
+    /// receive each datagram into
+    ///
+    /// A datagram larger than the capacity is rejected with
+    /// `io::ErrorKind::InvalidData`, so it should be at least the DoIP header
+    /// length plus the config `max_message_size` for the largest accepted
+    /// frame, or batch of frames.
+    /// `recv` always receives into a buffer large enough for any datagram.
+    /// Defaults to `DOIP_UDP_RECV_CAPACITY`.
+    pub fn set_recv_capacity(&mut self, capacity: usize) {
+        self.recv_capacity = capacity;
+    }
+
+    /// The capacity of the buffer each datagram is received into
+    pub fn recv_capacity(&self) -> usize {
+        self.recv_capacity
+    }
+
+    /// Enable or disable sending to broadcast addresses (`SO_BROADCAST`)
+    pub fn set_broadcast(&self, on: bool) -> io::Result<()> {
+        self.io.get_ref().set_broadcast(on)
+    }
+
+    /// Whether sending to broadcast addresses is enabled
+    pub fn broadcast(&self) -> io::Result<bool> {
+        self.io.get_ref().broadcast()
+    }
+
+    /// Set the time to live of outgoing IPv4 datagrams (`IP_TTL`)
+    ///
+    /// Bounds how many routers a discovery request is forwarded across, the OS
+    /// default is kept unless this is called.
+    pub fn set_ttl(&self, ttl: u32) -> io::Result<()> {
+        self.io.get_ref().set_ttl(ttl)
+    }
+
+    /// The time to live of outgoing IPv4 datagrams
+    pub fn ttl(&self) -> io::Result<u32> {
+        self.io.get_ref().ttl()
+    }
+
+    /// Set the hop limit of outgoing IPv6 multicast datagrams
+    /// (`IPV6_MULTICAST_HOPS`)
+    ///
+    /// The OS default of 1 keeps multicast on the local link. Only valid on a
+    /// socket bound to an IPv6 address.
+    pub fn set_multicast_hops(&self, hops: u32) -> io::Result<()> {
+        SockRef::from(self.io.get_ref()).set_multicast_hops_v6(hops)
+    }
+
+    /// The hop limit of outgoing IPv6 multicast datagrams
+    pub fn multicast_hops(&self) -> io::Result<u32> {
+        SockRef::from(self.io.get_ref()).multicast_hops_v6()
+    }
+
+    /// Join the IPv4 multicast group `multiaddr` on the interface with address
+    /// `interface`
+    ///
+    /// `Ipv4Addr::UNSPECIFIED` lets the OS choose the interface.
+    pub fn join_multicast_v4(&self, multiaddr: Ipv4Addr, interface: Ipv4Addr) -> io::Result<()> {
+        self.io.get_ref().join_multicast_v4(multiaddr, interface)
+    }
+
+    /// Leave the IPv4 multicast group joined with `join_multicast_v4`
+    pub fn leave_multicast_v4(&self, multiaddr: Ipv4Addr, interface: Ipv4Addr) -> io::Result<()> {
+        self.io.get_ref().leave_multicast_v4(multiaddr, interface)
+    }
+
+    /// Join the IPv6 multicast group `multiaddr`, such as the all nodes group
+    /// `FF02::1`, on the interface with index `interface`
+    ///
+    /// An interface index of `0` lets the OS choose the interface.
+    pub fn join_multicast_v6(&self, multiaddr: &Ipv6Addr, interface: u32) -> io::Result<()> {
+        self.io.get_ref().join_multicast_v6(multiaddr, interface)
+    }
+
+    /// Leave the IPv6 multicast group joined with `join_multicast_v6`
+    pub fn leave_multicast_v6(&self, multiaddr: &Ipv6Addr, interface: u32) -> io::Result<()> {
+        self.io.get_ref().leave_multicast_v6(multiaddr, interface)
+    }
+
+    /// Set the kernel receive buffer size (`SO_RCVBUF`) of the socket
+    ///
+    /// Enlarging it lets bursts of datagrams, such as announcements during
+    /// discovery, queue without being dropped while the application is busy.
+    /// The kernel may round or cap the size.
+    pub fn set_recv_buffer_size(&self, size: usize) -> io::Result<()> {
+        SockRef::from(self.io.get_ref()).set_recv_buffer_size(size)
+    }
+
+    /// Query the kernel receive buffer size (`SO_RCVBUF`) of the socket
+    pub fn recv_buffer_size(&self) -> io::Result<usize> {
+        SockRef::from(self.io.get_ref()).recv_buffer_size()
+    }
+
+    /// Announce a DoIP entity on the network
+    ///
+    /// Waits a random duration within `jitter` before sending the first
+    /// announcement, then sends `DOIP_ANNOUNCE_NUM` announcements spaced by
+    /// `DOIP_ANNOUNCE_INTERVAL`. ISO 13400 recommends a jitter of
+    /// `Duration::ZERO..DOIP_ANNOUNCE_WAIT` to avoid network storms when many
+    /// ECUs power on at once.
+    ///
+    /// Accepts either a `VehicleAnnouncementMessage` or a `VehicleIdentity`.
+    pub async fn announce(
+        &mut self,
+        announcement: impl Into<VehicleAnnouncementMessage>,
+        addr: SocketAddr,
+        jitter: Range<Duration>,
+    ) -> Result<(), SocketSendError> {
+        let announcement = announcement.into();
+
+        tokio::time::sleep(random_duration(jitter)).await;
+
+        for i in 0..DOIP_ANNOUNCE_NUM {
+            if i > 0 {
+                tokio::time::sleep(DOIP_ANNOUNCE_INTERVAL).await;
+            }
+
+            self.send(DoipPayload::VehicleAnnouncementMessage(announcement), addr)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Respond to a Vehicle Identification Request as a DoIP entity
+    ///
+    /// If `request` is a Vehicle Identification Request matching `identity`,
+    /// the identification response is sent to `addr`. Returns whether a
+    /// response was sent.
+    pub async fn respond_identification(
+        &mut self,
+        request: &DoipPayload,
+        addr: SocketAddr,
+        identity: &VehicleIdentity,
+    ) -> Result<bool, SocketSendError> {
+        if !identity.matches(request) {
+            return Ok(false);
+        }
+
+        self.send(identity.identification_response(), addr).await?;
+
+        Ok(true)
+    }
+
+    /// Enable kernel receive timestamps (`SO_TIMESTAMPNS`) on the socket
+    ///
+    /// Once enabled, `recv_from_ts` returns the time each datagram was received
+    /// by the kernel rather than when it was read by the application.
+    #[cfg(target_os = "linux")]
+    pub fn enable_rx_timestamps(&self) -> io::Result<()> {
+        let enable: libc::c_int = 1;
+
+        // SAFETY: `enable` outlives the call and the length matches its size.
+        let res = unsafe {
+            libc::setsockopt(
+                self.io.get_ref().as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_TIMESTAMPNS,
+                &enable as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+
+        match res {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    /// Query whether kernel receive timestamps are enabled on the socket
+    #[cfg(target_os = "linux")]
+    pub fn rx_timestamps(&self) -> io::Result<bool> {
+        let mut enabled: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+
+        // SAFETY: `enabled` and `len` outlive the call and describe a valid buffer.
+        let res = unsafe {
+            libc::getsockopt(
+                self.io.get_ref().as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_TIMESTAMPNS,
+                &mut enabled as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        };
+
+        match res {
+            0 => Ok(enabled != 0),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    /// Receive a DoIP Frame alongside the kernel receive timestamp
+    ///
+    /// The timestamp is `None` unless `enable_rx_timestamps` has been called.
+    /// Frames with an unknown payload type are handled as in `recv`, a datagram
+    /// holding only skipped frames is dropped and the next one awaited. Only
+    /// the first frame of a datagram is returned. This reads directly from the
+    /// inner socket, so it should not be interleaved with `recv` while a
+    /// datagram is partially decoded. A datagram larger than `recv_capacity`
+    /// is rejected with `io::ErrorKind::InvalidData`.
+    #[cfg(target_os = "linux")]
+    pub async fn recv_from_ts(
+        &mut self,
+    ) -> Result<(DoipMessage, SocketAddr, Option<SystemTime>), CodecError> {
+        let sock = self.io.get_ref();
+        let mut buf = vec![0u8; self.recv_capacity];
+        let mut codec = DatagramCodec::new(self.config.on_unknown_payload);
+
+        loop {
+            let (len, addr, ts) = sock
+                .async_io(Interest::READABLE, || {
+                    recvmsg_timestamp(sock.as_raw_fd(), &mut buf)
+                })
+                .await?;
+
+            let mut bytes = BytesMut::from(&buf[..len]);
+
+            match codec.decode(&mut bytes)? {
+                Some(msg) => return Ok((msg, addr, ts)),
+                None if bytes.is_empty() => continue,
+                None => {
+                    return Err(CodecError::IoError(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "datagram does not contain a complete DoIP frame",
+                    )))
+                }
+            }
+        }
+    }
+}
+
+fn truncated_datagram() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "datagram exceeds the receive capacity",
+    )
+}
+
+#[cfg(target_os = "linux")]
+fn recvmsg_timestamp(
+    fd: std::os::fd::RawFd,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, Option<SystemTime>)> {
+    // SAFETY: all-zero is a valid representation for these C structs.
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    let mut control = [0u64; 16];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+
+    msg.msg_name = &mut storage as *mut libc::sockaddr_storage as *mut libc::c_void;
+    msg.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+    // SAFETY: every pointer in `msg` refers to a live buffer of the stated length.
+    let len = unsafe { libc::recvmsg(fd, &mut msg, 0) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    if msg.msg_flags & libc::MSG_TRUNC != 0 {
+        return Err(truncated_datagram());
+    }
+
+    let mut ts = None;
+
+    // SAFETY: the control buffer was filled by `recvmsg` and is walked with
+    // the kernel provided CMSG helpers.
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_TIMESTAMPNS
+            {
+                let spec = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::timespec);
+                ts = Some(
+                    SystemTime::UNIX_EPOCH + Duration::new(spec.tv_sec as u64, spec.tv_nsec as u32),
+                );
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    let addr = match storage.ss_family as libc::c_int {
+        libc::AF_INET => {
+            // SAFETY: the family tag guarantees the storage holds a `sockaddr_in`.
+            let addr = unsafe { *(&storage as *const _ as *const libc::sockaddr_in) };
+            SocketAddr::from((
+                u32::from_be(addr.sin_addr.s_addr).to_be_bytes(),
+                u16::from_be(addr.sin_port),
+            ))
+        }
+        libc::AF_INET6 => {
+            // SAFETY: the family tag guarantees the storage holds a `sockaddr_in6`.
+            let addr = unsafe { *(&storage as *const _ as *const libc::sockaddr_in6) };
+            SocketAddr::V6(std::net::SocketAddrV6::new(
+                addr.sin6_addr.s6_addr.into(),
+                u16::from_be(addr.sin6_port),
+                addr.sin6_flowinfo,
+                addr.sin6_scope_id,
+            ))
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unsupported address family",
+            ))
+        }
+    };
+
+    Ok((len as usize, addr, ts))
+}
+
+fn random_duration(range: Range<Duration>) -> Duration {
+    if range.is_empty() {
+        return range.start;
+    }
+
+    let mut hasher = RandomState::new().build_hasher();
+    if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        hasher.write_u128(now.as_nanos());
+    }
+
+    let span = (range.end - range.start).as_nanos();
+    let offset = u128::from(hasher.finish()) % span;
+
+    range.start + Duration::from_nanos(offset as u64)
 }
 
 #[cfg(test)]
 mod test_udp_socket {
-    use std::net::ToSocketAddrs;
+    use std::{io, net::ToSocketAddrs, time::Duration};
 
+    use doip_codec::Error as CodecError;
     use doip_definitions::{
-        header::PayloadType,
-        payload::{DoipPayload, VehicleIdentificationRequest},
+        builder::DoipMessageBuilder,
+        header::{PayloadType, ProtocolVersion},
+        payload::{
+            ActionCode, DiagnosticMessage, DoipPayload, EntityStatusRequest,
+            PowerInformationRequest, PowerInformationResponse, PowerMode, SyncStatus,
+            VehicleAnnouncementMessage, VehicleIdentificationRequest,
+            VehicleIdentificationRequestEid, VehicleIdentificationRequestVin,
+        },
     };
+    use tokio_util::codec::Encoder;
 
     use super::UdpSocket;
+    use crate::{
+        error::SocketSendError,
+        udp::{UnknownPayloadPolicy, VehicleIdentityBuilder},
+    };
+
+    #[tokio::test]
+    async fn test_query_power_mode_ignores_unrelated() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let announcement = VehicleAnnouncementMessage {
+            vin: [0x31; 17],
+            logical_address: [0x10, 0x01],
+            eid: [0x00, 0x01, 0x02, 0x03, 0x04, 0x05],
+            gid: [0x00, 0x01, 0x02, 0x03, 0x04, 0x05],
+            further_action: ActionCode::NoFurtherActionRequired,
+            vin_gid_sync: Some(SyncStatus::VinGidSynchronized),
+        };
+
+        let mut tester = UdpSocket::bind(TESTER_ADDR).await.unwrap();
+        let mut entity = UdpSocket::bind(TESTER_ADDR).await.unwrap();
+        let mut decoy = UdpSocket::bind(TESTER_ADDR).await.unwrap();
+        let entity_addr = entity.get_socket_ref().local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (msg, tester_addr) = entity.recv().await.unwrap().unwrap();
+            assert_eq!(
+                msg.payload,
+                DoipPayload::PowerInformationRequest(PowerInformationRequest {})
+            );
+
+            decoy
+                .send(
+                    DoipPayload::PowerInformationResponse(PowerInformationResponse {
+                        power_mode: PowerMode::NotReady,
+                    }),
+                    tester_addr,
+                )
+                .await
+                .unwrap();
+            entity
+                .send(
+                    DoipPayload::VehicleAnnouncementMessage(announcement),
+                    tester_addr,
+                )
+                .await
+                .unwrap();
+            entity
+                .send(
+                    DoipPayload::PowerInformationResponse(PowerInformationResponse {
+                        power_mode: PowerMode::Ready,
+                    }),
+                    tester_addr,
+                )
+                .await
+                .unwrap();
+        });
+
+        let res = tester
+            .query_power_mode(entity_addr, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(res.power_mode, PowerMode::Ready);
+
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_send_to_udp_payload() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let mut sock1 = UdpSocket::bind(TESTER_ADDR).await.unwrap();
+        let mut sock2 = UdpSocket::bind(TESTER_ADDR).await.unwrap();
+        let addr = sock2.get_socket_ref().local_addr().unwrap();
+
+        let diagnostic = DoipPayload::DiagnosticMessage(DiagnosticMessage {
+            source_address: [0x0e, 0x80],
+            target_address: [0x10, 0x01],
+            message: vec![0x10, 0x01],
+        });
+        let res = sock1.send(diagnostic, addr).await;
+        assert!(matches!(res, Err(SocketSendError::InvalidUdpPayload)));
+
+        sock1
+            .send_to(VehicleIdentificationRequest {}, addr)
+            .await
+            .unwrap();
+
+        let (res, _) = sock2.recv().await.unwrap().unwrap();
+        assert_eq!(
+            res.payload,
+            DoipPayload::VehicleIdentificationRequest(VehicleIdentificationRequest {})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_peek_frame() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let mut sock1 = UdpSocket::bind(TESTER_ADDR).await.unwrap();
+        let mut sock2 = UdpSocket::bind(TESTER_ADDR).await.unwrap();
+        let addr = sock2.get_socket_ref().local_addr().unwrap();
+        let from = sock1.get_socket_ref().local_addr().unwrap();
+
+        sock1
+            .send_to(VehicleIdentificationRequest {}, addr)
+            .await
+            .unwrap();
+
+        let mut buf = [0u8; 4];
+        let (len, peer) = sock2.peek_from(&mut buf).await.unwrap();
+        assert_eq!((len, peer), (4, from));
+
+        let (header, peer) = sock2.peek_frame().await.unwrap();
+        assert_eq!(peer, from);
+        assert_eq!(
+            header.payload_type,
+            PayloadType::VehicleIdentificationRequest
+        );
+        assert_eq!(header.payload_length, 0);
+
+        let (res, _) = sock2.recv().await.unwrap().unwrap();
+        assert_eq!(
+            res.payload,
+            DoipPayload::VehicleIdentificationRequest(VehicleIdentificationRequest {})
+        );
+
+        sock1
+            .get_socket_ref()
+            .send_to(&[0x02, 0xfd], addr)
+            .await
+            .unwrap();
+        let err = sock2.peek_frame().await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn test_discover_at() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let announcement = VehicleAnnouncementMessage {
+            vin: [0x31; 17],
+            logical_address: [0x10, 0x01],
+            eid: [0x00, 0x01, 0x02, 0x03, 0x04, 0x05],
+            gid: [0x00, 0x01, 0x02, 0x03, 0x04, 0x05],
+            further_action: ActionCode::NoFurtherActionRequired,
+            vin_gid_sync: Some(SyncStatus::VinGidSynchronized),
+        };
+
+        let mut tester = UdpSocket::bind(TESTER_ADDR).await.unwrap();
+        let mut entity = UdpSocket::bind(TESTER_ADDR).await.unwrap();
+        let entity_addr = entity.get_socket_ref().local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (msg, tester_addr) = entity.recv().await.unwrap().unwrap();
+            assert_eq!(
+                msg.payload,
+                DoipPayload::VehicleIdentificationRequest(VehicleIdentificationRequest {})
+            );
+
+            entity
+                .send(
+                    DoipPayload::EntityStatusRequest(EntityStatusRequest {}),
+                    tester_addr,
+                )
+                .await
+                .unwrap();
+            entity
+                .send(
+                    DoipPayload::VehicleAnnouncementMessage(announcement),
+                    tester_addr,
+                )
+                .await
+                .unwrap();
+        });
+
+        let found = tester
+            .discover_at(entity_addr, Duration::from_millis(200))
+            .await
+            .unwrap();
+
+        assert!(tester.get_socket_ref().broadcast().unwrap());
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0.vin, [0x31; 17]);
+        assert_eq!(found[0].1, entity_addr);
+
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_multicast() {
+        let sock = UdpSocket::bind("0.0.0.0:0").await.unwrap();
+
+        assert!(!sock.broadcast().unwrap());
+        sock.set_broadcast(true).unwrap();
+        assert!(sock.broadcast().unwrap());
+
+        let group = std::net::Ipv4Addr::new(239, 255, 0, 1);
+        let interface = std::net::Ipv4Addr::LOCALHOST;
+        sock.join_multicast_v4(group, interface).unwrap();
+        sock.leave_multicast_v4(group, interface).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ttl_multicast_hops() {
+        let sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sock.set_ttl(4).unwrap();
+        assert_eq!(sock.ttl().unwrap(), 4);
+
+        let sock = UdpSocket::bind("[::1]:0").await.unwrap();
+        assert_eq!(sock.multicast_hops().unwrap(), 1);
+        sock.set_multicast_hops(3).unwrap();
+        assert_eq!(sock.multicast_hops().unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_send_recv_connected() {
+        const TESTER_ADDR: &str = "127.0.0.1:0";
+        let vehicle_id = DoipPayload::VehicleIdentificationRequest(VehicleIdentificationRequest {});
+
+        let mut sock1 = UdpSocket::bind(TESTER_ADDR).await.unwrap();
+        let mut sock2 = UdpSocket::bind(TESTER_ADDR).await.unwrap();
+
+        let res = sock1.send_connected(vehicle_id.clone()).await;
+        assert!(matches!(res, Err(SocketSendError::ConnectionClosed)));
+
+        let addr1 = sock1.get_socket_ref().local_addr().unwrap();
+        let addr2 = sock2.get_socket_ref().local_addr().unwrap();
+        sock1.connect(addr2).await.unwrap();
+        sock2.connect(addr1).await.unwrap();
+        assert_eq!(sock1.peer_addr().unwrap(), addr2);
+
+        sock1.send_connected(vehicle_id.clone()).await.unwrap();
+        assert_eq!(
+            sock2.recv_connected().await.unwrap().unwrap().payload,
+            vehicle_id
+        );
+
+        sock2.send_connected(vehicle_id.clone()).await.unwrap();
+        assert_eq!(
+            sock1.recv_connected().await.unwrap().unwrap().payload,
+            vehicle_id
+        );
+    }
 
     #[tokio::test]
     async fn test_read_write() {
@@ -108,4 +1086,348 @@ mod test_udp_socket {
         assert!(res.header.payload_length == 0);
         assert!(addr == TESTER_ADDR1.to_socket_addrs().unwrap().next().unwrap());
     }
+
+    #[tokio::test]
+    async fn test_announce_jitter() {
+        let announcement = VehicleAnnouncementMessage {
+            vin: [0x31; 17],
+            logical_address: [0x10, 0x01],
+            eid: [0x00, 0x01, 0x02, 0x03, 0x04, 0x05],
+            gid: [0x00, 0x01, 0x02, 0x03, 0x04, 0x05],
+            further_action: ActionCode::NoFurtherActionRequired,
+            vin_gid_sync: Some(SyncStatus::VinGidSynchronized),
+        };
+
+        let mut tester = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let tester_addr = tester.get_socket_ref().local_addr().unwrap();
+        let mut ecu = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        let min_jitter = Duration::from_millis(50);
+        let start = tokio::time::Instant::now();
+
+        tokio::spawn(async move {
+            ecu.announce(
+                announcement,
+                tester_addr,
+                min_jitter..Duration::from_millis(100),
+            )
+            .await
+            .unwrap();
+        });
+
+        let (res, _) = tester.recv().await.unwrap().unwrap();
+
+        assert!(start.elapsed() >= min_jitter);
+        assert!(res.header.payload_type == PayloadType::VehicleAnnouncementMessage);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_recv_from_ts() {
+        let mut sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let receiver_addr = receiver.get_socket_ref().local_addr().unwrap();
+
+        receiver.enable_rx_timestamps().unwrap();
+        assert!(receiver.rx_timestamps().unwrap());
+
+        let before = std::time::SystemTime::now();
+        sender
+            .send(
+                DoipPayload::VehicleIdentificationRequest(VehicleIdentificationRequest {}),
+                receiver_addr,
+            )
+            .await
+            .unwrap();
+
+        let (res, _, ts) = receiver.recv_from_ts().await.unwrap();
+        let ts = ts.unwrap();
+
+        assert!(res.header.payload_type == PayloadType::VehicleIdentificationRequest);
+        assert!(ts >= before - Duration::from_secs(1));
+        assert!(ts <= std::time::SystemTime::now() + Duration::from_secs(1));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_recv_from_ts_unknown_payload() {
+        const UNKNOWN: [u8; 10] = [0x02, 0xfd, 0x12, 0x34, 0x00, 0x00, 0x00, 0x02, 0xaa, 0xbb];
+        const VEHICLE_ID: [u8; 8] = [0x02, 0xfd, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00];
+
+        let sender = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let receiver_addr = receiver.get_socket_ref().local_addr().unwrap();
+
+        sender.send_to(&UNKNOWN, receiver_addr).await.unwrap();
+        sender.send_to(&VEHICLE_ID, receiver_addr).await.unwrap();
+
+        let (res, _, _) = receiver.recv_from_ts().await.unwrap();
+        assert_eq!(
+            res.header.payload_type,
+            PayloadType::VehicleIdentificationRequest
+        );
+
+        receiver.set_on_unknown_payload(UnknownPayloadPolicy::Error);
+        sender.send_to(&UNKNOWN, receiver_addr).await.unwrap();
+
+        assert!(matches!(
+            receiver.recv_from_ts().await,
+            Err(CodecError::DefinitionError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_recv_all_from() {
+        let sender = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let receiver_addr = receiver.get_socket_ref().local_addr().unwrap();
+
+        let mut codec = doip_codec::DoipCodec {};
+        let mut bytes = tokio_util::bytes::BytesMut::new();
+
+        for payload in [
+            DoipPayload::VehicleIdentificationRequest(VehicleIdentificationRequest {}),
+            DoipPayload::EntityStatusRequest(EntityStatusRequest {}),
+        ] {
+            let msg = DoipMessageBuilder::new()
+                .protocol_version(ProtocolVersion::Iso13400_2012)
+                .payload(payload)
+                .build();
+            codec.encode(msg, &mut bytes).unwrap();
+        }
+
+        sender.send_to(&bytes, receiver_addr).await.unwrap();
+
+        let (msgs, addr) = receiver.recv_all_from().await.unwrap();
+
+        assert_eq!(msgs.len(), 2);
+        assert!(msgs[0].header.payload_type == PayloadType::VehicleIdentificationRequest);
+        assert!(msgs[1].header.payload_type == PayloadType::EntityStatusRequest);
+        assert_eq!(addr, sender.local_addr().unwrap());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_recv_large_datagram() {
+        const FRAMES: usize = 1500;
+        let announcement = VehicleAnnouncementMessage {
+            vin: [0x31; 17],
+            logical_address: [0x10, 0x01],
+            eid: [0x00, 0x01, 0x02, 0x03, 0x04, 0x05],
+            gid: [0x00, 0x01, 0x02, 0x03, 0x04, 0x05],
+            further_action: ActionCode::NoFurtherActionRequired,
+            vin_gid_sync: Some(SyncStatus::VinGidSynchronized),
+        };
+
+        let sender = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let receiver_addr = receiver.get_socket_ref().local_addr().unwrap();
+
+        receiver.set_recv_buffer_size(1 << 16).unwrap();
+        assert!(receiver.recv_buffer_size().unwrap() >= 1 << 16);
+        receiver.set_recv_capacity(super::DOIP_UDP_RECV_CAPACITY);
+
+        let mut codec = doip_codec::DoipCodec {};
+        let mut bytes = tokio_util::bytes::BytesMut::new();
+
+        for _ in 0..FRAMES {
+            let msg = DoipMessageBuilder::new()
+                .protocol_version(ProtocolVersion::Iso13400_2012)
+                .payload(DoipPayload::VehicleAnnouncementMessage(announcement))
+                .build();
+            codec.encode(msg, &mut bytes).unwrap();
+        }
+
+        sender.send_to(&bytes, receiver_addr).await.unwrap();
+
+        let (msgs, _) = receiver.recv_all_from().await.unwrap();
+        assert_eq!(msgs.len(), FRAMES);
+        assert!(msgs
+            .iter()
+            .all(|msg| msg.payload == DoipPayload::VehicleAnnouncementMessage(announcement)));
+    }
+
+    #[tokio::test]
+    async fn test_recv_capacity() {
+        const VEHICLE_ID: [u8; 8] = [0x02, 0xfd, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00];
+
+        let sender = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let receiver_addr = receiver.get_socket_ref().local_addr().unwrap();
+
+        receiver.set_recv_capacity(VEHICLE_ID.len());
+        assert_eq!(receiver.recv_capacity(), VEHICLE_ID.len());
+
+        sender
+            .send_to(&[VEHICLE_ID, VEHICLE_ID].concat(), receiver_addr)
+            .await
+            .unwrap();
+
+        // The second frame lies beyond the capacity, so the datagram is rejected
+        let err = receiver.recv_all_from().await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        sender.send_to(&VEHICLE_ID, receiver_addr).await.unwrap();
+
+        let (msgs, _) = receiver.recv_all_from().await.unwrap();
+        assert_eq!(msgs.len(), 1);
+
+        #[cfg(target_os = "linux")]
+        {
+            sender
+                .send_to(&[VEHICLE_ID, VEHICLE_ID].concat(), receiver_addr)
+                .await
+                .unwrap();
+
+            assert!(matches!(
+                receiver.recv_from_ts().await,
+                Err(CodecError::IoError(err)) if err.kind() == io::ErrorKind::InvalidData
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recv_all_from_keeps_decoded_frames() {
+        const VEHICLE_ID: [u8; 8] = [0x02, 0xfd, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00];
+        const UNKNOWN: [u8; 8] = [0x02, 0xfd, 0x12, 0x34, 0x00, 0x00, 0x00, 0x00];
+
+        let sender = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let receiver_addr = receiver.get_socket_ref().local_addr().unwrap();
+
+        receiver.set_on_unknown_payload(UnknownPayloadPolicy::Error);
+
+        sender
+            .send_to(&[VEHICLE_ID, UNKNOWN].concat(), receiver_addr)
+            .await
+            .unwrap();
+        sender.send_to(&UNKNOWN, receiver_addr).await.unwrap();
+
+        let (msgs, _) = receiver.recv_all_from().await.unwrap();
+        assert_eq!(msgs.len(), 1);
+        assert!(msgs[0].header.payload_type == PayloadType::VehicleIdentificationRequest);
+
+        assert!(receiver.recv_all_from().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_send_identification_filtered() {
+        const EID: [u8; 6] = [0x00, 0x1a, 0x2b, 0x3c, 0x4d, 0x5e];
+        const VIN: [u8; 17] = *b"WVWZZZ1JZXW000001";
+
+        let mut tester = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut entity = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let entity_addr = entity.get_socket_ref().local_addr().unwrap();
+
+        tester
+            .send_identification_by_eid(entity_addr, EID)
+            .await
+            .unwrap();
+        tester
+            .send_identification_by_vin(entity_addr, VIN)
+            .await
+            .unwrap();
+
+        let (by_eid, _) = entity.recv().await.unwrap().unwrap();
+        let (by_vin, _) = entity.recv().await.unwrap().unwrap();
+
+        assert_eq!(
+            by_eid.payload,
+            DoipPayload::VehicleIdentificationRequestEid(VehicleIdentificationRequestEid {
+                eid: EID
+            })
+        );
+        assert_eq!(
+            by_vin.payload,
+            DoipPayload::VehicleIdentificationRequestVin(VehicleIdentificationRequestVin {
+                vin: VIN
+            })
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_send_peer_unreachable() {
+        let closed = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let closed_addr = closed.local_addr().unwrap();
+        drop(closed);
+
+        let mut socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.connect(closed_addr).await.unwrap();
+
+        let payload = DoipPayload::VehicleIdentificationRequest(VehicleIdentificationRequest {});
+
+        // The first datagram triggers the ICMP port unreachable, which is then
+        // reported on a following send.
+        let mut res = Ok(());
+        for _ in 0..10 {
+            res = socket.send(payload.clone(), closed_addr).await;
+            if res.is_err() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert!(matches!(res, Err(SocketSendError::PeerUnreachable)));
+    }
+
+    #[tokio::test]
+    async fn test_respond_identification() {
+        let identity = VehicleIdentityBuilder::new()
+            .vin(*b"WVWZZZ1JZXW000001")
+            .logical_address([0x14, 0x11])
+            .build();
+
+        let mut tester = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut entity = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let entity_addr = entity.get_socket_ref().local_addr().unwrap();
+
+        tester
+            .send_identification_by_vin(entity_addr, *b"WVWZZZ1JZXW000002")
+            .await
+            .unwrap();
+        tester
+            .send_identification_by_vin(entity_addr, identity.vin())
+            .await
+            .unwrap();
+
+        let (req, addr) = entity.recv().await.unwrap().unwrap();
+        assert!(!entity
+            .respond_identification(&req.payload, addr, &identity)
+            .await
+            .unwrap());
+
+        let (req, addr) = entity.recv().await.unwrap().unwrap();
+        assert!(entity
+            .respond_identification(&req.payload, addr, &identity)
+            .await
+            .unwrap());
+
+        let (res, _) = tester.recv().await.unwrap().unwrap();
+        assert_eq!(res.payload, identity.identification_response());
+    }
+
+    #[tokio::test]
+    async fn test_recv_skips_unknown_payload() {
+        const UNKNOWN: [u8; 10] = [0x02, 0xfd, 0x12, 0x34, 0x00, 0x00, 0x00, 0x02, 0xaa, 0xbb];
+
+        let identity = VehicleIdentityBuilder::new()
+            .vin(*b"WVWZZZ1JZXW000001")
+            .logical_address([0x14, 0x11])
+            .build();
+
+        let sender = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut entity = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut tester = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let tester_addr = tester.get_socket_ref().local_addr().unwrap();
+
+        sender.send_to(&UNKNOWN, tester_addr).await.unwrap();
+        entity
+            .send(identity.identification_response(), tester_addr)
+            .await
+            .unwrap();
+
+        let (msg, _) = tester.recv().await.unwrap().unwrap();
+        assert_eq!(msg.payload, identity.identification_response());
+    }
 }
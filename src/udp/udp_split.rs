@@ -0,0 +1,75 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use doip_codec::Error as CodecError;
+use doip_definitions::{message::DoipMessage, payload::DoipPayload};
+use futures::{SinkExt, StreamExt};
+use tokio::net::UdpSocket as TokioUdpSocket;
+use tokio_util::udp::UdpFramed;
+
+use crate::{error::SocketSendError, udp::build_udp_message, SocketConfig};
+
+use super::datagram_codec::DatagramCodec;
+
+/// Simple implementation of a UDP Socket Receive Half
+///
+/// Shares the inner Tokio UDP Socket with the `UdpSendHalf`, so either half
+/// can be moved into a separate task.
+pub struct UdpRecvHalf {
+    io: UdpFramed<DatagramCodec, Arc<TokioUdpSocket>>,
+}
+
+impl UdpRecvHalf {
+    pub(crate) fn new(io: Arc<TokioUdpSocket>, config: &SocketConfig) -> Self {
+        UdpRecvHalf {
+            io: UdpFramed::new(io, DatagramCodec::new(config.on_unknown_payload)),
+        }
+    }
+
+    /// Receive a DoIP Frame from the socket queue
+    pub async fn recv(&mut self) -> Option<Result<(DoipMessage, SocketAddr), CodecError>> {
+        self.io.next().await
+    }
+
+    /// Get a reference to the inner Tokio UDP Socket
+    pub fn get_socket_ref(&self) -> &TokioUdpSocket {
+        self.io.get_ref()
+    }
+}
+
+/// Simple implementation of a UDP Socket Send Half
+///
+/// Shares the inner Tokio UDP Socket with the `UdpRecvHalf`.
+pub struct UdpSendHalf {
+    io: UdpFramed<DatagramCodec, Arc<TokioUdpSocket>>,
+    config: SocketConfig,
+}
+
+impl UdpSendHalf {
+    pub(crate) fn new(io: Arc<TokioUdpSocket>, config: SocketConfig) -> Self {
+        UdpSendHalf {
+            io: UdpFramed::new(io, DatagramCodec::new(config.on_unknown_payload)),
+            config,
+        }
+    }
+
+    /// Send a DoIP Frame
+    ///
+    /// Payloads only valid over TCP are rejected with
+    /// `SocketSendError::InvalidUdpPayload`.
+    pub async fn send(
+        &mut self,
+        payload: DoipPayload,
+        addr: SocketAddr,
+    ) -> Result<(), SocketSendError> {
+        let msg = build_udp_message(self.config.protocol_version, payload)?;
+        self.io
+            .send((msg, addr))
+            .await
+            .map_err(SocketSendError::from)
+    }
+
+    /// Get a reference to the inner Tokio UDP Socket
+    pub fn get_socket_ref(&self) -> &TokioUdpSocket {
+        self.io.get_ref()
+    }
+}
@@ -0,0 +1,191 @@
+use doip_definitions::payload::{ActionCode, DoipPayload, SyncStatus, VehicleAnnouncementMessage};
+
+/// Static identity of a DoIP entity
+///
+/// Produces the Vehicle Announcement Message and the Vehicle Identification
+/// Response from the same fields, so both always agree. Created with a
+/// `VehicleIdentityBuilder`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct VehicleIdentity {
+    vin: [u8; 17],
+    logical_address: [u8; 2],
+    eid: [u8; 6],
+    gid: [u8; 6],
+    further_action: ActionCode,
+    vin_gid_sync: Option<SyncStatus>,
+}
+
+impl VehicleIdentity {
+    /// The Vehicle Identification Number
+    pub fn vin(&self) -> [u8; 17] {
+        self.vin
+    }
+
+    /// The logical address of the DoIP entity
+    pub fn logical_address(&self) -> [u8; 2] {
+        self.logical_address
+    }
+
+    /// The Entity Identification
+    pub fn eid(&self) -> [u8; 6] {
+        self.eid
+    }
+
+    /// The Group Identification
+    pub fn gid(&self) -> [u8; 6] {
+        self.gid
+    }
+
+    /// The Vehicle Announcement Message sent on start up by `UdpSocket::announce`
+    pub fn announcement(&self) -> VehicleAnnouncementMessage {
+        VehicleAnnouncementMessage {
+            vin: self.vin,
+            logical_address: self.logical_address,
+            eid: self.eid,
+            gid: self.gid,
+            further_action: self.further_action,
+            vin_gid_sync: self.vin_gid_sync,
+        }
+    }
+
+    /// The Vehicle Identification Response sent in reply to a Vehicle
+    /// Identification Request
+    ///
+    /// ISO-13400 uses the Vehicle Announcement Message payload for both.
+    pub fn identification_response(&self) -> DoipPayload {
+        DoipPayload::VehicleAnnouncementMessage(self.announcement())
+    }
+
+    /// Whether this identity should respond to a Vehicle Identification Request
+    ///
+    /// Generic requests always match, EID and VIN requests match only when the
+    /// requested EID or VIN is equal. Any other payload does not match.
+    pub fn matches(&self, request: &DoipPayload) -> bool {
+        match request {
+            DoipPayload::VehicleIdentificationRequest(_) => true,
+            DoipPayload::VehicleIdentificationRequestEid(req) => req.eid == self.eid,
+            DoipPayload::VehicleIdentificationRequestVin(req) => req.vin == self.vin,
+            _ => false,
+        }
+    }
+}
+
+impl From<&VehicleIdentity> for VehicleAnnouncementMessage {
+    fn from(identity: &VehicleIdentity) -> Self {
+        identity.announcement()
+    }
+}
+
+impl From<VehicleIdentity> for VehicleAnnouncementMessage {
+    fn from(identity: VehicleIdentity) -> Self {
+        identity.announcement()
+    }
+}
+
+/// Builder for a `VehicleIdentity`
+#[derive(Debug, Copy, Clone)]
+pub struct VehicleIdentityBuilder {
+    identity: VehicleIdentity,
+}
+
+impl VehicleIdentityBuilder {
+    /// Creates a new builder with zeroed fields, no further action required
+    /// and the VIN/GID synchronized
+    pub fn new() -> Self {
+        VehicleIdentityBuilder {
+            identity: VehicleIdentity {
+                vin: [0; 17],
+                logical_address: [0; 2],
+                eid: [0; 6],
+                gid: [0; 6],
+                further_action: ActionCode::NoFurtherActionRequired,
+                vin_gid_sync: Some(SyncStatus::VinGidSynchronized),
+            },
+        }
+    }
+
+    /// Set the Vehicle Identification Number
+    pub fn vin(mut self, vin: [u8; 17]) -> Self {
+        self.identity.vin = vin;
+        self
+    }
+
+    /// Set the logical address of the DoIP entity
+    pub fn logical_address(mut self, logical_address: [u8; 2]) -> Self {
+        self.identity.logical_address = logical_address;
+        self
+    }
+
+    /// Set the Entity Identification, typically the MAC address
+    pub fn eid(mut self, eid: [u8; 6]) -> Self {
+        self.identity.eid = eid;
+        self
+    }
+
+    /// Set the Group Identification
+    pub fn gid(mut self, gid: [u8; 6]) -> Self {
+        self.identity.gid = gid;
+        self
+    }
+
+    /// Set the further action required by the tester
+    pub fn further_action(mut self, further_action: ActionCode) -> Self {
+        self.identity.further_action = further_action;
+        self
+    }
+
+    /// Set the VIN/GID synchronisation status
+    pub fn vin_gid_sync(mut self, vin_gid_sync: Option<SyncStatus>) -> Self {
+        self.identity.vin_gid_sync = vin_gid_sync;
+        self
+    }
+
+    /// Finalise the identity
+    pub fn build(self) -> VehicleIdentity {
+        self.identity
+    }
+}
+
+impl Default for VehicleIdentityBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test_vehicle_identity {
+    use doip_definitions::payload::{
+        DoipPayload, VehicleIdentificationRequest, VehicleIdentificationRequestEid,
+    };
+
+    use super::VehicleIdentityBuilder;
+
+    #[test]
+    fn test_identity_payloads_match() {
+        let identity = VehicleIdentityBuilder::new()
+            .vin(*b"WVWZZZ1JZXW000001")
+            .logical_address([0x14, 0x11])
+            .eid([0x00, 0x1a, 0x2b, 0x3c, 0x4d, 0x5e])
+            .gid([0x00, 0x1a, 0x2b, 0x3c, 0x4d, 0x5e])
+            .build();
+
+        let announcement = identity.announcement();
+        let response = match identity.identification_response() {
+            DoipPayload::VehicleAnnouncementMessage(res) => res,
+            other => panic!("Expected Vehicle Announcement Message, got {other:?}"),
+        };
+
+        assert_eq!(announcement, response);
+        assert_eq!(announcement.vin, *b"WVWZZZ1JZXW000001");
+        assert_eq!(response.logical_address, [0x14, 0x11]);
+
+        assert!(identity.matches(&DoipPayload::VehicleIdentificationRequest(
+            VehicleIdentificationRequest {}
+        )));
+        assert!(
+            !identity.matches(&DoipPayload::VehicleIdentificationRequestEid(
+                VehicleIdentificationRequestEid { eid: [0xff; 6] }
+            ))
+        );
+    }
+}
@@ -0,0 +1,319 @@
+use std::fmt::Write;
+
+use doip_codec::DoipCodec;
+use doip_definitions::{
+    builder::DoipMessageBuilder,
+    header::{PayloadType, ProtocolVersion},
+    message::DoipMessage,
+    payload::DoipPayload,
+};
+use tokio_util::{
+    bytes::BytesMut,
+    codec::{Decoder, Encoder},
+};
+
+use crate::error::{RoundtripError, SocketSendError};
+
+/// Length of the source and target addresses leading a Diagnostic Message
+const DIAGNOSTIC_MESSAGE_ADDRESS_LEN: usize = 4;
+
+/// Number of payload bytes printed on each line of a hex dump
+const HEXDUMP_ROW_LEN: usize = 16;
+
+/// Builds a DoIP frame with a payload length matching the encoded payload
+///
+/// `DoipMessageBuilder` sizes fixed length payloads from their in-memory size,
+/// which is wrong for payloads such as the Diagnostic Message Ack, so the
+/// length is taken from the encoded payload instead. Payloads failing
+/// `check_payload_contents` are rejected.
+pub(crate) fn build_message(
+    protocol_version: ProtocolVersion,
+    payload: DoipPayload,
+) -> Result<DoipMessage, SocketSendError> {
+    check_payload_contents(&payload)?;
+
+    Ok(frame_message(protocol_version, payload))
+}
+
+/// Builds a DoIP frame as `build_message` does, without checking the payload
+/// contents
+pub(crate) fn frame_message(
+    protocol_version: ProtocolVersion,
+    payload: DoipPayload,
+) -> DoipMessage {
+    let payload_length = match &payload {
+        DoipPayload::DiagnosticMessage(_) => None,
+        payload => Some(Vec::<u8>::from(payload.clone()).len()),
+    };
+
+    let mut msg = DoipMessageBuilder::new()
+        .protocol_version(protocol_version)
+        .payload(payload)
+        .build();
+
+    if let Some(payload_length) = payload_length {
+        msg.header.payload_length =
+            u32::try_from(payload_length).expect("Fixed length payloads fit in a u32");
+    }
+
+    msg
+}
+
+/// Checks the fields of a payload are consistent before it is encoded
+///
+/// A Diagnostic Message must carry at least one byte of user data, and its
+/// payload length must fit in the header.
+fn check_payload_contents(payload: &DoipPayload) -> Result<(), SocketSendError> {
+    if let DoipPayload::DiagnosticMessage(msg) = payload {
+        let reason = if msg.message.is_empty() {
+            "Diagnostic Message has no user data"
+        } else if u32::try_from(msg.message.len() + DIAGNOSTIC_MESSAGE_ADDRESS_LEN).is_err() {
+            "Diagnostic Message user data exceeds the maximum payload length"
+        } else {
+            return Ok(());
+        };
+
+        return Err(SocketSendError::InvalidPayloadContents(
+            PayloadType::DiagnosticMessage,
+            reason.to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Whether a payload type may be sent under a protocol version
+///
+/// The Entity Status messages were introduced in ISO-13400 2012, and the
+/// default protocol version is only valid for Vehicle Identification Requests.
+/// The reserved version allows no payloads.
+pub fn is_payload_allowed(version: ProtocolVersion, payload_type: PayloadType) -> bool {
+    match version {
+        ProtocolVersion::ReservedVer => false,
+        ProtocolVersion::DefaultValue => matches!(
+            payload_type,
+            PayloadType::VehicleIdentificationRequest
+                | PayloadType::VehicleIdentificationRequestEid
+                | PayloadType::VehicleIdentificationRequestVin
+        ),
+        ProtocolVersion::Iso13400_2010 => !matches!(
+            payload_type,
+            PayloadType::EntityStatusRequest | PayloadType::EntityStatusResponse
+        ),
+        ProtocolVersion::Iso13400_2012
+        | ProtocolVersion::Iso13400_2019
+        | ProtocolVersion::Iso13400_2019Amd1 => true,
+    }
+}
+
+/// Formats a DoIP frame as an annotated hex dump
+///
+/// Each header field is printed on its own labelled line, followed by the
+/// payload bytes in rows of 16 with their offset.
+///
+/// ```text
+/// Protocol Version:         0x02 (Iso13400_2012)
+/// Inverse Protocol Version: 0xfd
+/// Payload Type:             0x0005 (RoutingActivationRequest)
+/// Payload Length:           7 bytes
+/// Payload:
+///   0000  0e 80 00 00 00 00 00
+/// ```
+pub fn hexdump_frame(msg: &DoipMessage) -> String {
+    let header = &msg.header;
+    let payload_type: [u8; 2] = header.payload_type.into();
+    let payload: Vec<u8> = msg.payload.clone().into();
+
+    let mut dump = String::new();
+
+    // Writing to a String cannot fail
+    let _ = writeln!(
+        dump,
+        "Protocol Version:         0x{:02x} ({:?})",
+        u8::from(header.protocol_version),
+        header.protocol_version
+    );
+    let _ = writeln!(
+        dump,
+        "Inverse Protocol Version: 0x{:02x}",
+        header.inverse_protocol_version
+    );
+    let _ = writeln!(
+        dump,
+        "Payload Type:             0x{:04x} ({:?})",
+        u16::from_be_bytes(payload_type),
+        header.payload_type
+    );
+    let _ = writeln!(
+        dump,
+        "Payload Length:           {} bytes",
+        header.payload_length
+    );
+    let _ = write!(dump, "Payload:");
+
+    for (row, bytes) in payload.chunks(HEXDUMP_ROW_LEN).enumerate() {
+        let _ = write!(dump, "\n  {:04x} ", row * HEXDUMP_ROW_LEN);
+
+        for byte in bytes {
+            let _ = write!(dump, " {byte:02x}");
+        }
+    }
+
+    dump
+}
+
+/// The UDS bytes carried by a Diagnostic Message frame
+///
+/// Returns the diagnostic data without the source and target addresses, or
+/// `None` if the frame is not a Diagnostic Message.
+pub fn uds_payload(msg: &DoipMessage) -> Option<&[u8]> {
+    match &msg.payload {
+        DoipPayload::DiagnosticMessage(diag) => Some(&diag.message),
+        _ => None,
+    }
+}
+
+/// Checks a DoIP frame decodes back to itself after being encoded
+///
+/// Useful when building or mutating frames by hand, as the decoded frame must
+/// consume every encoded byte and equal the original.
+pub fn assert_roundtrip(msg: &DoipMessage) -> Result<(), RoundtripError> {
+    let mut codec = DoipCodec {};
+    let mut bytes = BytesMut::new();
+
+    codec
+        .encode(msg.clone(), &mut bytes)
+        .map_err(RoundtripError::EncodeError)?;
+
+    let len = bytes.len();
+
+    let decoded = match codec.decode(&mut bytes) {
+        Ok(Some(decoded)) if bytes.is_empty() => decoded,
+        Ok(_) => return Err(RoundtripError::LengthMismatch(len)),
+        Err(err) => return Err(RoundtripError::DecodeError(err)),
+    };
+
+    if decoded != *msg {
+        return Err(RoundtripError::Mismatch(Box::new(decoded)));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_util {
+    use doip_definitions::{
+        builder::DoipMessageBuilder,
+        header::{PayloadType, ProtocolVersion},
+        payload::{
+            ActivationCode, ActivationType, DiagnosticMessage, DoipPayload,
+            RoutingActivationRequest, RoutingActivationResponse,
+        },
+    };
+
+    use super::{assert_roundtrip, build_message, hexdump_frame, is_payload_allowed, uds_payload};
+    use crate::error::SocketSendError;
+
+    #[test]
+    fn test_build_message_invalid_contents() {
+        let payload = DoipPayload::DiagnosticMessage(DiagnosticMessage {
+            source_address: [0x0e, 0x80],
+            target_address: [0x14, 0x11],
+            message: vec![],
+        });
+
+        let err = build_message(ProtocolVersion::Iso13400_2012, payload).unwrap_err();
+        assert!(matches!(
+            err,
+            SocketSendError::InvalidPayloadContents(PayloadType::DiagnosticMessage, _)
+        ));
+    }
+
+    #[test]
+    fn test_hexdump_frame() {
+        let msg = DoipMessageBuilder::new()
+            .protocol_version(ProtocolVersion::Iso13400_2012)
+            .payload(DoipPayload::RoutingActivationRequest(
+                RoutingActivationRequest {
+                    source_address: [0x0e, 0x80],
+                    activation_type: ActivationType::Default,
+                    buffer: [0, 0, 0, 0],
+                },
+            ))
+            .build();
+
+        let dump = hexdump_frame(&msg);
+
+        assert!(dump.contains("0x0005 (RoutingActivationRequest)"));
+        assert!(dump.contains("Payload Length:           7 bytes"));
+        assert!(dump.contains("  0000  0e 80 00 00 00 00 00"));
+    }
+
+    #[test]
+    fn test_uds_payload() {
+        let msg = DoipMessageBuilder::new()
+            .protocol_version(ProtocolVersion::Iso13400_2012)
+            .payload(DoipPayload::DiagnosticMessage(DiagnosticMessage {
+                source_address: [0x0e, 0x80],
+                target_address: [0x14, 0x11],
+                message: vec![0x22, 0xf1, 0x90],
+            }))
+            .build();
+
+        assert_eq!(uds_payload(&msg), Some(&[0x22, 0xf1, 0x90][..]));
+
+        let msg = DoipMessageBuilder::new()
+            .protocol_version(ProtocolVersion::Iso13400_2012)
+            .payload(DoipPayload::RoutingActivationResponse(
+                RoutingActivationResponse {
+                    logical_address: [0x0e, 0x80],
+                    source_address: [0x14, 0x11],
+                    activation_code: ActivationCode::SuccessfullyActivated,
+                    buffer: [0, 0, 0, 0],
+                },
+            ))
+            .build();
+
+        assert_eq!(uds_payload(&msg), None);
+    }
+
+    #[test]
+    fn test_assert_roundtrip() {
+        let mut msg = DoipMessageBuilder::new()
+            .protocol_version(ProtocolVersion::Iso13400_2012)
+            .payload(DoipPayload::RoutingActivationRequest(
+                RoutingActivationRequest {
+                    source_address: [0x0e, 0x80],
+                    activation_type: ActivationType::Default,
+                    buffer: [0, 0, 0, 0],
+                },
+            ))
+            .build();
+
+        assert!(assert_roundtrip(&msg).is_ok());
+
+        msg.header.payload_length = 3;
+
+        assert!(assert_roundtrip(&msg).is_err());
+    }
+
+    #[test]
+    fn test_is_payload_allowed() {
+        assert!(is_payload_allowed(
+            ProtocolVersion::Iso13400_2012,
+            PayloadType::EntityStatusRequest
+        ));
+        assert!(!is_payload_allowed(
+            ProtocolVersion::Iso13400_2010,
+            PayloadType::EntityStatusRequest
+        ));
+        assert!(is_payload_allowed(
+            ProtocolVersion::Iso13400_2010,
+            PayloadType::DiagnosticMessage
+        ));
+        assert!(!is_payload_allowed(
+            ProtocolVersion::DefaultValue,
+            PayloadType::RoutingActivationRequest
+        ));
+    }
+}
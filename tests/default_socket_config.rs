@@ -0,0 +1,118 @@
+//! The process-wide default config is shared by every test in a binary, so the
+//! tests touching it live in their own binary and hold `DEFAULT_CONFIG_LOCK`.
+
+use std::{
+    net::SocketAddr,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use doip_definitions::header::ProtocolVersion;
+use doip_sockets::{
+    set_default_socket_config,
+    tcp::{DoipServerBuilder, TcpListener, TcpStream},
+    SocketConfig,
+};
+
+static DEFAULT_CONFIG_LOCK: Mutex<()> = Mutex::new(());
+
+/// Sets the process default for the lifetime of the guard, restoring the
+/// built-in defaults on drop even if the test panics
+struct DefaultConfigGuard {
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl DefaultConfigGuard {
+    fn set(version: ProtocolVersion) -> Self {
+        let lock = DEFAULT_CONFIG_LOCK
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let mut config = SocketConfig::default();
+        config.set_protocol_version(version);
+        set_default_socket_config(Some(config));
+
+        DefaultConfigGuard { _lock: lock }
+    }
+}
+
+impl Drop for DefaultConfigGuard {
+    fn drop(&mut self) {
+        set_default_socket_config(None);
+    }
+}
+
+#[tokio::test]
+async fn test_default_socket_config() {
+    const TESTER_ADDR: &str = "127.0.0.1:0";
+
+    let listener = tokio::net::TcpListener::bind(TESTER_ADDR).await.unwrap();
+
+    let guard = DefaultConfigGuard::set(ProtocolVersion::Iso13400_2019);
+    let stream = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    drop(guard);
+
+    assert_eq!(
+        stream.config().protocol_version(),
+        ProtocolVersion::Iso13400_2019
+    );
+
+    let stream = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+
+    assert_eq!(
+        stream.config().protocol_version(),
+        ProtocolVersion::Iso13400_2012
+    );
+}
+
+#[tokio::test]
+async fn test_default_socket_config_listener() {
+    const TESTER_ADDR: &str = "127.0.0.1:0";
+
+    let guard = DefaultConfigGuard::set(ProtocolVersion::Iso13400_2019);
+    let listener = TcpListener::bind(TESTER_ADDR).await.unwrap();
+    let server = DoipServerBuilder::new()
+        .bind_addr(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .build()
+        .await
+        .unwrap();
+    drop(guard);
+
+    for listener in [listener, server] {
+        let addr = listener.local_addr().unwrap();
+        let _client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+
+        assert_eq!(
+            stream.config().protocol_version(),
+            ProtocolVersion::Iso13400_2019
+        );
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_default_socket_config_unix_listener() {
+    use doip_sockets::tcp::DoipUnixListener;
+
+    let path =
+        std::env::temp_dir().join(format!("doip-sockets-default-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let guard = DefaultConfigGuard::set(ProtocolVersion::Iso13400_2019);
+    let listener = DoipUnixListener::bind(&path).unwrap();
+    drop(guard);
+
+    let _client = tokio::net::UnixStream::connect(&path).await.unwrap();
+    let (stream, _) = listener.accept().await.unwrap();
+
+    assert_eq!(
+        stream.config().protocol_version(),
+        ProtocolVersion::Iso13400_2019
+    );
+
+    std::fs::remove_file(&path).unwrap();
+}